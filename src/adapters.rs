@@ -0,0 +1,236 @@
+//! Adapters for the streams returned by [`DeepSeekAPI`](crate::DeepSeekAPI).
+
+use anyhow::Result;
+use futures_util::{Stream, StreamExt};
+use serde::Serialize;
+use std::time::Duration;
+use tokio::time::Instant;
+
+use crate::models::{InlineImage, Message, SearchCitation};
+use crate::{CompletionWarning, DeepSeekError, StreamChunk};
+
+/// Merges consecutive [`StreamChunk::Content`] chunks of `stream`, reducing the
+/// number of writes for consumers that forward content to a terminal or socket.
+///
+/// Buffered content is flushed as a single chunk once it reaches `max_bytes`,
+/// `max_delay` after its first fragment arrived, before any other chunk, and at
+/// the end of the stream. Other chunks and errors pass through unchanged and in
+/// order. This adds up to `max_delay` of latency to content.
+pub fn coalesce(
+    stream: impl Stream<Item = Result<StreamChunk, DeepSeekError>>,
+    max_delay: Duration,
+    max_bytes: usize,
+) -> impl Stream<Item = Result<StreamChunk, DeepSeekError>> {
+    use async_stream::stream;
+
+    stream! {
+        let mut stream = std::pin::pin!(stream);
+        let mut pending = String::new();
+        let mut deadline: Option<Instant> = None;
+        loop {
+            let next = match deadline {
+                Some(deadline) => tokio::select! {
+                    item = stream.next() => Some(item),
+                    () = tokio::time::sleep_until(deadline) => None,
+                },
+                None => Some(stream.next().await),
+            };
+            match next {
+                // The oldest buffered fragment is `max_delay` old
+                None => {
+                    deadline = None;
+                    yield Ok(StreamChunk::Content(std::mem::take(&mut pending)));
+                }
+                Some(Some(Ok(StreamChunk::Content(text)))) => {
+                    if pending.is_empty() {
+                        deadline = Some(Instant::now() + max_delay);
+                    }
+                    pending.push_str(&text);
+                    if pending.len() >= max_bytes {
+                        deadline = None;
+                        yield Ok(StreamChunk::Content(std::mem::take(&mut pending)));
+                    }
+                }
+                Some(item) => {
+                    if !pending.is_empty() {
+                        deadline = None;
+                        yield Ok(StreamChunk::Content(std::mem::take(&mut pending)));
+                    }
+                    match item {
+                        Some(item) => yield item,
+                        None => return,
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// A chunk of a stream passed through [`with_offsets`].
+#[derive(Debug)]
+pub struct PositionedChunk {
+    pub chunk: StreamChunk,
+    /// For [`StreamChunk::Content`] and [`StreamChunk::Thinking`], the byte offset
+    /// of the fragment in the message's `content` or `thinking_content`; `None`
+    /// for other chunks.
+    pub offset: Option<usize>,
+}
+
+/// Annotates the content and thinking fragments of `stream` with their byte
+/// offsets in the assembled fields of the final message, e.g. to map rendered
+/// text back to stream positions.
+///
+/// The offsets of a field count the bytes of all earlier fragments of it, so
+/// `&message.content[offset..offset + text.len()]` is the fragment `text` as long
+/// as every fragment reaches this adapter; apply it before adapters that drop
+/// chunks. Merging adjacent fragments, as [`coalesce`] does, keeps offsets valid.
+pub fn with_offsets(
+    stream: impl Stream<Item = Result<StreamChunk, DeepSeekError>>,
+) -> impl Stream<Item = Result<PositionedChunk, DeepSeekError>> {
+    let mut content_len = 0;
+    let mut thinking_len = 0;
+    stream.map(move |chunk| {
+        let chunk = chunk?;
+        let offset = match &chunk {
+            StreamChunk::Content(text) => Some(advance(&mut content_len, text)),
+            StreamChunk::Thinking(text) => Some(advance(&mut thinking_len, text)),
+            _ => None,
+        };
+        Ok(PositionedChunk { chunk, offset })
+    })
+}
+
+/// Returns `len` and then advances it past `text`.
+fn advance(len: &mut usize, text: &str) -> usize {
+    let offset = *len;
+    *len += text.len();
+    offset
+}
+
+/// A frame of [`json_frames`].
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Frame<'a> {
+    Content { text: &'a str },
+    Thinking { text: &'a str },
+    Warning { warning: &'a CompletionWarning },
+    Image { image: &'a InlineImage },
+    SearchResults { results: &'a [SearchCitation] },
+    Usage { tokens: i64 },
+    Done { message: &'a Message },
+    Error { message: String },
+}
+
+/// Serializes each item of `stream` as a JSON object, e.g. to forward a
+/// completion to a browser over a WebSocket.
+///
+/// The frames are:
+/// - `{"type":"content","text":"..."}` and `{"type":"thinking","text":"..."}` for
+///   fragments of the answer and the reasoning,
+/// - `{"type":"warning","warning":{"kind":"...",...}}` for a [`CompletionWarning`],
+/// - `{"type":"image","image":{"url_or_data":"...","alt":"..."}}` for an
+///   [`InlineImage`],
+/// - `{"type":"search_results","results":[{"url":"...","title":"...",...}]}` for
+///   the [`SearchCitation`]s the answer is based on,
+/// - `{"type":"usage","tokens":123}` for the accumulated token usage,
+/// - `{"type":"done","message":{...}}` with the final [`Message`],
+/// - `{"type":"error","message":"..."}` for an error, with its full chain of causes.
+///
+/// Errors are turned into frames rather than passed through; the returned stream
+/// only fails if a frame cannot be serialized.
+pub fn json_frames(
+    stream: impl Stream<Item = Result<StreamChunk, DeepSeekError>>,
+) -> impl Stream<Item = Result<String, DeepSeekError>> {
+    stream.map(|chunk| {
+        let frame = match &chunk {
+            Ok(StreamChunk::Content(text)) => Frame::Content { text },
+            Ok(StreamChunk::Thinking(text)) => Frame::Thinking { text },
+            Ok(StreamChunk::Warning(warning)) => Frame::Warning { warning },
+            Ok(StreamChunk::Image(image)) => Frame::Image { image },
+            Ok(StreamChunk::SearchResults(results)) => Frame::SearchResults { results },
+            Ok(StreamChunk::Usage(tokens)) => Frame::Usage { tokens: *tokens },
+            Ok(StreamChunk::Message(message)) => Frame::Done { message },
+            Err(e) => Frame::Error {
+                message: format!("{e:#}"),
+            },
+        };
+        Ok(serde_json::to_string(&frame)?)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(chunks: Vec<Result<StreamChunk, DeepSeekError>>) -> Vec<String> {
+        chunks
+            .into_iter()
+            .map(|chunk| match chunk.unwrap() {
+                StreamChunk::Content(text) => format!("content:{text}"),
+                StreamChunk::Thinking(text) => format!("thinking:{text}"),
+                other => format!("{other:?}"),
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn adjacent_content_is_merged() {
+        let chunks = futures_util::stream::iter([
+            Ok(StreamChunk::Content("a".to_string())),
+            Ok(StreamChunk::Content("b".to_string())),
+            Ok(StreamChunk::Thinking("x".to_string())),
+            Ok(StreamChunk::Content("c".to_string())),
+        ]);
+        let merged = coalesce(chunks, Duration::from_mins(1), 1024)
+            .collect()
+            .await;
+        assert_eq!(render(merged), ["content:ab", "thinking:x", "content:c"]);
+    }
+
+    #[tokio::test]
+    async fn chunks_become_json_frames() {
+        let message: Message = serde_json::from_str(r#"{"message_id":2,"content":"Hi"}"#).unwrap();
+        let chunks = futures_util::stream::iter([
+            Ok(StreamChunk::Content("Hi".to_string())),
+            Ok(StreamChunk::Warning(CompletionWarning::ResponseTruncated {
+                limit: 2,
+            })),
+            Ok(StreamChunk::Message(message)),
+            Err(anyhow::anyhow!("boom").into()),
+        ]);
+        let frames: Vec<String> = json_frames(chunks).map(Result::unwrap).collect().await;
+        assert_eq!(
+            frames,
+            [
+                r#"{"type":"content","text":"Hi"}"#,
+                r#"{"type":"warning","warning":{"kind":"response_truncated","limit":2}}"#,
+                r#"{"type":"done","message":{"message_id":2,"content":"Hi"}}"#,
+                r#"{"type":"error","message":"boom"}"#,
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn fragments_carry_field_offsets() {
+        let chunks = futures_util::stream::iter([
+            Ok(StreamChunk::Thinking("hmm".to_string())),
+            Ok(StreamChunk::Content("é".to_string())),
+            Ok(StreamChunk::Thinking("!".to_string())),
+            Ok(StreamChunk::Content("ab".to_string())),
+        ]);
+        let offsets: Vec<_> = with_offsets(chunks)
+            .map(|chunk| chunk.unwrap().offset)
+            .collect()
+            .await;
+        assert_eq!(offsets, [Some(0), Some(0), Some(3), Some(2)]);
+    }
+
+    #[tokio::test]
+    async fn content_is_flushed_at_max_bytes() {
+        let chunks = futures_util::stream::iter(
+            ["ab", "cd", "e"].map(|text| Ok(StreamChunk::Content(text.to_string()))),
+        );
+        let merged = coalesce(chunks, Duration::from_mins(1), 3).collect().await;
+        assert_eq!(render(merged), ["content:abcd", "content:e"]);
+    }
+}
@@ -0,0 +1,323 @@
+//! Compatibility with the shape of Anthropic's Messages API.
+//!
+//! [`MessagesRequest`] and [`MessagesResponse`] (de)serialize like the bodies of
+//! `POST /v1/messages`, so code built around that format can be pointed at
+//! `DeepSeek` with [`create_message`]. Only text is covered:
+//!
+//! - Image, document, tool use and tool result blocks in requests are rejected.
+//! - Tools, `tool_choice`, `temperature`, `top_p`, `top_k`, `metadata` and
+//!   streaming are not supported; unknown request fields are ignored.
+//! - `stop_sequences` are applied by the client, see
+//!   [`CompletionOptions::stop_sequences`].
+//! - `model` is echoed back but does not select a model, and `max_tokens` does not
+//!   limit the response (see
+//!   [`DeepSeekAPIBuilder::max_response_size`](crate::DeepSeekAPIBuilder::max_response_size)).
+//! - `DeepSeek` chats are stateful while Messages requests carry the whole
+//!   conversation, so every request starts a new chat and earlier turns are sent
+//!   as a transcript in the prompt.
+//! - Thinking content is not returned, and `usage` only reports the tokens the
+//!   server accounted for the response, as `output_tokens`.
+
+use anyhow::{Result, anyhow, bail};
+use serde::{Deserialize, Serialize};
+
+use crate::{CompletionOptions, CompletionResult, DeepSeekAPI, DeepSeekError};
+
+/// The body of a Messages API request.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct MessagesRequest {
+    /// Echoed back in the response; `DeepSeek` picks the model itself.
+    #[serde(default)]
+    pub model: String,
+    /// Accepted for compatibility but not enforced.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u32>,
+    /// The system prompt, prepended to the conversation.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system: Option<Content>,
+    /// The conversation, ending with the user turn to answer.
+    pub messages: Vec<InputMessage>,
+    /// Sequences that end the response, not included in it.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub stop_sequences: Vec<String>,
+    /// Enables `DeepSeek`'s thinking mode; the budget is ignored.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thinking: Option<ThinkingConfig>,
+}
+
+/// A turn of the conversation in a [`MessagesRequest`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InputMessage {
+    /// `"user"` or `"assistant"`.
+    pub role: String,
+    pub content: Content,
+}
+
+/// Message content, either a plain string or a list of blocks.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Content {
+    Text(String),
+    Blocks(Vec<ContentBlock>),
+}
+
+/// A block of message content. Only text blocks are supported.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ContentBlock {
+    Text {
+        text: String,
+    },
+    /// Any other block type, which [`create_message`] rejects.
+    #[serde(other)]
+    Unsupported,
+}
+
+/// The `thinking` setting of a [`MessagesRequest`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ThinkingConfig {
+    Enabled {
+        #[serde(default)]
+        budget_tokens: u32,
+    },
+    Disabled,
+}
+
+/// The body of a Messages API response.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MessagesResponse {
+    /// `msg_` followed by the `DeepSeek` message id.
+    pub id: String,
+    /// Always `"message"`.
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// Always `"assistant"`.
+    pub role: String,
+    pub model: String,
+    pub content: Vec<ContentBlock>,
+    pub stop_reason: Option<StopReason>,
+    /// The stop sequence that ended the response, if any.
+    pub stop_sequence: Option<String>,
+    pub usage: Usage,
+}
+
+/// Why the response ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StopReason {
+    /// The model finished its answer.
+    EndTurn,
+    /// The answer was cut off by the client's maximum response size.
+    MaxTokens,
+    /// The answer reached one of the request's stop sequences.
+    StopSequence,
+}
+
+/// Token usage of a [`MessagesResponse`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Usage {
+    /// Always 0, as `DeepSeek` does not report prompt tokens separately.
+    pub input_tokens: u64,
+    /// The server's `accumulated_token_usage` for the response, or 0.
+    pub output_tokens: u64,
+}
+
+impl Content {
+    /// Joins the text of the content, failing on unsupported blocks.
+    fn text(&self) -> Result<String> {
+        match self {
+            Self::Text(text) => Ok(text.clone()),
+            Self::Blocks(blocks) => blocks
+                .iter()
+                .map(|block| match block {
+                    ContentBlock::Text { text } => Ok(text.as_str()),
+                    ContentBlock::Unsupported => bail!("Only text content blocks are supported"),
+                })
+                .collect::<Result<Vec<_>>>()
+                .map(|texts| texts.join("\n")),
+        }
+    }
+}
+
+impl MessagesRequest {
+    /// Renders the request as a single `DeepSeek` prompt.
+    ///
+    /// The system prompt and earlier turns precede the final user message as a
+    /// transcript; a request with a single user message is sent as is.
+    ///
+    /// # Errors
+    /// Returns an error if there are no messages, the last one is not from the
+    /// user, or any content is not text.
+    pub fn to_prompt(&self) -> Result<String, DeepSeekError> {
+        let Some((last, history)) = self.messages.split_last() else {
+            return Err(anyhow!("A Messages request needs at least one message").into());
+        };
+        if last.role != "user" {
+            return Err(anyhow!(
+                "The last message must be from the user, not `{}`",
+                last.role
+            )
+            .into());
+        }
+
+        let mut sections = Vec::new();
+        if let Some(system) = &self.system {
+            sections.push(system.text()?);
+        }
+        for message in history {
+            let speaker = match message.role.as_str() {
+                "user" => "User",
+                "assistant" => "Assistant",
+                role => return Err(anyhow!("Unsupported message role `{role}`").into()),
+            };
+            sections.push(format!("{speaker}: {}", message.content.text()?));
+        }
+        let prompt = last.content.text()?;
+        if sections.is_empty() {
+            return Ok(prompt);
+        }
+        sections.push(if history.is_empty() {
+            prompt
+        } else {
+            format!("User: {prompt}")
+        });
+        Ok(sections.join("\n\n"))
+    }
+
+    /// Returns the completion options matching the request.
+    #[must_use]
+    pub fn options(&self) -> CompletionOptions {
+        CompletionOptions {
+            thinking: matches!(self.thinking, Some(ThinkingConfig::Enabled { .. })),
+            stop_sequences: self.stop_sequences.clone(),
+            ..CompletionOptions::default()
+        }
+    }
+}
+
+impl MessagesResponse {
+    /// Builds a response for `model` from the outcome of a completion.
+    #[must_use]
+    pub fn from_result(result: &CompletionResult, model: &str) -> Self {
+        let message = &result.message;
+        Self {
+            id: format!("msg_{}", message.message_id.unwrap_or_default()),
+            kind: "message".to_string(),
+            role: "assistant".to_string(),
+            model: model.to_string(),
+            content: vec![ContentBlock::Text {
+                text: message.content.clone(),
+            }],
+            stop_reason: Some(if result.truncated {
+                StopReason::MaxTokens
+            } else if result.stop_sequence.is_some() {
+                StopReason::StopSequence
+            } else {
+                StopReason::EndTurn
+            }),
+            stop_sequence: result.stop_sequence.clone(),
+            usage: Usage {
+                input_tokens: 0,
+                output_tokens: message
+                    .accumulated_token_usage
+                    .and_then(|tokens| u64::try_from(tokens).ok())
+                    .unwrap_or_default(),
+            },
+        }
+    }
+}
+
+/// Answers `request` in a new chat session, like Anthropic's `POST /v1/messages`.
+///
+/// See the [module documentation](self) for what is supported.
+///
+/// # Errors
+/// Returns an error if the request cannot be rendered as a prompt (see
+/// [`MessagesRequest::to_prompt`]), the chat cannot be created, or the
+/// completion fails.
+pub async fn create_message(
+    api: &DeepSeekAPI,
+    request: &MessagesRequest,
+) -> Result<MessagesResponse, DeepSeekError> {
+    let prompt = request.to_prompt()?;
+    let chat = api.create_chat().await?;
+    let result = api
+        .collect_completion(&chat.id, &prompt, None, request.options())
+        .await?;
+    Ok(MessagesResponse::from_result(&result, &request.model))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn conversation_is_rendered_as_transcript() {
+        let request: MessagesRequest = serde_json::from_value(json!({
+            "model": "claude-compatible",
+            "max_tokens": 1024,
+            "system": "Be brief.",
+            "messages": [
+                {"role": "user", "content": "My name is Alice."},
+                {"role": "assistant", "content": [{"type": "text", "text": "Hi Alice!"}]},
+                {"role": "user", "content": "What's my name?"}
+            ],
+            "thinking": {"type": "enabled", "budget_tokens": 2048}
+        }))
+        .unwrap();
+        assert_eq!(
+            request.to_prompt().unwrap(),
+            "Be brief.\n\nUser: My name is Alice.\n\nAssistant: Hi Alice!\n\nUser: What's my name?"
+        );
+        assert!(request.options().thinking);
+
+        let single: MessagesRequest = serde_json::from_value(json!({
+            "messages": [{"role": "user", "content": "Hello"}]
+        }))
+        .unwrap();
+        assert_eq!(single.to_prompt().unwrap(), "Hello");
+    }
+
+    #[test]
+    fn unsupported_blocks_are_rejected() {
+        let request: MessagesRequest = serde_json::from_value(json!({
+            "messages": [{"role": "user", "content": [
+                {"type": "image", "source": {"type": "base64", "data": ""}}
+            ]}]
+        }))
+        .unwrap();
+        assert!(request.to_prompt().is_err());
+    }
+
+    #[test]
+    fn response_has_messages_shape() {
+        let result = CompletionResult {
+            message: serde_json::from_value(json!({
+                "message_id": 2,
+                "content": "Hi",
+                "accumulated_token_usage": 5
+            }))
+            .unwrap(),
+            continuations: 0,
+            session: None,
+            truncated: false,
+            retries: 0,
+            stop_sequence: None,
+        };
+        assert_eq!(
+            serde_json::to_value(MessagesResponse::from_result(&result, "m")).unwrap(),
+            json!({
+                "id": "msg_2",
+                "type": "message",
+                "role": "assistant",
+                "model": "m",
+                "content": [{"type": "text", "text": "Hi"}],
+                "stop_reason": "end_turn",
+                "stop_sequence": null,
+                "usage": {"input_tokens": 0, "output_tokens": 5}
+            })
+        );
+    }
+}
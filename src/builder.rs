@@ -0,0 +1,461 @@
+//! Builder for configuring a [`DeepSeekAPI`] client.
+
+use anyhow::Result;
+use futures_util::future::BoxFuture;
+use reqwest::Client;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::middleware::Middleware;
+use crate::{
+    DEFAULT_API_VERSION, DEFAULT_BASE_URL, DEFAULT_MAX_CONTINUATIONS, DEFAULT_MAX_POW_SOLVERS,
+    DeepSeekAPI, DeepSeekError, OversizeAction, PowConfig, RetryPolicy, Token, TokenRefresh,
+    bearer, pow_solver,
+};
+
+/// Builder for [`DeepSeekAPI`], created with [`DeepSeekAPI::builder`].
+#[allow(clippy::struct_excessive_bools)]
+pub struct DeepSeekAPIBuilder {
+    token: Token,
+    thinking_fallback: bool,
+    detect_images: bool,
+    stream_buffer: Option<usize>,
+    pow_config: PowConfig,
+    pow_solvers: usize,
+    api_version: String,
+    base_url: String,
+    pow_enabled: bool,
+    pow_response: Option<String>,
+    middleware: Vec<Arc<dyn Middleware>>,
+    response_limit: Option<(usize, OversizeAction)>,
+    empty_response_retries: u32,
+    lenient_parsing: bool,
+    max_concurrency: Option<usize>,
+    total_timeout: Option<Duration>,
+    heartbeat_timeout: Option<Duration>,
+    request_timeout: Option<Duration>,
+    connect_timeout: Option<Duration>,
+    warm_up: bool,
+    token_refresh: Option<Arc<TokenRefresh>>,
+    client: Option<Client>,
+    max_continuations: u32,
+    retry: RetryPolicy,
+}
+
+impl DeepSeekAPIBuilder {
+    pub(crate) fn new(token: Token) -> Self {
+        Self {
+            token,
+            thinking_fallback: false,
+            detect_images: false,
+            stream_buffer: None,
+            pow_config: PowConfig::default(),
+            pow_solvers: std::thread::available_parallelism()
+                .map_or(1, std::num::NonZeroUsize::get)
+                .min(DEFAULT_MAX_POW_SOLVERS),
+            api_version: DEFAULT_API_VERSION.to_string(),
+            base_url: DEFAULT_BASE_URL.to_string(),
+            pow_enabled: true,
+            pow_response: None,
+            middleware: Vec::new(),
+            response_limit: None,
+            empty_response_retries: 0,
+            lenient_parsing: false,
+            max_concurrency: None,
+            total_timeout: None,
+            heartbeat_timeout: None,
+            request_timeout: None,
+            connect_timeout: None,
+            warm_up: false,
+            token_refresh: None,
+            client: None,
+            max_continuations: DEFAULT_MAX_CONTINUATIONS,
+            retry: RetryPolicy::default(),
+        }
+    }
+
+    /// Retries a completion once with thinking disabled when the server rejects
+    /// thinking as unsupported.
+    ///
+    /// When the fallback is used, the stream yields a
+    /// [`StreamChunk::Warning`](crate::StreamChunk::Warning) before any content.
+    /// Defaults to `false`, in which case the
+    /// [`DeepSeekError::UnsupportedFeature`](crate::DeepSeekError::UnsupportedFeature)
+    /// error is returned to the caller.
+    #[must_use]
+    pub fn thinking_fallback(mut self, enabled: bool) -> Self {
+        self.thinking_fallback = enabled;
+        self
+    }
+
+    /// Yields a [`StreamChunk::Image`](crate::StreamChunk::Image) for each image
+    /// embedded in streamed content.
+    ///
+    /// This is a heuristic: images are recognized by Markdown image syntax in the
+    /// content, as [`Message::inline_images`](crate::models::Message::inline_images)
+    /// parses it, so images the model merely writes out are reported too.
+    /// Defaults to `false`.
+    #[must_use]
+    pub fn detect_images(mut self, enabled: bool) -> Self {
+        self.detect_images = enabled;
+        self
+    }
+
+    /// Reads and parses streaming responses on a separate task, buffering up to
+    /// `capacity` parsed events ahead of the consumer.
+    ///
+    /// This keeps the connection draining while a slow consumer processes earlier
+    /// chunks, at the cost of holding up to `capacity` events in memory. By default
+    /// responses are parsed inline as the stream is polled, so a slow consumer
+    /// stalls network reads but nothing is buffered. A `capacity` of zero is
+    /// treated as one.
+    #[must_use]
+    pub fn stream_buffer(mut self, capacity: usize) -> Self {
+        self.stream_buffer = Some(capacity);
+        self
+    }
+
+    /// Configures the WebAssembly engine used to solve `PoW` challenges, e.g. to
+    /// reduce its memory footprint. See [`PowConfig`] for the available settings.
+    #[must_use]
+    pub fn pow_config(mut self, config: PowConfig) -> Self {
+        self.pow_config = config;
+        self
+    }
+
+    /// Creates `count` `PoW` solvers, so that up to `count` challenges of
+    /// concurrent requests are solved in parallel instead of one after another.
+    ///
+    /// The solvers share one compiled WebAssembly module, but each needs its own
+    /// instance with around 1 MiB of linear memory. Solving is CPU-bound, so more
+    /// solvers than CPU cores do not help. Defaults to the number of CPU cores,
+    /// at most 4; at least one solver is created.
+    #[must_use]
+    pub fn pow_solvers(mut self, count: usize) -> Self {
+        self.pow_solvers = count;
+        self
+    }
+
+    /// Skips `PoW` entirely: no challenges are requested or solved and no
+    /// `x-ds-pow-response` header is sent.
+    ///
+    /// Use this when a proxy injects `PoW` or the account does not require it. The
+    /// WebAssembly solver is not loaded, so building the client needs no download.
+    #[must_use]
+    pub fn disable_pow(mut self) -> Self {
+        self.pow_enabled = false;
+        self
+    }
+
+    /// Like [`disable_pow`](Self::disable_pow), but sends `value` as the
+    /// `x-ds-pow-response` header of every request that would need `PoW`.
+    #[must_use]
+    pub fn pow_response(mut self, value: impl Into<String>) -> Self {
+        self.pow_enabled = false;
+        self.pow_response = Some(value.into());
+        self
+    }
+
+    /// Adds a middleware wrapping every HTTP request the client sends, including
+    /// `PoW` challenge requests and streaming completions. See
+    /// [`middleware`](crate::middleware) for how middleware is called.
+    ///
+    /// Middleware added first is outermost: it sees the request first and the
+    /// response last. Requests reach middleware fully prepared, with the
+    /// `x-ds-pow-response` header already set, so a middleware cannot influence the
+    /// `PoW` solved for a request; the challenge request for that `PoW` passes
+    /// through the middleware separately, before it. The `Authorization` header is
+    /// added only when the request is finally sent and is not visible to
+    /// middleware.
+    #[must_use]
+    pub fn middleware(mut self, middleware: impl Middleware) -> Self {
+        self.middleware.push(Arc::new(middleware));
+        self
+    }
+
+    /// Caps the text of a single response at `max_bytes`, counting content and
+    /// thinking across continuations.
+    ///
+    /// When a response exceeds the cap, the client asks the server to stop
+    /// generating and, depending on `action`, ends the stream with the message
+    /// truncated to `max_bytes` or with a
+    /// [`DeepSeekError::ResponseTooLarge`](crate::DeepSeekError::ResponseTooLarge)
+    /// error. A truncated message only carries its id, content and thinking.
+    /// Unlimited by default.
+    #[must_use]
+    pub fn max_response_size(mut self, max_bytes: usize, action: OversizeAction) -> Self {
+        self.response_limit = Some((max_bytes, action));
+        self
+    }
+
+    /// Retries a non‑streaming completion up to `retries` times when it finishes
+    /// with empty content, which is usually a transient server hiccup.
+    ///
+    /// Each retry sends the prompt again with the same parent, so the empty message
+    /// remains in the chat as a sibling of the answer.
+    /// [`CompletionResult::retries`](crate::CompletionResult::retries) reports how
+    /// many retries were made. Streaming methods never retry. Defaults to 0.
+    #[must_use]
+    pub fn retry_empty_response(mut self, retries: u32) -> Self {
+        self.empty_response_retries = retries;
+        self
+    }
+
+    /// Requests at most `max` continuations per completion when the server keeps
+    /// stopping before the message is complete, bounding the cost of a runaway
+    /// generation.
+    ///
+    /// Past the limit the stream ends with a
+    /// [`CompletionWarning::ContinuationLimit`](crate::CompletionWarning::ContinuationLimit)
+    /// followed by the incomplete message, whose `message_id` can be passed to
+    /// [`DeepSeekAPI::continue_stream`] to resume it. Override it per request with
+    /// [`CompletionOptions::max_continuations`](crate::CompletionOptions::max_continuations).
+    /// Defaults to 8.
+    #[must_use]
+    pub fn max_continuations(mut self, max: u32) -> Self {
+        self.max_continuations = max;
+        self
+    }
+
+    /// Retries requests that fail to connect or get a 5xx or 429 response
+    /// according to `policy`, see [`RetryPolicy`] for what is retried.
+    ///
+    /// This covers fetching `PoW` challenges, creating chats, sending completion
+    /// requests, continuations and regenerations included, and uploading files,
+    /// each attempt with a freshly solved `PoW`. It never resumes a response that
+    /// has started streaming. Other requests, e.g. deleting a chat or stopping a
+    /// generation, are sent once. Defaults to [`RetryPolicy::default`], 3
+    /// attempts; pass [`RetryPolicy::none`] to disable retrying.
+    #[must_use]
+    pub fn retry(mut self, policy: RetryPolicy) -> Self {
+        self.retry = policy;
+        self
+    }
+
+    /// Skips data lines of streaming responses that cannot be parsed or applied,
+    /// logging each at warn level, instead of failing the stream.
+    ///
+    /// This keeps a generation going when the server sends an update this crate
+    /// does not understand, at the risk of a final message missing the skipped
+    /// parts. Errors reported by the API and connection failures still end the
+    /// stream. Defaults to `false`.
+    #[must_use]
+    pub fn lenient_parsing(mut self, enabled: bool) -> Self {
+        self.lenient_parsing = enabled;
+        self
+    }
+
+    /// Limits how many completion and upload requests the client and its clones
+    /// have in flight at once; further requests wait for a slot instead of failing.
+    ///
+    /// A completion holds its slot until its response has been read or the stream
+    /// is dropped, and each continuation request takes a slot of its own. An
+    /// upload holds its slot until the file is sent, not while it is processed.
+    /// The `PoW` challenge of a request is created and solved while holding the
+    /// request's slot, so it counts against the same limit; challenges solved with
+    /// [`DeepSeekAPI::prefetch_pow`] are not limited. A `limit` of zero is treated
+    /// as one. Unlimited by default.
+    #[must_use]
+    pub fn max_concurrency(mut self, limit: usize) -> Self {
+        self.max_concurrency = Some(limit);
+        self
+    }
+
+    /// Bounds the whole of a streamed completion, continuations included.
+    ///
+    /// When `timeout` elapses before the final message, the stream ends with a
+    /// [`DeepSeekError::StreamTimedOut`](crate::DeepSeekError::StreamTimedOut)
+    /// error. Set it generously for long thinking generations, and use
+    /// [`heartbeat_timeout`](Self::heartbeat_timeout) to detect dead connections.
+    /// Unlimited by default.
+    #[must_use]
+    pub fn total_timeout(mut self, timeout: Duration) -> Self {
+        self.total_timeout = Some(timeout);
+        self
+    }
+
+    /// Ends a streaming response with a
+    /// [`DeepSeekError::StreamStalled`](crate::DeepSeekError::StreamStalled) error
+    /// when no data, keepalives included, arrives for `timeout`.
+    ///
+    /// This detects silently dropped connections without limiting how long a
+    /// healthy generation may take. Disabled by default.
+    #[must_use]
+    pub fn heartbeat_timeout(mut self, timeout: Duration) -> Self {
+        self.heartbeat_timeout = Some(timeout);
+        self
+    }
+
+    /// Bounds each attempt of a request whose response is not streamed, e.g.
+    /// creating a chat or uploading a file, from connecting until the response
+    /// has been read.
+    ///
+    /// A request that takes longer fails with a [`reqwest::Error`] for which
+    /// [`is_timeout`](reqwest::Error::is_timeout) is `true`. Uploads are bounded
+    /// too, so allow enough time to send the largest file. Streaming
+    /// completions are not bounded by it, as a healthy generation may take
+    /// minutes: use [`total_timeout`](Self::total_timeout) and
+    /// [`heartbeat_timeout`](Self::heartbeat_timeout) for them. Unlimited by
+    /// default.
+    #[must_use]
+    pub fn request_timeout(mut self, timeout: Duration) -> Self {
+        self.request_timeout = Some(timeout);
+        self
+    }
+
+    /// Bounds connecting to the server, for all requests including streaming
+    /// completions.
+    ///
+    /// A connection that cannot be established in time fails like an
+    /// unreachable server and is retried according to [`retry`](Self::retry).
+    /// It is ignored when a [`client`](Self::client) is set, which brings its
+    /// own timeouts. Unlimited by default.
+    #[must_use]
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Opens a connection to the server while building the client, as
+    /// [`DeepSeekAPI::warm_up`] does, so that the first request does not pay for
+    /// connection setup.
+    ///
+    /// This adds a round trip to [`build`](Self::build). A failed warm-up is
+    /// logged at warn level and does not fail the build. Defaults to `false`.
+    #[must_use]
+    pub fn warm_up(mut self, enabled: bool) -> Self {
+        self.warm_up = enabled;
+        self
+    }
+
+    /// Sets the API version used in request paths, e.g. `"v1"` for
+    /// `/api/v1/chat/completion`.
+    ///
+    /// `PoW` challenges are solved for the same versioned paths, so the two always
+    /// agree. Defaults to `"v0"`.
+    #[must_use]
+    pub fn api_version(mut self, version: impl Into<String>) -> Self {
+        self.api_version = version.into();
+        self
+    }
+
+    /// Calls `refresh` for a new API token when a continuation request is rejected
+    /// with HTTP 401 because the token expired during a long generation.
+    ///
+    /// The new token replaces the old one for all later requests of the client and
+    /// its clones, and the continuation is retried once. If `refresh` fails, or
+    /// the retry is rejected as well, the stream ends with a
+    /// [`DeepSeekError::Unauthorized`](crate::DeepSeekError::Unauthorized) error.
+    /// Other requests are not retried. Not set by default.
+    #[must_use]
+    pub fn token_refresh<F, Fut>(mut self, refresh: F) -> Self
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<String>> + Send + 'static,
+    {
+        self.token_refresh = Some(Arc::new(move || {
+            Box::pin(refresh()) as BoxFuture<'static, _>
+        }));
+        self
+    }
+
+    /// Sends requests through `client` instead of a client built by the crate,
+    /// e.g. to share its connection pool or apply its proxy, TLS and timeout
+    /// settings.
+    ///
+    /// The `Authorization` and `Content-Type: application/json` headers are still
+    /// added to each request unless it already carries them, so `client` needs no
+    /// default headers. The `PoW` WebAssembly module is downloaded without it.
+    #[must_use]
+    pub fn client(mut self, client: Client) -> Self {
+        self.client = Some(client);
+        self
+    }
+
+    /// Sends requests to `base_url` instead of `https://chat.deepseek.com`, e.g.
+    /// `http://localhost:8080` for a mock server or the address of a reverse proxy.
+    ///
+    /// API paths such as `/api/v0/chat/completion` are appended to it, and a
+    /// trailing slash is ignored. `PoW` challenges are still solved for those
+    /// paths, and the `PoW` WebAssembly module is still downloaded from
+    /// `DeepSeek`. Authenticated requests carry the token to this host, so only
+    /// point it at servers you trust.
+    #[must_use]
+    pub fn base_url(mut self, base_url: impl Into<String>) -> Self {
+        self.base_url = base_url.into();
+        self
+    }
+
+    /// Builds the client.
+    ///
+    /// # Errors
+    /// Returns an error if:
+    /// - The authorization header cannot be built.
+    /// - The HTTP client cannot be constructed.
+    /// - The Proof‑of‑Work solver fails to initialize (unless `PoW` is disabled).
+    pub async fn build(self) -> Result<DeepSeekAPI, DeepSeekError> {
+        let pow_solver = if self.pow_enabled {
+            Some(Arc::new(
+                pow_solver::SolverPool::new(&self.pow_config, self.pow_solvers).await?,
+            ))
+        } else {
+            None
+        };
+        let warm_up = self.warm_up;
+        let api = self.build_with_solver(pow_solver)?;
+        if warm_up && let Err(e) = api.warm_up().await {
+            tracing::warn!(error = %format!("{e:#}"), "Connection warm-up failed");
+        }
+        Ok(api)
+    }
+
+    /// Builds the client around `pow_solver`, without any I/O.
+    pub(crate) fn build_with_solver(
+        self,
+        pow_solver: Option<Arc<pow_solver::SolverPool>>,
+    ) -> Result<DeepSeekAPI> {
+        let token = self.token;
+        let authorization = bearer(&token)?;
+        let client = if let Some(client) = self.client {
+            client
+        } else {
+            let builder = Client::builder();
+            match self.connect_timeout {
+                Some(timeout) => builder.connect_timeout(timeout),
+                None => builder,
+            }
+            .build()?
+        };
+
+        Ok(DeepSeekAPI {
+            client,
+            pow_solver,
+            pow_response: self.pow_response,
+            token: Arc::new(std::sync::Mutex::new(token)),
+            authorization: Arc::new(std::sync::Mutex::new(authorization)),
+            token_refresh: self.token_refresh,
+            thinking_fallback: self.thinking_fallback,
+            detect_images: self.detect_images,
+            stream_buffer: self.stream_buffer,
+            pow_config: self.pow_config,
+            api_version: self.api_version,
+            base_url: self.base_url.trim_end_matches('/').to_string(),
+            clock_skew: Arc::default(),
+            stream_cancellation: Arc::default(),
+            pow_pool: Arc::default(),
+            middleware: self.middleware.into(),
+            response_limit: self.response_limit,
+            empty_response_retries: self.empty_response_retries,
+            lenient_parsing: self.lenient_parsing,
+            concurrency: self
+                .max_concurrency
+                .map(|permits| Arc::new(tokio::sync::Semaphore::new(permits.max(1)))),
+            total_timeout: self.total_timeout,
+            heartbeat_timeout: self.heartbeat_timeout,
+            request_timeout: self.request_timeout,
+            max_continuations: self.max_continuations,
+            retry: self.retry,
+        })
+    }
+}
@@ -0,0 +1,677 @@
+//! Typed errors surfaced by the `DeepSeek` client.
+//!
+//! Public methods return `Result<T, DeepSeekError>`, and streams yield such
+//! results, so callers can react to an error by matching on its variant.
+//! Failures below the API have variants of their own, holding the underlying
+//! error: [`Http`](DeepSeekError::Http) and [`Timeout`](DeepSeekError::Timeout)
+//! for transport errors and unexpected HTTP statuses,
+//! [`Parse`](DeepSeekError::Parse) for responses that cannot be parsed and
+//! [`PowSolve`](DeepSeekError::PowSolve) for `PoW` challenges that cannot be
+//! solved. Errors with no dedicated variant are
+//! [`Other`](DeepSeekError::Other), with their full context.
+//!
+//! Rate limits are reported in two ways: an HTTP 429 response is
+//! [`RateLimited`](DeepSeekError::RateLimited), with the server's `Retry-After`,
+//! while a rate limit reported in the `toast` event of a completion stream is
+//! [`Toast(ToastError::RateLimited)`](ToastError::RateLimited), with the
+//! server's message. Handle both to back off in either case:
+//!
+//! ```no_run
+//! use deepseek_api::{DeepSeekAPI, DeepSeekError, ToastError};
+//!
+//! # async fn example(api: &DeepSeekAPI) {
+//! match api.complete("chat-id", "Hello", None, false, false, vec![]).await {
+//!     Ok(message) => println!("{}", message.content),
+//!     Err(DeepSeekError::Unauthorized { .. }) => eprintln!("Log in again"),
+//!     Err(
+//!         DeepSeekError::RateLimited { .. }
+//!         | DeepSeekError::Toast(ToastError::RateLimited(_)),
+//!     ) => eprintln!("Retry later"),
+//!     Err(DeepSeekError::Api { code, message }) => eprintln!("{code:?}: {message}"),
+//!     Err(e @ (DeepSeekError::Http(_) | DeepSeekError::Timeout(_))) => {
+//!         eprintln!("Network error: {e}");
+//!     }
+//!     Err(e) => eprintln!("{e}"),
+//! }
+//! # }
+//! ```
+
+use std::fmt;
+use std::ops::Deref;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Errors returned by the `DeepSeek` client.
+///
+/// New variants may be added, so matches need a catch-all arm.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum DeepSeekError {
+    /// The server rejected a requested feature (e.g. thinking) as unsupported.
+    UnsupportedFeature {
+        /// The feature that was rejected, e.g. `"thinking"`.
+        feature: String,
+        /// The raw message returned by the server.
+        message: String,
+    },
+    /// The conversation no longer fits into the model's context window; summarize
+    /// or shorten it and retry.
+    ///
+    /// Detected from messages mentioning the context together with its length or
+    /// window, being exceeded or too long, or an error code containing
+    /// `context_length`. The counts are parsed only from known phrasings, as in
+    /// `maximum context length is 65536 tokens, however you requested 70123
+    /// tokens`; otherwise they are `None`.
+    ContextLengthExceeded {
+        /// The context size in tokens, if reported.
+        limit: Option<u64>,
+        /// The tokens the conversation needed, if reported.
+        used: Option<u64>,
+        /// The raw message returned by the server.
+        message: String,
+    },
+    /// The `PoW` WASM module could not be downloaded.
+    WasmDownload {
+        /// The URL the module was downloaded from.
+        url: String,
+        /// The number of attempts made.
+        attempts: u32,
+    },
+    /// The `PoW` WASM module was obtained but could not be loaded, e.g. because
+    /// the cached file is corrupt.
+    WasmInvalid {
+        /// Where the module was loaded from.
+        location: String,
+    },
+    /// The server reported an error in a `toast` event of a completion stream.
+    Toast(ToastError),
+    /// A `PoW` challenge uses an algorithm the WASM solver does not implement;
+    /// updating the crate may add support for it.
+    UnsupportedPowAlgorithm {
+        /// The algorithm requested by the challenge.
+        algorithm: String,
+        /// The algorithm the solver implements.
+        supported: String,
+    },
+    /// A `PoW` challenge expired, or was about to, before it could be solved, e.g.
+    /// because the local clock is off or the challenge waited too long for a
+    /// solver.
+    PowChallengeExpired {
+        /// When the challenge expires, in milliseconds since the Unix epoch.
+        expire_at: i64,
+    },
+    /// No data arrived on a streaming response within the heartbeat timeout.
+    StreamStalled {
+        /// The configured heartbeat timeout.
+        timeout: Duration,
+    },
+    /// A streamed completion did not finish within the total timeout.
+    StreamTimedOut {
+        /// The configured total timeout.
+        timeout: Duration,
+    },
+    /// The server rejected the API token with HTTP 401, e.g. because it expired.
+    /// This is the only authentication error the API reports.
+    ///
+    /// When a continuation request is rejected, `message_id` is the incomplete
+    /// message, which can be resumed with
+    /// [`DeepSeekAPI::continue_stream`](crate::DeepSeekAPI::continue_stream) by a
+    /// client with a valid token. See also
+    /// [`DeepSeekAPIBuilder::token_refresh`](crate::DeepSeekAPIBuilder::token_refresh).
+    Unauthorized {
+        /// The message that was being continued, if any.
+        message_id: Option<i64>,
+        /// The request that was rejected, if known.
+        request: Option<RequestContext>,
+    },
+    /// The server rejected the request with HTTP 429 because too many requests
+    /// were sent, after any retries set with
+    /// [`DeepSeekAPIBuilder::retry`](crate::DeepSeekAPIBuilder::retry). A rate
+    /// limit reported in a completion stream is
+    /// [`ToastError::RateLimited`] instead.
+    RateLimited {
+        /// How long the server asked to wait before sending another request,
+        /// from its `Retry-After` header.
+        retry_after: Option<Duration>,
+        /// The request that was rejected, if known.
+        request: Option<RequestContext>,
+    },
+    /// The API reported an error that has no dedicated variant.
+    Api {
+        /// The error code of the response, if it had one.
+        code: Option<i64>,
+        /// The raw message returned by the server.
+        message: String,
+    },
+    /// The operation was cancelled through its cancellation token.
+    Cancelled,
+    /// A response exceeded the configured maximum size and was stopped.
+    ResponseTooLarge {
+        /// The limit in bytes.
+        limit: usize,
+    },
+    /// A request could not be sent or its response could not be read, or the
+    /// server answered with an unexpected HTTP status, see
+    /// [`reqwest::Error::status`].
+    Http(Shared<reqwest::Error>),
+    /// A request did not complete within the request or connect timeout, see
+    /// [`DeepSeekAPIBuilder::request_timeout`](crate::DeepSeekAPIBuilder::request_timeout).
+    /// Streams end with [`StreamStalled`](Self::StreamStalled) or
+    /// [`StreamTimedOut`](Self::StreamTimedOut) instead.
+    Timeout(Shared<reqwest::Error>),
+    /// A response could not be parsed, e.g. because the API changed; the source
+    /// is usually a [`serde_json::Error`].
+    Parse(Shared<dyn std::error::Error + Send + Sync>),
+    /// A `PoW` challenge could not be solved, e.g. because the WASM module
+    /// failed. Challenges that cannot be solved for known reasons have variants
+    /// of their own, such as [`PowChallengeExpired`](Self::PowChallengeExpired).
+    PowSolve(Shared<anyhow::Error>),
+    /// Any other error, e.g. an invalid argument or a failed file operation,
+    /// with the context it was reported in.
+    Other(Shared<anyhow::Error>),
+}
+
+/// An error from below the API, shared so that [`DeepSeekError`] can be cloned.
+///
+/// Dereferences to the error. Two `Shared` errors are equal only if they are
+/// clones of the same error.
+pub struct Shared<E: ?Sized> {
+    error: Arc<E>,
+    context: Option<RequestContext>,
+}
+
+impl<E: ?Sized> Shared<E> {
+    fn from_arc(error: Arc<E>) -> Self {
+        Self {
+            error,
+            context: None,
+        }
+    }
+}
+
+impl<E> Shared<E> {
+    pub(crate) fn new(error: E) -> Self {
+        Self::from_arc(Arc::new(error))
+    }
+}
+
+impl<E: ?Sized> Clone for Shared<E> {
+    fn clone(&self) -> Self {
+        Self {
+            error: Arc::clone(&self.error),
+            context: self.context.clone(),
+        }
+    }
+}
+
+impl<E: ?Sized> PartialEq for Shared<E> {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.error, &other.error)
+    }
+}
+
+impl<E: ?Sized> Eq for Shared<E> {}
+
+impl<E: ?Sized> Deref for Shared<E> {
+    type Target = E;
+
+    fn deref(&self) -> &E {
+        &self.error
+    }
+}
+
+impl<E: fmt::Debug + ?Sized> fmt::Debug for Shared<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.error.fmt(f)
+    }
+}
+
+impl DeepSeekError {
+    /// Returns the request the error originated from, if known.
+    ///
+    /// Errors from completion, continuation and upload requests carry it when
+    /// they are [`Http`](Self::Http), [`Timeout`](Self::Timeout),
+    /// [`Parse`](Self::Parse), [`PowSolve`](Self::PowSolve),
+    /// [`Other`](Self::Other), [`Unauthorized`](Self::Unauthorized) or
+    /// [`RateLimited`](Self::RateLimited).
+    #[must_use]
+    pub fn request_context(&self) -> Option<&RequestContext> {
+        match self {
+            Self::Http(err) | Self::Timeout(err) => err.context.as_ref(),
+            Self::Parse(err) => err.context.as_ref(),
+            Self::PowSolve(err) | Self::Other(err) => err.context.as_ref(),
+            Self::Unauthorized { request, .. } | Self::RateLimited { request, .. } => {
+                request.as_ref()
+            }
+            _ => None,
+        }
+    }
+
+    /// Records `context` on the error if its variant carries one and it has none
+    /// yet.
+    fn with_request_context(mut self, context: Option<RequestContext>) -> Self {
+        let slot = match &mut self {
+            Self::Http(err) | Self::Timeout(err) => &mut err.context,
+            Self::Parse(err) => &mut err.context,
+            Self::PowSolve(err) | Self::Other(err) => &mut err.context,
+            Self::Unauthorized { request, .. } | Self::RateLimited { request, .. } => request,
+            _ => return self,
+        };
+        if slot.is_none() {
+            *slot = context;
+        }
+        self
+    }
+}
+
+impl From<anyhow::Error> for DeepSeekError {
+    /// Recovers the [`DeepSeekError`] in the chain of `err`, or classifies it by
+    /// its underlying error, keeping the [`RequestContext`] of the chain.
+    fn from(err: anyhow::Error) -> Self {
+        let context = err.downcast_ref::<RequestContext>().cloned();
+        let err = if let Some(err) = err.downcast_ref::<DeepSeekError>() {
+            err.clone()
+        } else {
+            match err.downcast::<reqwest::Error>() {
+                Ok(err) => err.into(),
+                Err(err) => match err.downcast::<serde_json::Error>() {
+                    Ok(err) => err.into(),
+                    Err(err) => Self::Other(Shared::new(err)),
+                },
+            }
+        };
+        err.with_request_context(context)
+    }
+}
+
+impl From<reqwest::Error> for DeepSeekError {
+    fn from(err: reqwest::Error) -> Self {
+        if err.is_timeout() {
+            Self::Timeout(Shared::new(err))
+        } else if err.is_decode() {
+            Self::Parse(Shared::from_arc(Arc::new(err)))
+        } else {
+            Self::Http(Shared::new(err))
+        }
+    }
+}
+
+impl From<serde_json::Error> for DeepSeekError {
+    fn from(err: serde_json::Error) -> Self {
+        Self::Parse(Shared::from_arc(Arc::new(err)))
+    }
+}
+
+impl From<std::io::Error> for DeepSeekError {
+    fn from(err: std::io::Error) -> Self {
+        Self::Other(Shared::new(err.into()))
+    }
+}
+
+/// An error reported in a `toast` event, classified by its message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ToastError {
+    /// Too many requests were sent; retry later. A rate limit reported with HTTP
+    /// 429 is [`DeepSeekError::RateLimited`] instead.
+    RateLimited(String),
+    /// The prompt or answer was blocked by content policy.
+    ContentPolicy(String),
+    /// The server is busy; back off and retry.
+    ServerOverloaded(String),
+    /// Any other toast, with its raw message.
+    Other(String),
+}
+
+impl ToastError {
+    /// Classifies a toast message (and its code, if any) into a variant.
+    #[must_use]
+    pub fn classify(message: &str, code: Option<&str>) -> Self {
+        let text = format!("{} {message}", code.unwrap_or_default()).to_ascii_lowercase();
+        let message = message.to_string();
+        if ["rate limit", "rate_limit", "too many", "too frequent"]
+            .iter()
+            .any(|needle| text.contains(needle))
+        {
+            Self::RateLimited(message)
+        } else if ["busy", "overload", "capacity"]
+            .iter()
+            .any(|needle| text.contains(needle))
+        {
+            Self::ServerOverloaded(message)
+        } else if [
+            "content policy",
+            "content_filter",
+            "sensitive",
+            "violat",
+            "inappropriate",
+        ]
+        .iter()
+        .any(|needle| text.contains(needle))
+        {
+            Self::ContentPolicy(message)
+        } else {
+            Self::Other(message)
+        }
+    }
+
+    /// Returns the raw message of the toast.
+    #[must_use]
+    pub fn message(&self) -> &str {
+        match self {
+            Self::RateLimited(message)
+            | Self::ContentPolicy(message)
+            | Self::ServerOverloaded(message)
+            | Self::Other(message) => message,
+        }
+    }
+}
+
+impl fmt::Display for ToastError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self {
+            Self::RateLimited(_) => "Rate limited",
+            Self::ContentPolicy(_) => "Blocked by content policy",
+            Self::ServerOverloaded(_) => "Server overloaded",
+            Self::Other(_) => "API error",
+        };
+        write!(f, "{kind}: {}", self.message())
+    }
+}
+
+impl std::error::Error for ToastError {}
+
+impl fmt::Display for DeepSeekError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnsupportedFeature { feature, message } => {
+                write!(f, "Feature `{feature}` is not supported: {message}")
+            }
+            Self::ContextLengthExceeded { message, .. } => {
+                write!(f, "Context length exceeded: {message}")
+            }
+            Self::WasmDownload { url, attempts } => {
+                write!(
+                    f,
+                    "Failed to download WASM from {url} after {attempts} attempts"
+                )
+            }
+            Self::WasmInvalid { location } => write!(f, "Invalid WASM module at {location}"),
+            Self::Toast(toast) => toast.fmt(f),
+            Self::UnsupportedPowAlgorithm {
+                algorithm,
+                supported,
+            } => write!(
+                f,
+                "PoW algorithm `{algorithm}` is not supported (expected `{supported}`); \
+                 a newer version of the crate may be required"
+            ),
+            Self::PowChallengeExpired { expire_at } => {
+                write!(
+                    f,
+                    "PoW challenge expired (expire_at {expire_at}) before it was solved"
+                )
+            }
+            Self::StreamStalled { timeout } => {
+                write!(f, "No data received from the stream for {timeout:?}")
+            }
+            Self::StreamTimedOut { timeout } => {
+                write!(f, "Stream did not finish within {timeout:?}")
+            }
+            Self::Unauthorized {
+                message_id: None, ..
+            } => f.write_str("Unauthorized: the API token was rejected"),
+            Self::Unauthorized {
+                message_id: Some(message_id),
+                ..
+            } => write!(
+                f,
+                "Unauthorized: the API token was rejected while continuing message {message_id}"
+            ),
+            Self::RateLimited {
+                retry_after: None, ..
+            } => f.write_str("Rate limited: too many requests"),
+            Self::RateLimited {
+                retry_after: Some(retry_after),
+                ..
+            } => write!(
+                f,
+                "Rate limited: too many requests, retry after {retry_after:?}"
+            ),
+            Self::Api {
+                code: Some(code),
+                message,
+            } => write!(f, "API error (code {code}): {message}"),
+            Self::Api {
+                code: None,
+                message,
+            } => write!(f, "API error: {message}"),
+            Self::Cancelled => f.write_str("Operation cancelled"),
+            Self::ResponseTooLarge { limit } => {
+                write!(f, "Response exceeded the maximum size of {limit} bytes")
+            }
+            Self::Http(err) => write!(f, "HTTP error: {}", **err),
+            Self::Timeout(err) => write!(f, "Request timed out: {}", **err),
+            Self::Parse(err) => write!(f, "Failed to parse response: {}", &**err),
+            Self::PowSolve(err) => write!(f, "Failed to solve PoW challenge: {:#}", **err),
+            Self::Other(err) => write!(f, "{:#}", **err),
+        }
+    }
+}
+
+impl std::error::Error for DeepSeekError {
+    /// Returns the cause of the underlying error; the message of the underlying
+    /// error itself, and that of its whole chain for `PowSolve` and `Other`, is
+    /// part of the message of the `DeepSeekError`.
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Http(err) | Self::Timeout(err) => err.source(),
+            Self::Parse(err) => err.source(),
+            _ => None,
+        }
+    }
+}
+
+/// Identifies the HTTP request an error originated from.
+///
+/// Carried by errors from completion, continuation and upload requests, see
+/// [`DeepSeekError::request_context`]. Variants describing the response rather
+/// than the request, such as [`DeepSeekError::Toast`], do not carry it, but
+/// every failed request is logged with its id. The id is sent to `DeepSeek` in the `x-request-id`
+/// header of both the request and its `PoW` challenge, and is recorded on the
+/// `deepseek_request` tracing span.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestContext {
+    /// The client-generated request id.
+    pub request_id: String,
+    /// The API path that was requested.
+    pub path: String,
+}
+
+impl fmt::Display for RequestContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Request {} to {} failed", self.request_id, self.path)
+    }
+}
+
+/// Turns an error message (and its code, if any) returned by the API into an
+/// error, classifying known messages into dedicated [`DeepSeekError`] variants
+/// and others as [`DeepSeekError::Api`].
+pub(crate) fn classify_api_error(message: &str, code: Option<i64>) -> DeepSeekError {
+    known_error(message, None).unwrap_or_else(|| DeepSeekError::Api {
+        code,
+        message: message.to_string(),
+    })
+}
+
+/// Classifies the message and code of a `toast` event.
+pub(crate) fn classify_toast(message: &str, code: Option<&str>) -> DeepSeekError {
+    known_error(message, code)
+        .unwrap_or_else(|| DeepSeekError::Toast(ToastError::classify(message, code)))
+}
+
+/// Recognizes errors that have a dedicated [`DeepSeekError`] variant.
+fn known_error(message: &str, code: Option<&str>) -> Option<DeepSeekError> {
+    unsupported_feature(message).or_else(|| context_length_exceeded(message, code))
+}
+
+/// Recognizes the conversation exceeding the context window, see
+/// [`DeepSeekError::ContextLengthExceeded`].
+fn context_length_exceeded(message: &str, code: Option<&str>) -> Option<DeepSeekError> {
+    let lower = message.to_ascii_lowercase();
+    // Quota and rate limit messages mention tokens too, so require the context
+    let matches = (lower.contains("context")
+        && ["length", "window", "exceed", "too long"]
+            .iter()
+            .any(|needle| lower.contains(needle)))
+        || code.is_some_and(|code| code.to_ascii_lowercase().contains("context_length"));
+    if !matches {
+        return None;
+    }
+    let count_after = |phrases: &[&str]| {
+        phrases.iter().find_map(|phrase| {
+            let rest = &lower[lower.find(phrase)? + phrase.len()..];
+            let digits = rest
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(rest.len());
+            rest[..digits].parse().ok()
+        })
+    };
+    Some(DeepSeekError::ContextLengthExceeded {
+        limit: count_after(&["maximum context length is ", "context window of "]),
+        used: count_after(&["you requested ", "conversation has ", "resulted in "]),
+        message: message.to_string(),
+    })
+}
+
+/// Recognizes the server rejecting thinking as unsupported.
+fn unsupported_feature(message: &str) -> Option<DeepSeekError> {
+    let lower = message.to_ascii_lowercase();
+    (lower.contains("think") && (lower.contains("not support") || lower.contains("unsupported")))
+        .then(|| DeepSeekError::UnsupportedFeature {
+            feature: "thinking".to_string(),
+            message: message.to_string(),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn toasts_are_classified() {
+        assert!(matches!(
+            classify_toast("Too many requests, please slow down", None),
+            DeepSeekError::Toast(ToastError::RateLimited(_))
+        ));
+        assert!(matches!(
+            classify_toast("The server is busy. Please try again later.", None),
+            DeepSeekError::Toast(ToastError::ServerOverloaded(_))
+        ));
+        assert!(matches!(
+            classify_toast("Content may violate our policies", None),
+            DeepSeekError::Toast(ToastError::ContentPolicy(_))
+        ));
+        assert_eq!(
+            classify_toast("Something odd", None),
+            DeepSeekError::Toast(ToastError::Other("Something odd".to_string()))
+        );
+        assert!(matches!(
+            classify_toast("Thinking is not supported", None),
+            DeepSeekError::UnsupportedFeature { .. }
+        ));
+    }
+
+    #[test]
+    fn context_length_is_classified() {
+        let err = classify_api_error(
+            "This model's maximum context length is 65536 tokens, \
+             but the conversation has 70123 tokens",
+            None,
+        );
+        assert!(matches!(
+            err,
+            DeepSeekError::ContextLengthExceeded {
+                limit: Some(65536),
+                used: Some(70123),
+                ..
+            }
+        ));
+        assert_eq!(
+            classify_api_error("Chat session not found", Some(40300)),
+            DeepSeekError::Api {
+                code: Some(40300),
+                message: "Chat session not found".to_string(),
+            }
+        );
+        assert!(matches!(
+            classify_toast(
+                "The conversation is too long",
+                Some("context_length_exceeded")
+            ),
+            DeepSeekError::ContextLengthExceeded {
+                limit: None,
+                used: None,
+                ..
+            }
+        ));
+        assert!(matches!(
+            classify_toast("Too many requests", None),
+            DeepSeekError::Toast(ToastError::RateLimited(_))
+        ));
+        for quota in [
+            "Daily token limit reached",
+            "Too many tokens per minute, retry later",
+        ] {
+            assert!(!matches!(
+                classify_toast(quota, None),
+                DeepSeekError::ContextLengthExceeded { .. }
+            ));
+        }
+        assert!(matches!(
+            classify_api_error("Context too long (70123 > 65536)", None),
+            DeepSeekError::ContextLengthExceeded {
+                limit: None,
+                used: None,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn errors_keep_their_kind() {
+        let typed = anyhow::Error::from(DeepSeekError::Cancelled).context("Failed to upload");
+        assert_eq!(DeepSeekError::from(typed), DeepSeekError::Cancelled);
+
+        let parse = serde_json::from_str::<u32>("{").unwrap_err();
+        let err = DeepSeekError::from(anyhow::Error::from(parse).context("Bad response"));
+        assert!(matches!(err, DeepSeekError::Parse(_)));
+
+        let err = DeepSeekError::from(anyhow::anyhow!("disk full").context("Failed to save"));
+        assert!(matches!(&err, DeepSeekError::Other(_)));
+        assert_eq!(err.to_string(), "Failed to save: disk full");
+        assert_eq!(err.clone(), err);
+    }
+
+    #[test]
+    fn errors_keep_their_request_context() {
+        let context = RequestContext {
+            request_id: "id".to_string(),
+            path: "/api/v0/chat/completion".to_string(),
+        };
+        let parse = serde_json::from_str::<u32>("{").unwrap_err();
+        let err = DeepSeekError::from(anyhow::Error::from(parse).context(context.clone()));
+        assert!(matches!(err, DeepSeekError::Parse(_)));
+        assert_eq!(err.request_context(), Some(&context));
+
+        let rejected = DeepSeekError::Unauthorized {
+            message_id: None,
+            request: None,
+        };
+        let err = DeepSeekError::from(anyhow::Error::from(rejected).context(context.clone()));
+        assert_eq!(err.request_context(), Some(&context));
+
+        let toast = DeepSeekError::Toast(ToastError::Other("Oops".to_string()));
+        let err = DeepSeekError::from(anyhow::Error::from(toast).context(context));
+        assert_eq!(err.request_context(), None);
+    }
+}
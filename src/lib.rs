@@ -2,10 +2,46 @@
 //!
 //! This crate provides an asynchronous client for the `DeepSeek` chat API,
 //! including Proof of Work (`PoW`) solving using a WebAssembly module.
+//!
+//! # Features
+//!
+//! - `zeroize`: scrubs the API token from memory when the client is dropped, and
+//!   a replaced token when it is refreshed. The `Authorization: Bearer <token>`
+//!   header built from it is not scrubbed: `reqwest`'s header values cannot be
+//!   zeroized, so copies of the header, including the one cached by the client,
+//!   stay in memory until their allocations are reused.
+//! - `embed-wasm`: embeds the `PoW` WebAssembly module in the binary instead of
+//!   downloading and caching it at runtime, so building a client needs neither
+//!   network access nor a writable cache directory. Set the `DEEPSEEK_POW_WASM`
+//!   environment variable to the absolute path of the module when building, e.g.
+//!   a copy of the file [`wasm_download::get_wasm_path`] caches; without it,
+//!   building with this feature, including with `--all-features`, fails with a
+//!   message saying so. The module is
+//!   `DeepSeek`'s code, not part of this crate: check that its terms allow you to
+//!   redistribute it before shipping a binary that embeds it.
+//! - `mock`: adds [`DeepSeekAPI::mock`] and the [`mock`] module, for testing code
+//!   that uses the client without network access.
 
+pub mod adapters;
+pub mod anthropic;
+pub mod builder;
+pub mod error;
+pub mod middleware;
+#[cfg(any(test, feature = "mock"))]
+pub mod mock;
 pub mod models;
+pub mod poll;
 mod pow_solver;
-mod wasm_download;
+pub mod request;
+pub mod retry;
+pub mod wasm_download;
+
+pub use builder::DeepSeekAPIBuilder;
+pub use error::{DeepSeekError, RequestContext, ToastError};
+pub use poll::PollStrategy;
+pub use pow_solver::{Challenge, PowConfig, SolveResponse};
+pub use request::CompletionRequest;
+pub use retry::RetryPolicy;
 
 use anyhow::{Context, Result};
 use bytes::Buf;
@@ -13,60 +49,211 @@ use reqwest::multipart;
 use futures_util::StreamExt;
 use reqwest::{Client, header};
 use serde_json::json;
+use std::collections::{HashMap, VecDeque};
 use std::sync::Arc;
-use tokio::sync::Mutex;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+use tracing::Instrument;
+
+use crate::error::{classify_api_error, classify_toast};
 
-use crate::pow_solver::Challenge;
+/// Base URL used unless configured with [`DeepSeekAPIBuilder::base_url`].
+const DEFAULT_BASE_URL: &str = "https://chat.deepseek.com";
+/// API version used unless configured with [`DeepSeekAPIBuilder::api_version`].
+const DEFAULT_API_VERSION: &str = "v0";
+/// Continuations allowed per completion unless configured with
+/// [`DeepSeekAPIBuilder::max_continuations`].
+const DEFAULT_MAX_CONTINUATIONS: u32 = 8;
+/// Upper bound of the default number of `PoW` solvers, see
+/// [`DeepSeekAPIBuilder::pow_solvers`].
+const DEFAULT_MAX_POW_SOLVERS: usize = 4;
+/// Sessions requested per page by [`DeepSeekAPI::list_chats`].
+const CHAT_PAGE_SIZE: usize = 50;
+const COMPLETION_ENDPOINT: &str = "chat/completion";
+const CONTINUE_ENDPOINT: &str = "chat/continue";
+const REGENERATE_ENDPOINT: &str = "chat/regenerate";
+const UPLOAD_ENDPOINT: &str = "file/upload_file";
+/// Header carrying the client-generated id of each request.
+const REQUEST_ID_HEADER: &str = "x-request-id";
 
-const COMPLETION_PATH: &str = "/api/v0/chat/completion";
-const CONTINUE_PATH: &str = "/api/v0/chat/continue";
+/// The API token, scrubbed from memory on drop with the `zeroize` feature. The
+/// `Authorization` header built from it is not, see the crate documentation.
+#[cfg(feature = "zeroize")]
+type Token = zeroize::Zeroizing<String>;
+#[cfg(not(feature = "zeroize"))]
+type Token = String;
+
+/// A callback returning a fresh API token, see [`DeepSeekAPIBuilder::token_refresh`].
+type TokenRefresh =
+    dyn Fn() -> futures_util::future::BoxFuture<'static, Result<String>> + Send + Sync;
+
+/// Builds the sensitive `Authorization` header for `token`.
+fn bearer(token: &str) -> Result<header::HeaderValue> {
+    let authorization = Token::from(format!("Bearer {token}"));
+    let mut authorization =
+        header::HeaderValue::from_str(&authorization).context("Invalid authorization header")?;
+    authorization.set_sensitive(true);
+    Ok(authorization)
+}
 
 /// Client for interacting with the `DeepSeek` API.
 pub struct DeepSeekAPI {
     client: Client,
-    pow_solver: Arc<Mutex<pow_solver::POWSolver>>,
-    token: String,
+    /// `None` when `PoW` solving is disabled.
+    pow_solver: Option<Arc<pow_solver::SolverPool>>,
+    /// Fixed `x-ds-pow-response` header sent when `PoW` solving is disabled.
+    pow_response: Option<String>,
+    /// The API token, replaced when it is refreshed.
+    token: Arc<std::sync::Mutex<Token>>,
+    /// The `Authorization` header sent with every request, replaced when the
+    /// token is refreshed.
+    authorization: Arc<std::sync::Mutex<header::HeaderValue>>,
+    token_refresh: Option<Arc<TokenRefresh>>,
+    thinking_fallback: bool,
+    detect_images: bool,
+    stream_buffer: Option<usize>,
+    pow_config: PowConfig,
+    api_version: String,
+    /// The scheme and host requests are sent to, without a trailing slash.
+    base_url: String,
+    clock_skew: Arc<std::sync::Mutex<Option<f64>>>,
+    /// Parent of the tokens of all abortable streams, replaced by `cancel_all`.
+    stream_cancellation: Arc<std::sync::Mutex<CancellationToken>>,
+    /// Solutions from `prefetch_pow`, by target path.
+    pow_pool: Arc<std::sync::Mutex<HashMap<String, VecDeque<SolvedPow>>>>,
+    middleware: Arc<[Arc<dyn middleware::Middleware>]>,
+    response_limit: Option<(usize, OversizeAction)>,
+    empty_response_retries: u32,
+    lenient_parsing: bool,
+    /// Limits concurrent completions and uploads, see `max_concurrency`.
+    concurrency: Option<Arc<tokio::sync::Semaphore>>,
+    total_timeout: Option<Duration>,
+    heartbeat_timeout: Option<Duration>,
+    /// Bounds each attempt of a request whose response is not streamed.
+    request_timeout: Option<Duration>,
+    max_continuations: u32,
+    retry: RetryPolicy,
 }
 
 impl DeepSeekAPI {
-    /// Creates a new `DeepSeek` API client.
+    /// Creates a new `DeepSeek` API client with default settings.
+    ///
+    /// Use [`DeepSeekAPI::builder`] to customize the client.
     ///
     /// # Errors
     /// Returns an error if:
     /// - The authorization header cannot be built.
     /// - The HTTP client cannot be constructed.
     /// - The Proof‑of‑Work solver fails to initialize.
-    pub async fn new(token: impl Into<String>) -> Result<Self> {
-        let token = token.into();
-        let client = Client::builder()
-            .default_headers({
-                let mut headers = header::HeaderMap::new();
-                headers.insert(
-                    header::AUTHORIZATION,
-                    header::HeaderValue::from_str(&format!("Bearer {token}"))
-                        .context("Invalid authorization header")?,
-                );
-                headers.insert(
-                    header::CONTENT_TYPE,
-                    header::HeaderValue::from_static("application/json"),
-                );
-                headers
-            })
-            .build()?;
+    pub async fn new(token: impl Into<String>) -> Result<Self, DeepSeekError> {
+        Self::builder(token).build().await
+    }
 
-        let pow_solver = Arc::new(Mutex::new(pow_solver::POWSolver::new().await?));
-        Ok(Self {
-            client,
-            pow_solver,
-            token,
-        })
+    /// Creates a client with default settings that sends its requests to
+    /// `base_url` instead of `https://chat.deepseek.com`, e.g. a reverse proxy or
+    /// a local mock server. See [`DeepSeekAPIBuilder::base_url`].
+    ///
+    /// # Errors
+    /// Returns an error as described for [`new`](Self::new).
+    pub async fn with_base_url(
+        token: impl Into<String>,
+        base_url: impl Into<String>,
+    ) -> Result<Self, DeepSeekError> {
+        Self::builder(token).base_url(base_url).build().await
+    }
+
+    /// Creates a client with default settings that sends its requests through
+    /// `client`, e.g. one shared with the rest of an application. See
+    /// [`DeepSeekAPIBuilder::client`].
+    ///
+    /// # Errors
+    /// Returns an error as described for [`new`](Self::new).
+    pub async fn with_client(
+        client: Client,
+        token: impl Into<String>,
+    ) -> Result<Self, DeepSeekError> {
+        Self::builder(token).client(client).build().await
+    }
+
+    /// Returns a builder for configuring a new `DeepSeek` API client.
+    #[must_use]
+    pub fn builder(token: impl Into<String>) -> DeepSeekAPIBuilder {
+        DeepSeekAPIBuilder::new(Token::from(token.into()))
+    }
+
+    /// Clears the cached `PoW` WASM module, downloads it again and rebuilds the solvers.
+    ///
+    /// Use this to recover when the cached module is corrupt or `DeepSeek` has
+    /// rotated it. The new solvers are shared by all clones of this client. If the
+    /// client was built with [`DeepSeekAPIBuilder::disable_pow`], only the cache
+    /// is cleared. With the `embed-wasm` feature, the solvers are rebuilt from the
+    /// embedded module.
+    ///
+    /// # Errors
+    /// Returns an error if the cache cannot be cleared or the new solver fails to
+    /// initialize; the previous solver is kept in that case.
+    pub async fn refresh_pow(&self) -> Result<(), DeepSeekError> {
+        wasm_download::clear_cache_in(self.pow_config.cache_dir.as_deref()).await?;
+        if let Some(pow_solver) = &self.pow_solver {
+            pow_solver.refresh(&self.pow_config).await?;
+        }
+        Ok(())
+    }
+
+    /// Returns whether the `PoW` WASM module of the current solvers was downloaded or
+    /// loaded from the cache, or `None` if `PoW` is disabled.
+    pub async fn wasm_source(&self) -> Option<wasm_download::WasmSource> {
+        match &self.pow_solver {
+            Some(solver) => Some(solver.source().await),
+            None => None,
+        }
+    }
+
+    /// Estimates how long solving a `PoW` challenge of `difficulty` takes on this
+    /// machine, based on the solves made so far.
+    ///
+    /// Returns `None` before the first solve or if `PoW` is disabled. Solved
+    /// difficulties are logged at debug level. The estimate is a mean: the time of
+    /// a single solve is random and often half or twice as long.
+    pub async fn estimate_pow_solve_time(&self, difficulty: f64) -> Option<Duration> {
+        match &self.pow_solver {
+            Some(solver) => solver.estimate_solve_time(difficulty).await,
+            None => None,
+        }
     }
 
     /// Creates a new chat session.
     ///
+    /// The server picks the session's agent, reported in
+    /// [`ChatSession::agent`](models::ChatSession::agent); use
+    /// [`create_chat_with_agent`](Self::create_chat_with_agent) to choose it.
+    ///
     /// # Errors
     /// Returns an error if the API request fails or the response cannot be parsed.
-    pub async fn create_chat(&self) -> Result<crate::models::ChatSession> {
+    pub async fn create_chat(&self) -> Result<crate::models::ChatSession, DeepSeekError> {
+        Ok(self.create_session(json!({})).await?)
+    }
+
+    /// Creates a new chat session with the agent `agent`, e.g. `"chat"`.
+    ///
+    /// The agent is a property of the session: it can only be chosen when the
+    /// session is created and applies to all of its completions. The model used
+    /// for a single completion is chosen with [`CompletionOptions::thinking`],
+    /// which switches between the reasoning and the chat model; other fields of
+    /// the completion request can be set with [`CompletionOptions::extra`].
+    ///
+    /// # Errors
+    /// Returns an error if the API request fails, e.g. because the server does
+    /// not know `agent`, or the response cannot be parsed.
+    pub async fn create_chat_with_agent(
+        &self,
+        agent: &str,
+    ) -> Result<crate::models::ChatSession, DeepSeekError> {
+        Ok(self.create_session(json!({ "agent": agent })).await?)
+    }
+
+    /// Creates a chat session from the request body `body`.
+    async fn create_session(&self, body: serde_json::Value) -> Result<crate::models::ChatSession> {
         #[derive(serde::Deserialize)]
         struct CreateChatResponse {
             data: CreateChatData,
@@ -75,14 +262,17 @@ impl DeepSeekAPI {
         struct CreateChatData {
             biz_data: crate::models::ChatSession,
         }
-        let response = self
-            .client
-            .post("https://chat.deepseek.com/api/v0/chat_session/create")
-            .body("{}")
-            .send()
-            .await?
-            .error_for_status()?;
-        let response_text = response.text().await?;
+        let body = &body;
+        let (response, ()) = self
+            .send_retrying(self.retry, move || async move {
+                let request = self
+                    .client
+                    .post(self.api_url("chat_session/create"))
+                    .json(body);
+                Ok((self.timed(request), ()))
+            })
+            .await?;
+        let response_text = response.error_for_status()?.text().await?;
         let response: CreateChatResponse = serde_json::from_str(&response_text)?;
         Ok(response.data.biz_data)
     }
@@ -92,627 +282,5931 @@ impl DeepSeekAPI {
     /// # Errors
     /// Returns an error if the API request fails, the response indicates an error,
     /// or the response cannot be parsed.
-    pub async fn get_chat_info(&self, chat_id: &str) -> Result<crate::models::ChatSession> {
+    pub async fn get_chat_info(
+        &self,
+        chat_id: &str,
+    ) -> Result<crate::models::ChatSession, DeepSeekError> {
+        let history = self.history::<serde::de::IgnoredAny>(chat_id).await?;
+        Ok(history.chat_session)
+    }
+
+    /// Gets all messages of a chat session, e.g. to show a conversation again
+    /// after a restart.
+    ///
+    /// Messages are ordered by id, oldest first, and include those of all
+    /// branches; follow `parent_id` from the session's `current_message_id` for
+    /// the current thread. Use [`get_messages_page`](Self::get_messages_page) to
+    /// keep only the newest ones.
+    ///
+    /// # Errors
+    /// Returns an error if the API request fails, the response indicates an error,
+    /// or the response cannot be parsed.
+    pub async fn get_chat_messages(
+        &self,
+        chat_id: &str,
+    ) -> Result<Vec<models::Message>, DeepSeekError> {
+        let mut messages = self
+            .history::<models::Message>(chat_id)
+            .await?
+            .chat_messages;
+        messages.sort_by_key(|message| message.message_id);
+        Ok(messages)
+    }
+
+    /// Lists all chat sessions of the account, most recently updated first, as
+    /// shown in the sidebar of the web interface.
+    ///
+    /// Pages of 50 sessions are requested with
+    /// [`list_chats_page`](Self::list_chats_page) until the server reports no
+    /// more; use that method directly to load sessions lazily.
+    ///
+    /// # Errors
+    /// Returns an error if an API request fails, the response indicates an error,
+    /// or the response cannot be parsed.
+    pub async fn list_chats(&self) -> Result<Vec<models::ChatSession>, DeepSeekError> {
+        let mut sessions: Vec<models::ChatSession> = Vec::new();
+        let mut seen = std::collections::HashSet::new();
+        let mut before = None;
+        loop {
+            let page = self.list_chats_page(CHAT_PAGE_SIZE, before).await?;
+            before = page.chat_sessions.last().map(|session| session.updated_at);
+            let found = sessions.len();
+            // The cursor is inclusive, so sessions updated at the same time as the
+            // last one of a page are returned again
+            sessions.extend(
+                page.chat_sessions
+                    .into_iter()
+                    .filter(|session| seen.insert(session.id.clone())),
+            );
+            if !page.has_more || sessions.len() == found {
+                return Ok(sessions);
+            }
+        }
+    }
+
+    /// Gets up to `count` chat sessions of the account, most recently updated
+    /// first, that were updated at or before the Unix timestamp `before`, or the
+    /// most recent ones if it is `None`.
+    ///
+    /// To load the next page, pass the `updated_at` of the last session of this
+    /// one as `before`; it may return sessions from the end of this page again.
+    ///
+    /// # Errors
+    /// Returns an error if the API request fails, the response indicates an error,
+    /// or the response cannot be parsed.
+    pub async fn list_chats_page(
+        &self,
+        count: usize,
+        before: Option<f64>,
+    ) -> Result<models::ChatSessionPage, DeepSeekError> {
         #[derive(serde::Deserialize)]
-        struct GetChatInfoResponse {
+        struct FetchPageResponse {
             code: i64,
             msg: String,
-            data: GetChatInfoData,
-        }
-        #[derive(serde::Deserialize)]
-        struct GetChatInfoData {
-            biz_data: GetChatInfoBizData,
+            /// Missing or `null` when `code` reports an error.
+            data: Option<FetchPageData>,
         }
         #[derive(serde::Deserialize)]
-        struct GetChatInfoBizData {
-            chat_session: crate::models::ChatSession,
+        struct FetchPageData {
+            biz_data: models::ChatSessionPage,
         }
+        let cursor = before.map(|before| format!("&lte_cursor.updated_at={before}"));
         let url = format!(
-            "https://chat.deepseek.com/api/v0/chat/history_messages?chat_session_id={chat_id}"
+            "{}?count={count}{}",
+            self.api_url("chat_session/fetch_page"),
+            cursor.unwrap_or_default()
         );
-        let response: GetChatInfoResponse = self
-            .client
-            .get(&url)
-            .send()
-            .await?
-            .error_for_status()?
-            .json()
-            .await?;
+        let request = self.client.get(&url);
+        let response: FetchPageResponse =
+            self.send(request).await?.error_for_status()?.json().await?;
 
         if response.code != 0 {
-            anyhow::bail!("Failed to get chat info: {}", response.msg);
+            return Err(classify_api_error(&response.msg, Some(response.code)));
         }
 
-        Ok(response.data.biz_data.chat_session)
+        Ok(response
+            .data
+            .context("Missing data in chat session list response")?
+            .biz_data)
     }
 
-    /// Sets the `PoW` header by solving a challenge for the given target path.
-    async fn set_pow_header(&self, target_path: &str) -> Result<String> {
+    /// Deletes the chat session `chat_id` with all its messages.
+    ///
+    /// Like other session management requests, deleting needs no `PoW`.
+    ///
+    /// # Errors
+    /// Returns an error if the API request fails or the response indicates an
+    /// error, e.g. because the session does not exist.
+    pub async fn delete_chat(&self, chat_id: &str) -> Result<(), DeepSeekError> {
         #[derive(serde::Deserialize)]
-        struct PowChallengeResponse {
-            data: PowChallengeData,
+        struct DeleteResponse {
+            code: i64,
+            msg: String,
         }
-        #[derive(serde::Deserialize)]
-        struct PowChallengeData {
-            biz_data: PowChallengeBizData,
+        let request = self
+            .client
+            .post(self.api_url("chat_session/delete"))
+            .json(&json!({ "chat_session_id": chat_id }));
+        let response: DeleteResponse = self.send(request).await?.error_for_status()?.json().await?;
+
+        if response.code != 0 {
+            return Err(classify_api_error(&response.msg, Some(response.code)));
         }
-        #[derive(serde::Deserialize)]
-        struct PowChallengeBizData {
-            challenge: Challenge,
+        Ok(())
+    }
+
+    /// Deletes all chat sessions of the account and returns how many were
+    /// deleted.
+    ///
+    /// The sessions are listed with [`list_chats`](Self::list_chats) and deleted
+    /// one at a time with [`delete_chat`](Self::delete_chat).
+    ///
+    /// # Errors
+    /// Returns an error if the sessions cannot be listed or one cannot be
+    /// deleted; the sessions before it stay deleted.
+    pub async fn delete_all_chats(&self) -> Result<usize, DeepSeekError> {
+        let sessions = self.list_chats().await?;
+        for session in &sessions {
+            self.delete_chat(&session.id).await?;
         }
-        let request_body = serde_json::json!({ "target_path": target_path });
-        let challenge_response = self
-            .client
-            .post("https://chat.deepseek.com/api/v0/chat/create_pow_challenge")
-            .json(&request_body)
-            .send()
-            .await?
-            .error_for_status()?;
-        let challenge_response_text = challenge_response.text().await?;
+        Ok(sessions.len())
+    }
 
-        let challenge_response: PowChallengeResponse =
-            serde_json::from_str(&challenge_response_text)?;
+    /// Gets the latest message of a chat session, or `None` if the session has
+    /// no messages yet.
+    ///
+    /// This is the session's current message when the server reports one, and the
+    /// message with the highest id otherwise. After a completion it is the
+    /// assistant's reply.
+    ///
+    /// # Errors
+    /// Returns an error if the API request fails, the response indicates an error,
+    /// or the response cannot be parsed.
+    pub async fn get_last_message(
+        &self,
+        chat_id: &str,
+    ) -> Result<Option<models::Message>, DeepSeekError> {
+        let history = self.history(chat_id).await?;
+        Ok(last_message(
+            history.chat_session.current_message_id,
+            history.chat_messages,
+        ))
+    }
 
-        let challenge = challenge_response.data.biz_data.challenge;
-        self.pow_solver.lock().await.solve_challenge(challenge)
+    /// Summarizes a chat session: its number of messages, tokens used and the time
+    /// span of its messages. An empty session has zero messages and tokens and no
+    /// timestamps.
+    ///
+    /// # Errors
+    /// Returns an error if the API request fails, the response indicates an error,
+    /// or the response cannot be parsed.
+    pub async fn get_session_stats(
+        &self,
+        chat_id: &str,
+    ) -> Result<models::SessionStats, DeepSeekError> {
+        let history = self.history(chat_id).await?;
+        Ok(session_stats(
+            history.chat_session.current_message_id,
+            history.chat_messages,
+        ))
     }
 
-    /// Completes a chat message (non‑streaming).
+    /// Gets up to `count` messages of a chat session older than
+    /// `before_message_id`, or the newest ones if it is `None`.
     ///
-    /// This method internally uses the streaming version (`complete_stream`) and
-    /// collects all chunks, automatically handling any necessary continuations.
+    /// Messages are ordered oldest first. To load the page before this one, pass
+    /// the `message_id` of its first message as `before_message_id`; an empty page
+    /// means there are no older messages.
+    ///
+    /// `DeepSeek` does not paginate the history endpoint, so the full history is
+    /// still fetched; this bounds how many messages are returned and kept.
     ///
     /// # Errors
-    /// Returns an error if:
-    /// - The Proof‑of‑Work challenge cannot be solved.
-    /// - The API request fails or returns an error status.
-    /// - The response cannot be parsed into a `Message`.
-    pub async fn complete(
+    /// Returns an error if the API request fails, the response indicates an error,
+    /// or the response cannot be parsed.
+    pub async fn get_messages_page(
         &self,
         chat_id: &str,
-        prompt: &str,
-        parent_message_id: Option<i64>,
-        search: bool,
-        thinking: bool,
-        ref_file_ids: Vec<String>,
-    ) -> Result<models::Message> {
-        use futures_util::StreamExt;
-        use tokio::pin;
+        before_message_id: Option<i64>,
+        count: usize,
+    ) -> Result<Vec<models::Message>, DeepSeekError> {
+        let history = self.history(chat_id).await?;
+        Ok(messages_page(
+            history.chat_messages,
+            before_message_id,
+            count,
+        ))
+    }
 
-        let stream = self.complete_stream(
-            chat_id.to_string(),
-            prompt.to_string(),
-            parent_message_id,
-            search,
-            thinking,
-            ref_file_ids,
+    /// Copies the current thread of a chat session into a new session, to try
+    /// different prompts from a common base without changing the original.
+    ///
+    /// `DeepSeek` has no endpoint to duplicate a session, so the fork is made
+    /// client-side: a new session is created and the user prompts on the path to
+    /// the session's current message are sent to it again, in order. This has
+    /// some limitations:
+    /// - The answers are generated anew and may differ from the original ones.
+    /// - Every prompt costs a completion, including its `PoW`.
+    /// - Message ids differ from the original session, and other branches, file
+    ///   references and search or thinking settings are not copied.
+    ///
+    /// # Errors
+    /// Returns an error if the history cannot be fetched, the session cannot be
+    /// created, or a prompt fails; the partially replayed session is kept.
+    pub async fn fork_chat(&self, chat_id: &str) -> Result<models::ChatSession, DeepSeekError> {
+        let history = self.history(chat_id).await?;
+        let thread = current_thread(
+            history.chat_session.current_message_id,
+            history.chat_messages,
         );
-        pin!(stream);
+        let prompts = thread
+            .into_iter()
+            .filter(|message| message.role == Some(models::Role::User))
+            .map(|message| (message.content, Vec::new()));
+        Ok(self.replay(prompts).await?)
+    }
 
-        let mut final_message = None;
-        while let Some(chunk) = stream.next().await {
-            match chunk? {
-                StreamChunk::Content(_) | StreamChunk::Thinking(_) => (),
-                StreamChunk::Message(msg) => {
-                    final_message = Some(msg);
-                    break;
-                }
-            }
+    /// Exports a chat session with all its messages, e.g. for a backup that can be
+    /// restored with [`import_conversation`](Self::import_conversation).
+    ///
+    /// Referenced files are recorded by id only; their contents are not exported.
+    ///
+    /// # Errors
+    /// Returns an error if the API request fails, the response indicates an error,
+    /// or the response cannot be parsed.
+    pub async fn export_conversation(
+        &self,
+        chat_id: &str,
+    ) -> Result<models::ConversationExport, DeepSeekError> {
+        #[derive(serde::Deserialize)]
+        struct FileRef {
+            id: String,
         }
-
-        final_message.context("No final message received")
+        #[derive(serde::Deserialize)]
+        struct HistoryMessage {
+            #[serde(flatten)]
+            message: models::Message,
+            #[serde(default)]
+            files: Vec<FileRef>,
+        }
+        let history = self.history::<HistoryMessage>(chat_id).await?;
+        let mut messages: Vec<_> = history
+            .chat_messages
+            .into_iter()
+            .map(|m| models::ExportedMessage {
+                message: m.message,
+                ref_file_ids: m.files.into_iter().map(|file| file.id).collect(),
+            })
+            .collect();
+        messages.sort_by_key(|m| m.message.message_id);
+        Ok(models::ConversationExport {
+            version: models::ConversationExport::VERSION,
+            session: history.chat_session,
+            messages,
+        })
     }
 
-    /// Completes a chat message (streaming), yielding chunks of content or thinking.
+    /// Recreates an exported conversation as a new chat session.
     ///
-    /// This method automatically continues the generation if the response is incomplete,
-    /// transparently issuing continuation requests until a complete message is obtained.
+    /// Like [`fork_chat`](Self::fork_chat), this replays the user prompts on the
+    /// path to the exported session's current message, with the same limitations:
+    /// answers are generated anew, ids differ and other branches are dropped.
+    /// Referenced files are sent by id, so they must still exist on the account.
     ///
     /// # Errors
-    /// Each yielded `Result` may contain an error if:
-    /// - The Proof‑of‑Work challenge cannot be solved.
-    /// - The API request fails.
-    /// - The streaming response cannot be parsed.
-    ///
-    pub fn complete_stream(
+    /// Returns an error if the export has a newer schema version than this crate
+    /// supports, the session cannot be created, or a prompt fails; the partially
+    /// replayed session is kept.
+    pub async fn import_conversation(
         &self,
-        chat_id: String,
-        prompt: String,
-        parent_message_id: Option<i64>,
-        search: bool,
-        thinking: bool,
-        ref_file_ids: Vec<String>,
-    ) -> impl futures_util::Stream<Item = Result<StreamChunk>> + '_ {
-        use async_stream::stream;
-
-        let this = self.clone();
-        stream! {
-            // Initial request
-            let pow_response = match this.set_pow_header(COMPLETION_PATH).await {
-                Ok(r) => r,
-                Err(e) => {
-                    yield Err(e);
-                    return;
-                }
-            };
-            let request = json!({
-                "chat_session_id": chat_id.clone(),
-                "prompt": prompt,
-                "parent_message_id": parent_message_id,
-                "ref_file_ids": ref_file_ids,
-                "search_enabled": search,
-                "thinking_enabled": thinking,
+        export: models::ConversationExport,
+    ) -> Result<models::ChatSession, DeepSeekError> {
+        if export.version > models::ConversationExport::VERSION {
+            return Err(anyhow::anyhow!(
+                "Unsupported conversation export version {} (up to {} is supported)",
+                export.version,
+                models::ConversationExport::VERSION
+            )
+            .into());
+        }
+        let mut ref_file_ids: HashMap<_, _> = export
+            .messages
+            .iter()
+            .filter_map(|m| m.message.message_id.map(|id| (id, m.ref_file_ids.clone())))
+            .collect();
+        let thread = current_thread(
+            export.session.current_message_id,
+            export.messages.into_iter().map(|m| m.message).collect(),
+        );
+        let prompts = thread
+            .into_iter()
+            .filter(|message| message.role == Some(models::Role::User))
+            .map(|message| {
+                let files = message
+                    .message_id
+                    .and_then(|id| ref_file_ids.remove(&id))
+                    .unwrap_or_default();
+                (message.content, files)
             });
-            let response = match this.client
-                .post(format!("https://chat.deepseek.com{COMPLETION_PATH}"))
-                .header("x-ds-pow-response", &pow_response)
-                .json(&request)
-                .send()
-                .await
-            {
-                Ok(r) => r,
-                Err(e) => {
-                    yield Err(e.into());
-                    return;
-                }
-            };
-            let response = match response.error_for_status() {
-                Ok(r) => r,
-                Err(e) => {
-                    yield Err(e.into());
-                    return;
-                }
-            };
-
-            let mut current_stream = Box::pin(response_to_chunk_stream(response));
-            let mut message_id_for_continuation: Option<i64> = None;
-
-            loop {
-                while let Some(chunk) = current_stream.next().await {
-                    match chunk? {
-                        StreamChunk::Content(c) => yield Ok(StreamChunk::Content(c)),
-                        StreamChunk::Thinking(t) => yield Ok(StreamChunk::Thinking(t)),
-                        StreamChunk::Message(msg) => {
-                            if msg.status.as_deref() == Some("INCOMPLETE") {
-                                message_id_for_continuation = msg.message_id;
-                                break; // exit inner while to start continuation
-                            }
-                            yield Ok(StreamChunk::Message(msg));
-                            return;
-                        }
-                    }
-                }
+        Ok(self.replay(prompts).await?)
+    }
 
-                if let Some(msg_id) = message_id_for_continuation.take() {
-                    // Start continuation
-                    let pow_response = match this.set_pow_header(CONTINUE_PATH).await {
-                        Ok(r) => r,
-                        Err(e) => {
-                            yield Err(e);
-                            return;
-                        }
-                    };
-                    let request = json!({
-                        "chat_session_id": chat_id.clone(),
-                        "message_id": msg_id,
-                        "fallback_to_resume": true,
-                    });
-                    let response = match this.client
-                        .post(format!("https://chat.deepseek.com{CONTINUE_PATH}"))
-                        .header("x-ds-pow-response", &pow_response)
-                        .json(&request)
-                        .send()
-                        .await
-                    {
-                        Ok(r) => r,
-                        Err(e) => {
-                            yield Err(e.into());
-                            return;
-                        }
-                    };
-                    let response = match response.error_for_status() {
-                        Ok(r) => r,
-                        Err(e) => {
-                            yield Err(e.into());
-                            return;
-                        }
-                    };
-                    current_stream = Box::pin(response_to_chunk_stream(response));
-                    // Loop again to process this new stream
-                } else {
-                    // No continuation ID – should not happen, but break to be safe
-                    break;
-                }
-            }
+    /// Sends `prompts` with their referenced files, each answering the previous
+    /// one, into a new chat session.
+    async fn replay(
+        &self,
+        prompts: impl IntoIterator<Item = (String, Vec<String>)>,
+    ) -> Result<models::ChatSession> {
+        let chat = self.create_chat().await?;
+        let mut parent_message_id = None;
+        for (prompt, ref_file_ids) in prompts {
+            let result = self
+                .collect_completion(
+                    &chat.id,
+                    &prompt,
+                    parent_message_id,
+                    CompletionOptions {
+                        ref_file_ids,
+                        ..CompletionOptions::default()
+                    },
+                )
+                .await
+                .with_context(|| format!("Failed to replay message into chat {}", chat.id))?;
+            parent_message_id = result.message.message_id;
         }
+        Ok(self.get_chat_info(&chat.id).await?)
     }
 
-    /// Continues an incomplete message (streaming).
+    /// Clears the title of a chat session, so that `DeepSeek` may generate a new
+    /// one.
     ///
-    /// This method is used internally by `complete_stream` for auto‑continuation,
-    /// but can also be called manually if desired.
+    /// `DeepSeek` has no endpoint that regenerates a title on demand; titles are
+    /// generated while a message is answered. Whether the server generates a
+    /// title with the next completion after this is not documented, so wait for
+    /// one explicitly with [`wait_for_title`](Self::wait_for_title) after sending
+    /// that completion.
     ///
     /// # Errors
-    /// Each yielded `Result` may contain an error if:
-    /// - The Proof‑of‑Work challenge cannot be solved.
-    /// - The API request fails.
-    /// - The streaming response cannot be parsed.
-    pub fn continue_stream(
-        &self,
-        chat_id: String,
-        message_id: i64,
-        fallback_to_resume: bool,
-    ) -> impl futures_util::Stream<Item = Result<StreamChunk>> + '_ {
-        use async_stream::stream;
+    /// Returns an error if the API request fails or the response indicates an
+    /// error.
+    pub async fn clear_title(&self, chat_id: &str) -> Result<(), DeepSeekError> {
+        Ok(self
+            .update_title(chat_id, "")
+            .await
+            .context("Failed to clear chat title")?)
+    }
 
-        let this = self.clone();
-        stream! {
-            let pow_response = match this.set_pow_header(CONTINUE_PATH).await {
-                Ok(r) => r,
-                Err(e) => {
-                    yield Err(e);
-                    return;
-                }
-            };
-            let request = json!({
+    /// Sets the title of a chat session to `title` and returns the updated
+    /// session.
+    ///
+    /// The `chat_session/update_title` endpoint takes no `title_type`; the server
+    /// sets it for a title provided by the user, and the returned session is
+    /// fetched after the update so that it reflects the server's value.
+    ///
+    /// # Errors
+    /// Returns an error if `title` is empty, the API request fails, the response
+    /// indicates an error, or the updated session cannot be fetched.
+    pub async fn rename_chat(
+        &self,
+        chat_id: &str,
+        title: &str,
+    ) -> Result<models::ChatSession, DeepSeekError> {
+        if title.trim().is_empty() {
+            return Err(anyhow::anyhow!(
+                "Chat title must not be empty; use clear_title to clear it"
+            )
+            .into());
+        }
+        self.update_title(chat_id, title)
+            .await
+            .context("Failed to rename chat")?;
+        self.get_chat_info(chat_id).await
+    }
+
+    /// Sets the title of a chat session; an empty title clears it.
+    async fn update_title(&self, chat_id: &str, title: &str) -> Result<()> {
+        #[derive(serde::Deserialize)]
+        struct UpdateTitleResponse {
+            code: i64,
+            msg: String,
+        }
+        let request = self
+            .client
+            .post(self.api_url("chat_session/update_title"))
+            .json(&json!({
                 "chat_session_id": chat_id,
-                "message_id": message_id,
-                "fallback_to_resume": fallback_to_resume,
-            });
-            let response = match this.client
-                .post(format!("https://chat.deepseek.com{CONTINUE_PATH}"))
-                .header("x-ds-pow-response", &pow_response)
-                .json(&request)
-                .send()
-                .await
-            {
-                Ok(r) => r,
-                Err(e) => {
-                    yield Err(e.into());
-                    return;
-                }
-            };
-            let response = match response.error_for_status() {
-                Ok(r) => r,
-                Err(e) => {
-                    yield Err(e.into());
-                    return;
-                }
-            };
+                "title": title,
+            }));
+        let response: UpdateTitleResponse =
+            self.send(request).await?.error_for_status()?.json().await?;
 
-            let mut stream = Box::pin(response_to_chunk_stream(response));
-            while let Some(chunk) = stream.next().await {
-                yield chunk;
-            }
+        if response.code != 0 {
+            return Err(classify_api_error(&response.msg, Some(response.code)).into());
         }
+        Ok(())
     }
 
-    // Removed handle_property_update; logic moved to StreamingMessageBuilder
-
-    /// Uploads a file to the server and waits for it to finish processing.
-    ///
-    /// This method will poll the server until the file status becomes `SUCCESS` or `ERROR`,
-    /// with a maximum of 60 attempts (2 seconds apart, total up to 2 minutes).
+    /// Waits until a chat session has a non-empty title, polling as decided by
+    /// `strategy`, and returns it.
     ///
-    /// # Arguments
-    /// * `file_data` - The file content as bytes.
-    /// * `filename` - The name of the file.
-    /// * `mime_type` - Optional MIME type; if `None`, attempts to guess from the file extension.
+    /// The session reports no status while its title is generated, so `strategy`
+    /// is always given the status `PENDING`.
     ///
     /// # Errors
-    /// Returns an error if the `PoW` challenge fails, the upload request fails, the response
-    /// cannot be parsed, or the file processing fails or times out.
-    pub async fn upload_file(&self, file_data: Vec<u8>, filename: &str, mime_type: Option<&str>) -> Result<models::FileInfo> {
-        use std::time::Duration;
+    /// Returns an error if fetching the session fails or `strategy` gives up
+    /// before a title is available.
+    pub async fn wait_for_title(
+        &self,
+        chat_id: &str,
+        strategy: &dyn PollStrategy,
+    ) -> Result<String, DeepSeekError> {
+        for attempt in 1.. {
+            let session = self.get_chat_info(chat_id).await?;
+            match session.title {
+                Some(title) if !title.is_empty() => return Ok(title),
+                _ => match strategy.next_delay(attempt, "PENDING") {
+                    Some(delay) => tokio::time::sleep(delay).await,
+                    None => {
+                        return Err(
+                            anyhow::anyhow!("No chat title after {attempt} attempts").into()
+                        );
+                    }
+                },
+            }
+        }
+        unreachable!()
+    }
 
-        // Define response structs
+    /// Fetches a chat session together with its messages, parsed as `M`.
+    async fn history<M: serde::de::DeserializeOwned>(
+        &self,
+        chat_id: &str,
+    ) -> Result<ChatHistory<M>> {
         #[derive(serde::Deserialize)]
-        struct UploadResponse {
-            data: UploadData,
+        struct HistoryResponse<M> {
+            code: i64,
+            msg: String,
+            /// Missing or `null` when `code` reports an error.
+            data: Option<HistoryData<M>>,
         }
         #[derive(serde::Deserialize)]
-        struct UploadData {
-            biz_data: models::FileInfo,
+        struct HistoryData<M> {
+            biz_data: ChatHistory<M>,
         }
+        let url = format!(
+            "{}?chat_session_id={chat_id}",
+            self.api_url("chat/history_messages")
+        );
+        let request = self.client.get(&url);
+        let response: HistoryResponse<M> =
+            self.send(request).await?.error_for_status()?.json().await?;
 
-        // 1. Get PoW challenge for file upload
-        let pow_response = self.set_pow_header("/api/v0/file/upload_file").await?;
-
-        // 2. Compute file size before moving data
-        let file_size = file_data.len();
-
-        // 3. Guess MIME type if not provided
-        let mime = mime_type.unwrap_or_else(|| {
-            match std::path::Path::new(filename)
-                .extension()
-                .and_then(|ext| ext.to_str())
-            {
-                Some("png") => "image/png",
-                Some("jpg" | "jpeg") => "image/jpeg",
-                Some("pdf") => "application/pdf",
-                Some("txt") => "text/plain",
-                _ => "application/octet-stream",
-            }
-        });
+        if response.code != 0 {
+            return Err(classify_api_error(&response.msg, Some(response.code)).into());
+        }
 
-        // 4. Prepare multipart form
-        let part = multipart::Part::bytes(file_data)
-            .file_name(filename.to_string())
-            .mime_str(mime)?;
-        let form = multipart::Form::new().part("file", part);
+        Ok(response
+            .data
+            .context("Missing data in chat info response")?
+            .biz_data)
+    }
 
-        // 5. Send upload request
-        let response = self
-            .client
-            .post("https://chat.deepseek.com/api/v0/file/upload_file")
-            .header("x-ds-pow-response", pow_response)
-            .header("x-file-size", file_size.to_string())
-            .multipart(form)
-            .send()
-            .await?
-            .error_for_status()?;
+    /// Fetches the server's current time in seconds since the Unix epoch, read
+    /// from the `Date` header of a lightweight request.
+    ///
+    /// The offset from the local clock is recorded and available from
+    /// [`skew`](Self::skew); it is used to check `PoW` challenges for expiry. The
+    /// `Date` header has a resolution of one second.
+    ///
+    /// # Errors
+    /// Returns an error if the request fails or the response has no valid `Date`
+    /// header.
+    pub async fn get_server_time(&self) -> Result<f64, DeepSeekError> {
+        let sent = std::time::SystemTime::now();
+        let response = self.send(self.client.head(&self.base_url)).await?;
+        Ok(self.record_server_time(&response, sent)?)
+    }
 
-        // 6. Parse initial response (file is now pending)
-        let upload: UploadResponse = response.json().await?;
-        let file_id = upload.data.biz_data.id.clone();
+    /// Records the offset of the server clock from the `Date` header of
+    /// `response` to a request sent at `sent`, returning the server time.
+    fn record_server_time(
+        &self,
+        response: &reqwest::Response,
+        sent: std::time::SystemTime,
+    ) -> Result<f64> {
+        let received = std::time::SystemTime::now();
+        let date = response
+            .headers()
+            .get(header::DATE)
+            .context("Response has no Date header")?
+            .to_str()
+            .context("Invalid Date header")?;
+        let server_time = httpdate::parse_http_date(date)
+            .context("Invalid Date header")?
+            .duration_since(std::time::UNIX_EPOCH)?
+            .as_secs_f64();
 
-        // 7. Wait for processing (max 60 attempts, 2 seconds each)
-        let processed = self
-            .wait_for_file_processing(&file_id, 60, Duration::from_secs(2))
-            .await?;
+        // Assume the server stamped the response halfway through the round trip
+        let sent = sent.duration_since(std::time::UNIX_EPOCH)?.as_secs_f64();
+        let received = received
+            .duration_since(std::time::UNIX_EPOCH)?
+            .as_secs_f64();
+        let local_time = f64::midpoint(sent, received);
+        *self
+            .clock_skew
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner) = Some(server_time - local_time);
 
-        Ok(processed)
+        Ok(server_time)
     }
 
-    /// Fetches information about a file by its ID.
+    /// Opens a connection to the server ahead of the first real request, so that
+    /// DNS resolution and the TCP and TLS handshakes are off its critical path.
+    ///
+    /// Sends a lightweight `HEAD` request whose connection is kept in the client's
+    /// pool, shared with its clones, and reused by the next request. Idle pooled
+    /// connections are closed after about 90 seconds, so warm up shortly before
+    /// the connection is needed. Combine with [`prefetch_pow`](Self::prefetch_pow)
+    /// to also solve the `PoW` of the first completion in advance. See
+    /// [`DeepSeekAPIBuilder::warm_up`] to warm up when the client is built.
     ///
     /// # Errors
-    /// Returns an error if the request fails, the response indicates an error, or the file is not found.
-    pub async fn fetch_file_info(&self, file_id: &str) -> Result<models::FileInfo> {
-        use anyhow::anyhow;
-
-        // Define response structs
-        #[derive(serde::Deserialize)]
-        struct FetchResponse {
-            data: FetchData,
-        }
-        #[derive(serde::Deserialize)]
-        struct FetchData {
-            biz_data: FetchBizData,
-        }
-        #[derive(serde::Deserialize)]
-        struct FetchBizData {
-            files: Vec<models::FileInfo>,
-        }
+    /// Returns an error if the request cannot be sent.
+    pub async fn warm_up(&self) -> Result<(), DeepSeekError> {
+        self.send(self.client.head(&self.base_url)).await?;
+        Ok(())
+    }
 
-        let url = format!(
-            "https://chat.deepseek.com/api/v0/file/fetch_files?file_ids={file_id}"
-        );
-        let resp: FetchResponse = self
-            .client
-            .get(&url)
-            .send()
-            .await?
-            .error_for_status()?
-            .json()
-            .await?;
-        resp.data
-            .biz_data
-            .files
-            .into_iter()
-            .next()
-            .ok_or_else(|| anyhow!("No file found with ID {file_id}"))
+    /// Returns how many seconds the server clock is ahead of the local one (negative
+    /// if behind), as measured by the last [`get_server_time`](Self::get_server_time)
+    /// call on this client or its clones, or `None` if it was never measured.
+    ///
+    /// If it was not measured before, it is measured from the response to the
+    /// first `PoW` challenge request.
+    #[must_use]
+    pub fn skew(&self) -> Option<f64> {
+        *self
+            .clock_skew
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
     }
 
-    /// Waits for a file to finish processing (status `SUCCESS`).
+    /// Sends `request` once through the configured middleware, bounding it as set
+    /// with [`DeepSeekAPIBuilder::request_timeout`].
     ///
-    /// # Arguments
-    /// * `file_id` - The file ID.
-    /// * `max_attempts` - Maximum number of polling attempts.
-    /// * `delay` - Delay between attempts (e.g., `std::time::Duration::from_millis(500)`).
+    /// A response with status 401 is turned into [`DeepSeekError::Unauthorized`]
+    /// and one with status 429 into [`DeepSeekError::RateLimited`].
+    async fn send(&self, request: reqwest::RequestBuilder) -> Result<reqwest::Response> {
+        check_status(self.dispatch(self.timed(request)).await?)
+    }
+
+    /// Builds a request with `build` and sends it like [`send`](Self::send), but
+    /// builds and sends it again after transient failures as set with `retry`.
     ///
-    /// # Errors
-    /// Returns an error if the file status becomes `ERROR`, or if the maximum attempts are exceeded.
-    pub async fn wait_for_file_processing(
+    /// The request is built anew for each attempt, so that each attempt solves a
+    /// fresh `PoW` challenge. `build` also returns a value that is returned with
+    /// the response of the last attempt, and must apply the request timeout
+    /// itself where one is wanted. Only requests that are safe to repeat are sent
+    /// this way: `PoW` challenge, chat creation, completion and upload requests.
+    async fn send_retrying<T, F, Fut>(
         &self,
-        file_id: &str,
-        max_attempts: usize,
-        delay: std::time::Duration,
-    ) -> Result<models::FileInfo> {
-        for attempt in 0..max_attempts {
-            let info = self.fetch_file_info(file_id).await?;
-            match info.status.as_str() {
-                "SUCCESS" => return Ok(info),
-                "ERROR" => anyhow::bail!("File processing error: {:?}", info.error_code),
-                _ => {
-                    if attempt == max_attempts - 1 {
-                        anyhow::bail!("File processing timed out after {max_attempts} attempts");
-                    }
-                    tokio::time::sleep(delay).await;
-                }
-            }
+        retry: RetryPolicy,
+        mut build: F,
+    ) -> Result<(reqwest::Response, T)>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<(reqwest::RequestBuilder, T)>>,
+    {
+        let mut attempt = 1;
+        loop {
+            let (request, value) = build().await?;
+            let result = self.dispatch(request).await;
+            let delay = retry
+                .delay(attempt)
+                .and_then(|backoff| retry.retry_delay(&result, backoff));
+            let Some(delay) = delay else {
+                return Ok((check_status(result?)?, value));
+            };
+            tracing::warn!(
+                attempt,
+                delay_ms = delay.as_millis(),
+                status = ?result.as_ref().ok().map(reqwest::Response::status),
+                "Transient failure, retrying request"
+            );
+            tokio::time::sleep(delay).await;
+            attempt += 1;
         }
-        unreachable!()
     }
-}
-
-/// Represents a chunk from the streaming response.
-#[derive(Debug)]
-pub enum StreamChunk {
-    Content(String),
-    Thinking(String),
-    Message(models::Message),
-}
 
-impl Clone for DeepSeekAPI {
-    fn clone(&self) -> Self {
-        Self {
-            client: self.client.clone(),
-            pow_solver: Arc::clone(&self.pow_solver),
-            token: self.token.clone(),
+    /// Bounds `request` as set with [`DeepSeekAPIBuilder::request_timeout`].
+    fn timed(&self, request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        match self.request_timeout {
+            Some(timeout) => request.timeout(timeout),
+            None => request,
         }
     }
-}
-
-struct SseParser {
-    builder: crate::models::StreamingMessageBuilder,
-    current_property: Option<String>,
-    toast_error: Option<String>,
-}
 
-impl SseParser {
-    fn new() -> Self {
-        Self {
-            builder: crate::models::StreamingMessageBuilder::default(),
-            current_property: None,
-            toast_error: None,
-        }
+    /// Sends `request` through the configured middleware and returns the response
+    /// as received.
+    async fn dispatch(&self, request: reqwest::RequestBuilder) -> Result<reqwest::Response> {
+        let authorization = self
+            .authorization
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .clone();
+        middleware::Next::new(
+            self.client.clone(),
+            Arc::clone(&self.middleware),
+            authorization,
+        )
+        .run(request.build()?)
+        .await
     }
 
-    fn process_data_line(&mut self, data_json: &[u8]) -> Result<Option<StreamChunk>> {
-        // Check for error type first
-        if let Ok(val) = serde_json::from_slice::<serde_json::Value>(data_json)
-            && val.get("type").and_then(|t| t.as_str()) == Some("error")
-            && let Some(content) = val.get("content").and_then(|c| c.as_str())
-        {
-            return Err(anyhow::anyhow!("API error: {content}"));
-        }
+    /// Obtains a new token from the callback set with
+    /// [`DeepSeekAPIBuilder::token_refresh`] and uses it for all later requests.
+    async fn refresh_token(&self) -> Result<()> {
+        let Some(refresh) = &self.token_refresh else {
+            anyhow::bail!("No token refresh callback is configured");
+        };
+        let token = Token::from(refresh().await.context("Token refresh failed")?);
+        let authorization = bearer(&token)?;
+        *self
+            .authorization
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner) = authorization;
+        *self
+            .token
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner) = token;
+        Ok(())
+    }
 
-        let data: crate::models::StreamingUpdate = serde_json::from_slice(data_json)?;
-        // Handle case where the entire data is a plain JSON object (not a patch)
-        if data.v.is_none() && data.p.is_none() {
-            let full_value: serde_json::Value = serde_json::from_slice(data_json)?;
-            if full_value.get("response").is_some() {
-                self.builder = crate::models::StreamingMessageBuilder::from_value(full_value)?;
-            }
-            return Ok(None);
-        }
+    /// Returns the path of `endpoint` under the configured API version, e.g.
+    /// `/api/v0/chat/completion`. `PoW` challenges are solved for this path.
+    fn api_path(&self, endpoint: &str) -> String {
+        format!("/api/{}/{endpoint}", self.api_version)
+    }
 
-        let is_new_object = data
-            .v
-            .as_ref()
-            .is_some_and(|v| v.is_object() && data.p.as_deref().unwrap_or("").is_empty());
-        let path = data.p.clone().unwrap_or_default();
+    /// Returns the URL of `endpoint` under the configured API version.
+    fn api_url(&self, endpoint: &str) -> String {
+        format!("{}{}", self.base_url, self.api_path(endpoint))
+    }
 
-        let content_to_yield = if !is_new_object && !path.is_empty() {
-            if path == "response/content" {
-                data.v
-                    .as_ref()
-                    .and_then(|v| v.as_str().map(|s| StreamChunk::Content(s.to_string())))
-            } else if path == "response/thinking_content" {
-                data.v
-                    .as_ref()
-                    .and_then(|v| v.as_str().map(|s| StreamChunk::Thinking(s.to_string())))
-            } else {
-                None
+    /// Sets the `PoW` header on `request` by solving a challenge for the given
+    /// target path.
+    ///
+    /// When `PoW` is disabled no challenge is requested, and the header is only set
+    /// if a fixed value was configured.
+    async fn set_pow_header(
+        &self,
+        request: reqwest::RequestBuilder,
+        target_path: &str,
+        request_id: &str,
+    ) -> Result<reqwest::RequestBuilder> {
+        let Some(solver) = &self.pow_solver else {
+            return Ok(match &self.pow_response {
+                Some(pow_response) => request.header("x-ds-pow-response", pow_response),
+                None => request,
+            });
+        };
+        let pow_response = match self.take_prefetched_pow(target_path) {
+            Some(pow_response) => pow_response,
+            None => {
+                self.solve_pow(solver, target_path, request_id)
+                    .await?
+                    .header
             }
-        } else {
-            None
         };
+        Ok(request.header("x-ds-pow-response", pow_response))
+    }
 
-        if is_new_object {
-            if let Some(v) = data.v.as_ref()
-                && v.get("response").is_some()
-            {
-                self.builder = crate::models::StreamingMessageBuilder::from_value(v.clone())?;
+    /// Solves `count` `PoW` challenges for `target_path` ahead of time, so that the
+    /// next `count` requests to it skip solving.
+    ///
+    /// `target_path` is the full request path, e.g. `/api/v0/chat/completion`.
+    /// The solutions are added to a pool shared by clones of this client, and each
+    /// is used by one request; they are also returned as `x-ds-pow-response` header
+    /// values. Challenges expire at their `expire_at` time, usually a few minutes
+    /// after they are issued: expired solutions are discarded from the pool and the
+    /// request solves a fresh challenge instead, so only prefetch right before a
+    /// burst.
+    ///
+    /// # Errors
+    /// Returns an error if `PoW` is disabled, or if requesting or solving a
+    /// challenge fails; solutions obtained before the failure stay in the pool.
+    pub async fn prefetch_pow(
+        &self,
+        target_path: &str,
+        count: usize,
+    ) -> Result<Vec<String>, DeepSeekError> {
+        let Some(solver) = &self.pow_solver else {
+            return Err(anyhow::anyhow!("PoW is disabled for this client").into());
+        };
+        let mut headers = Vec::with_capacity(count);
+        for _ in 0..count {
+            let pow = self
+                .solve_pow(solver, target_path, &new_request_id())
+                .await?;
+            headers.push(pow.header.clone());
+            self.pow_pool
+                .lock()
+                .unwrap_or_else(std::sync::PoisonError::into_inner)
+                .entry(target_path.to_string())
+                .or_default()
+                .push_back(pow);
+        }
+        Ok(headers)
+    }
+
+    /// Takes an unexpired prefetched solution for `target_path`, dropping expired
+    /// ones.
+    fn take_prefetched_pow(&self, target_path: &str) -> Option<String> {
+        let skew = self.skew().unwrap_or_default();
+        let mut pool = self
+            .pow_pool
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        let solutions = pool.get_mut(target_path)?;
+        while let Some(solved) = solutions.pop_front() {
+            if !pow_solver::is_expired(solved.expire_at, skew) {
+                return Some(solved.header);
             }
-            return Ok(None);
         }
+        None
+    }
 
-        if path.is_empty() {
-            if let Some(ref cur) = self.current_property {
-                let continuation_content = if cur == "response/content" {
-                    data.v
-                        .as_ref()
-                        .and_then(|v| v.as_str().map(|s| StreamChunk::Content(s.to_string())))
-                } else if cur == "response/thinking_content" {
-                    data.v
-                        .as_ref()
-                        .and_then(|v| v.as_str().map(|s| StreamChunk::Thinking(s.to_string())))
-                } else {
-                    None
-                };
-                let mut update = data.clone();
-                update.p = Some(cur.clone());
-                update.o = Some("APPEND".to_string());
-                self.builder.apply_update(&update)?;
-                if let Some(chunk) = continuation_content {
-                    return Ok(Some(chunk));
+    /// Requests a `PoW` challenge for `target_path` and solves it with `solver`.
+    ///
+    /// A challenge that is about to expire when a solver is available is not
+    /// solved; a fresh one is requested once instead.
+    async fn solve_pow(
+        &self,
+        solver: &pow_solver::SolverPool,
+        target_path: &str,
+        request_id: &str,
+    ) -> Result<SolvedPow> {
+        let mut retried = false;
+        loop {
+            let challenge = self.fetch_challenge(target_path, request_id).await?;
+            let expire_at = challenge.expire_at;
+            let skew = self.skew().unwrap_or_default();
+            // Solving is CPU-bound and may take a while: keep it off the async runtime
+            let mut solver = solver.acquire().await;
+            let span = tracing::Span::current();
+            let header = tokio::task::spawn_blocking(move || {
+                span.in_scope(|| solver.solve_challenge(challenge, skew))
+            })
+            .await
+            .context("PoW solver task failed")?;
+            match header {
+                Ok(header) => return Ok(SolvedPow { header, expire_at }),
+                Err(DeepSeekError::PowChallengeExpired { .. }) if !retried => {
+                    tracing::warn!(
+                        request_id,
+                        expire_at,
+                        skew,
+                        "PoW challenge expired before solving, requesting a new one"
+                    );
+                    retried = true;
                 }
-            }
-        } else {
-            self.current_property = Some(path.clone());
-            self.builder.apply_update(&data)?;
-            if let Some(chunk) = content_to_yield {
-                return Ok(Some(chunk));
+                Err(e) => return Err(e.into()),
             }
         }
-        Ok(None)
     }
 
-    fn finish(self) -> Result<models::Message> {
-        if let Some(err) = self.toast_error {
-            anyhow::bail!("API error: {err}");
+    /// Requests a `PoW` challenge for `target_path`.
+    ///
+    /// The request carries `request_id` so it can be correlated with the request
+    /// the solution is used for. Unless the clock skew is known, it is recorded
+    /// from the response.
+    async fn fetch_challenge(&self, target_path: &str, request_id: &str) -> Result<Challenge> {
+        #[derive(serde::Deserialize)]
+        struct PowChallengeResponse {
+            data: PowChallengeData,
         }
-        self.builder.build()
-    }
-}
+        #[derive(serde::Deserialize)]
+        struct PowChallengeData {
+            biz_data: PowChallengeBizData,
+        }
+        #[derive(serde::Deserialize)]
+        struct PowChallengeBizData {
+            challenge: Challenge,
+        }
+        let request_body = &serde_json::json!({ "target_path": target_path });
+        let (challenge_response, sent) = self
+            .send_retrying(self.retry, move || async move {
+                let request = self
+                    .client
+                    .post(self.api_url("chat/create_pow_challenge"))
+                    .header(REQUEST_ID_HEADER, request_id)
+                    .json(request_body);
+                Ok((self.timed(request), std::time::SystemTime::now()))
+            })
+            .await?;
+        let challenge_response = challenge_response.error_for_status()?;
+        if self.skew().is_none()
+            && let Err(e) = self.record_server_time(&challenge_response, sent)
+        {
+            tracing::debug!(error = %format!("{e:#}"), "Could not determine clock skew");
+        }
+        let challenge_response_text = challenge_response.text().await?;
 
-// Helper to turn an HTTP response into a stream of chunks.
-fn response_to_chunk_stream(
-    response: reqwest::Response,
-) -> impl futures_util::Stream<Item = Result<StreamChunk>> {
-    use async_stream::stream;
-    stream! {
-        let mut parser = SseParser::new();
-        let mut buffer = bytes::BytesMut::new();
+        let challenge_response: PowChallengeResponse =
+            serde_json::from_str(&challenge_response_text)?;
 
-        let mut bytes = response.bytes_stream();
-        while let Some(chunk) = bytes.next().await {
-            let chunk = match chunk {
-                Ok(c) => c,
-                Err(e) => {
-                    yield Err(e.into());
-                    return;
-                }
-            };
-            buffer.extend_from_slice(&chunk);
-            while let Some(pos) = buffer.iter().position(|&b| b == b'\n') {
-                let line = buffer.split_to(pos);
-                buffer.advance(1); // consume newline
-                if line.is_empty() {
-                    continue;
-                }
-                if line == b"event: finish"[..] {
-                    match parser.finish() {
-                        Ok(final_msg) => {
-                            yield Ok(StreamChunk::Message(final_msg));
-                            return;
-                        }
-                        Err(e) => {
-                            yield Err(e);
-                            return;
-                        }
-                    }
-                }
-                if line == b"event: toast"[..] {
-                    // According to the protocol, a toast event precedes a data line with error info.
-                    // We'll just skip it; the data line will be handled in the next iteration.
+        Ok(challenge_response.data.biz_data.challenge)
+    }
+
+    /// Completes a chat message (non‑streaming).
+    ///
+    /// This method internally uses the streaming version (`complete_stream`) and
+    /// collects all chunks, automatically handling any necessary continuations.
+    /// With thinking, the message's `thinking_content` holds all the reasoning that
+    /// was streamed, even where a later update of the message replaced it.
+    ///
+    /// # Errors
+    /// Returns an error if:
+    /// - The Proof‑of‑Work challenge cannot be solved.
+    /// - The API request fails or returns an error status.
+    /// - The response cannot be parsed into a `Message`.
+    ///
+    /// [`CompletionRequest`] sets the same parameters by name.
+    pub async fn complete(
+        &self,
+        chat_id: &str,
+        prompt: &str,
+        parent_message_id: Option<i64>,
+        search: bool,
+        thinking: bool,
+        ref_file_ids: Vec<String>,
+    ) -> Result<models::Message, DeepSeekError> {
+        completion(
+            chat_id,
+            prompt,
+            parent_message_id,
+            search,
+            thinking,
+            ref_file_ids,
+        )
+        .send(self)
+        .await
+    }
+
+    /// Completes a chat message (non‑streaming), returning the final message along
+    /// with details about how it was obtained.
+    ///
+    /// # Errors
+    /// Returns an error under the same conditions as [`complete`](Self::complete).
+    pub async fn complete_detailed(
+        &self,
+        chat_id: &str,
+        prompt: &str,
+        parent_message_id: Option<i64>,
+        search: bool,
+        thinking: bool,
+        ref_file_ids: Vec<String>,
+    ) -> Result<CompletionResult, DeepSeekError> {
+        completion(
+            chat_id,
+            prompt,
+            parent_message_id,
+            search,
+            thinking,
+            ref_file_ids,
+        )
+        .send_detailed(self)
+        .await
+    }
+
+    /// Runs a completion to its final message, retrying it while the message has
+    /// no content, up to the number of times set with
+    /// [`DeepSeekAPIBuilder::retry_empty_response`].
+    async fn collect_completion(
+        &self,
+        chat_id: &str,
+        prompt: &str,
+        parent_message_id: Option<i64>,
+        options: CompletionOptions,
+    ) -> Result<CompletionResult> {
+        let mut retries = 0;
+        loop {
+            let mut result = Box::pin(collect_result(self.complete_events_with(
+                chat_id.to_string(),
+                prompt.to_string(),
+                parent_message_id,
+                options.clone(),
+            )))
+            .await?;
+            if !result.message.content.is_empty() || retries >= self.empty_response_retries {
+                result.retries = retries;
+                return Ok(result);
+            }
+            retries += 1;
+            tracing::warn!(
+                chat_id,
+                retries,
+                "Completion finished without content, retrying"
+            );
+        }
+    }
+
+    /// Like [`complete_once`](Self::complete_once), but continues incomplete
+    /// messages and also returns a [`LatencyProfile`] of where the time was spent.
+    ///
+    /// Thinking is never retried without thinking, even with
+    /// [`DeepSeekAPIBuilder::thinking_fallback`], so that the profile describes a
+    /// single initial request.
+    ///
+    /// # Errors
+    /// Returns an error as described for [`complete`](Self::complete).
+    pub async fn complete_profiled(
+        &self,
+        chat_id: &str,
+        prompt: &str,
+        parent_message_id: Option<i64>,
+        mut options: CompletionOptions,
+    ) -> Result<(models::Message, LatencyProfile), DeepSeekError> {
+        use async_stream::stream;
+
+        let started = std::time::Instant::now();
+        let max_continuations = self.continuation_limit(&options);
+        let headers = std::mem::take(&mut options.headers);
+        let stop_sequences = std::mem::take(&mut options.stop_sequences);
+        let request = completion_request(chat_id, prompt.to_string(), parent_message_id, options);
+        let path = self.api_path(COMPLETION_ENDPOINT);
+        let request_id = new_request_id();
+        let permit = self.acquire_permit().await;
+        let (response, pow) = self
+            .send_stream_request_timed(&path, &request, &request_id, &headers)
+            .await?;
+        let first_byte = started.elapsed();
+        let initial = stream! {
+            // Held until the response has been read, as in `request_stream`
+            let _permit = permit;
+            let mut events = Box::pin(self.response_events(response, None));
+            while let Some(event) = events.next().await {
+                yield event.map_err(|e| with_request_context(e, &request_id, &path));
+            }
+        };
+        let events = self.auto_continue(
+            chat_id.to_string(),
+            initial,
+            None,
+            None,
+            headers,
+            max_continuations,
+        );
+        let events = self.stop_at(chat_id.to_string(), events, stop_sequences);
+        let mut events = std::pin::pin!(events);
+        let mut first_content = None;
+        let mut continuations = 0;
+        while let Some(event) = events.next().await {
+            match event? {
+                CompletionEvent::Content(_) | CompletionEvent::Thinking(_) => {
+                    first_content.get_or_insert_with(|| started.elapsed());
+                }
+                CompletionEvent::ContinuationStarted { .. } => continuations += 1,
+                CompletionEvent::Finished(message) => {
+                    let profile = LatencyProfile {
+                        pow,
+                        first_byte,
+                        first_content,
+                        total: started.elapsed(),
+                        continuations,
+                    };
+                    return Ok((message, profile));
+                }
+                _ => (),
+            }
+        }
+        Err(anyhow::anyhow!("No final message received").into())
+    }
+
+    /// Completes a prompt in `chat_id`, or in a newly created chat session if it
+    /// is `None`, returning the id of the chat used and the final message.
+    ///
+    /// The prompt starts a new thread in the chat, as with a `parent_message_id`
+    /// of `None` in [`complete`](Self::complete).
+    ///
+    /// # Errors
+    /// Returns an error if the chat cannot be created, or as described for
+    /// [`complete`](Self::complete).
+    pub async fn complete_or_create(
+        &self,
+        chat_id: Option<&str>,
+        prompt: &str,
+        options: CompletionOptions,
+    ) -> Result<(String, models::Message), DeepSeekError> {
+        let chat_id = match chat_id {
+            Some(chat_id) => chat_id.to_string(),
+            None => self.create_chat().await?.id,
+        };
+        let result = self
+            .collect_completion(&chat_id, prompt, None, options)
+            .await?;
+        Ok((chat_id, result.message))
+    }
+
+    /// Issues exactly one completion request and returns its final message.
+    ///
+    /// Unlike [`complete`](Self::complete), no continuation is requested when the
+    /// message is incomplete (its `status` is then [`models::MessageStatus::Incomplete`]), and thinking is
+    /// never retried without thinking, even with
+    /// [`DeepSeekAPIBuilder::thinking_fallback`].
+    ///
+    /// # Errors
+    /// Returns an error as described for [`complete`](Self::complete), or if the
+    /// response ends without a final message.
+    pub async fn complete_once(
+        &self,
+        chat_id: &str,
+        prompt: &str,
+        parent_message_id: Option<i64>,
+        mut options: CompletionOptions,
+    ) -> Result<models::Message, DeepSeekError> {
+        let headers = std::mem::take(&mut options.headers);
+        let stop_sequences = std::mem::take(&mut options.stop_sequences);
+        let request = completion_request(chat_id, prompt.to_string(), parent_message_id, options);
+        let stream = self.request_stream(COMPLETION_ENDPOINT, request, headers, None);
+        let stream = self.stop_at(chat_id.to_string(), stream, stop_sequences);
+        let mut stream = std::pin::pin!(stream);
+        while let Some(event) = stream.next().await {
+            if let CompletionEvent::Finished(message) = event? {
+                return Ok(message);
+            }
+        }
+        Err(anyhow::anyhow!("No final message received").into())
+    }
+
+    /// Completes a chat message (streaming), yielding chunks of content or thinking.
+    ///
+    /// This method automatically continues the generation if the response is incomplete,
+    /// transparently issuing continuation requests until a complete message is obtained.
+    /// Use [`CompletionRequest::auto_continue`] to get the incomplete message instead.
+    ///
+    /// If thinking is requested but rejected by the server and the client was built
+    /// with [`DeepSeekAPIBuilder::thinking_fallback`], the request is retried once
+    /// without thinking and a [`StreamChunk::Warning`] is yielded first.
+    ///
+    /// # Errors
+    /// Each yielded `Result` may contain an error if:
+    /// - The Proof‑of‑Work challenge cannot be solved.
+    /// - The API request fails.
+    /// - The streaming response cannot be parsed.
+    /// - The server rejects a requested feature
+    ///   ([`DeepSeekError::UnsupportedFeature`]).
+    ///
+    /// [`CompletionRequest`] sets the same parameters by name.
+    pub fn complete_stream(
+        &self,
+        chat_id: String,
+        prompt: String,
+        parent_message_id: Option<i64>,
+        search: bool,
+        thinking: bool,
+        ref_file_ids: Vec<String>,
+    ) -> impl futures_util::Stream<Item = Result<StreamChunk, DeepSeekError>> + '_ {
+        completion(
+            chat_id,
+            prompt,
+            parent_message_id,
+            search,
+            thinking,
+            ref_file_ids,
+        )
+        .stream(self)
+    }
+
+    /// Starts a new branch of the conversation from `parent_message_id` (streaming).
+    ///
+    /// `DeepSeek` threads messages by `parent_id`: sending a prompt with a parent
+    /// other than the session's `current_message_id` creates a sibling of that
+    /// parent's existing replies instead of extending the latest one. Afterwards the
+    /// session's `current_message_id` points at the new branch and its `version`
+    /// is incremented, so later calls with `parent_message_id: None` continue
+    /// from the branch. The branches of a message are the messages in the chat
+    /// history sharing it as their `parent_id`.
+    ///
+    /// # Errors
+    /// Each yielded `Result` may contain an error as described for
+    /// [`complete_stream`](Self::complete_stream).
+    pub fn branch_from(
+        &self,
+        chat_id: String,
+        parent_message_id: i64,
+        prompt: String,
+        options: CompletionOptions,
+    ) -> impl futures_util::Stream<Item = Result<StreamChunk, DeepSeekError>> + '_ {
+        into_chunks(
+            self.complete_events_with(chat_id, prompt, Some(parent_message_id), options),
+            self.detect_images,
+        )
+    }
+
+    /// Like [`complete_stream`](Self::complete_stream), but also returns an
+    /// [`AbortHandle`] that stops the stream.
+    ///
+    /// Calling [`AbortHandle::abort`] ends the stream without a final message and
+    /// sends a best‑effort request asking the server to stop generating, see
+    /// [`stop_generation`](Self::stop_generation). Dropping the stream instead
+    /// stops reading locally but lets the server keep generating.
+    /// The stream is also aborted by [`cancel_all`](Self::cancel_all).
+    ///
+    /// # Errors
+    /// Each yielded `Result` may contain an error as described for
+    /// [`complete_stream`](Self::complete_stream).
+    pub fn complete_stream_with_handle(
+        &self,
+        chat_id: String,
+        prompt: String,
+        parent_message_id: Option<i64>,
+        search: bool,
+        thinking: bool,
+        ref_file_ids: Vec<String>,
+    ) -> (
+        AbortHandle,
+        impl futures_util::Stream<Item = Result<StreamChunk, DeepSeekError>> + '_,
+    ) {
+        let token = self
+            .stream_cancellation
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .child_token();
+        let events = self.complete_events_with(
+            chat_id.clone(),
+            prompt,
+            parent_message_id,
+            CompletionOptions {
+                search,
+                thinking,
+                ref_file_ids,
+                ..CompletionOptions::default()
+            },
+        );
+        let stream = into_chunks(
+            self.cancellable(chat_id, events, token.clone()),
+            self.detect_images,
+        );
+        (AbortHandle { token }, stream)
+    }
+
+    /// Aborts every stream started with
+    /// [`complete_stream_with_handle`](Self::complete_stream_with_handle) on this
+    /// client or its clones, as if [`AbortHandle::abort`] was called on each.
+    ///
+    /// Streams from the other methods cannot be aborted and are not affected, nor
+    /// are streams started after this call.
+    pub fn cancel_all(&self) {
+        let active = std::mem::take(
+            &mut *self
+                .stream_cancellation
+                .lock()
+                .unwrap_or_else(std::sync::PoisonError::into_inner),
+        );
+        active.cancel();
+    }
+
+    /// Like [`complete_stream`](Self::complete_stream), but also returns a future
+    /// resolving to the final message.
+    ///
+    /// The future does not drive the completion itself: it only resolves once the
+    /// returned stream has been polled to its end, so the stream must be consumed
+    /// (e.g. on another task) for the future to complete.
+    ///
+    /// # Errors
+    /// Each yielded `Result` may contain an error as described for
+    /// [`complete_stream`](Self::complete_stream). The future resolves to the
+    /// same error if the stream fails, or to an error if the stream is dropped
+    /// before the final message.
+    pub fn complete_split(
+        &self,
+        chat_id: String,
+        prompt: String,
+        parent_message_id: Option<i64>,
+        search: bool,
+        thinking: bool,
+        ref_file_ids: Vec<String>,
+    ) -> (
+        impl futures_util::Stream<Item = Result<StreamChunk, DeepSeekError>> + '_,
+        impl Future<Output = Result<models::Message, DeepSeekError>> + use<>,
+    ) {
+        use async_stream::stream;
+
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        let chunks = self.complete_stream(
+            chat_id,
+            prompt,
+            parent_message_id,
+            search,
+            thinking,
+            ref_file_ids,
+        );
+        let stream = stream! {
+            let mut sender = Some(sender);
+            let mut chunks = Box::pin(chunks);
+            while let Some(chunk) = chunks.next().await {
+                match &chunk {
+                    Ok(StreamChunk::Message(message)) => {
+                        if let Some(sender) = sender.take() {
+                            let _ = sender.send(Ok(message.clone()));
+                        }
+                    }
+                    Err(e) => {
+                        if let Some(sender) = sender.take() {
+                            let _ = sender.send(Err(e.clone()));
+                        }
+                    }
+                    Ok(_) => (),
+                }
+                yield chunk;
+            }
+        };
+        let message = async move {
+            receiver.await.unwrap_or_else(|_| {
+                Err(anyhow::anyhow!("Completion stream ended before the final message").into())
+            })
+        };
+        (stream, message)
+    }
+
+    /// Completes a chat message, streaming thinking and content separately.
+    ///
+    /// Returns `(thinking, content)`: [`StreamChunk::Thinking`] text is routed to
+    /// the first stream and [`StreamChunk::Content`] text to the second; other
+    /// chunks are dropped. Ordering is preserved within each stream, and both
+    /// end when generation finishes.
+    ///
+    /// The completion runs on a spawned task, so the streams can be consumed
+    /// independently; text is buffered until its stream is polled. Generation
+    /// stops once both streams are dropped.
+    ///
+    /// # Errors
+    /// Each yielded `Result` may contain an error as described for
+    /// [`complete_stream`](Self::complete_stream). An error ends both streams, and
+    /// each yields it.
+    pub fn complete_dual_stream(
+        &self,
+        chat_id: String,
+        prompt: String,
+        parent_message_id: Option<i64>,
+        search: bool,
+        thinking: bool,
+        ref_file_ids: Vec<String>,
+    ) -> (
+        impl futures_util::Stream<Item = Result<String, DeepSeekError>> + use<>,
+        impl futures_util::Stream<Item = Result<String, DeepSeekError>> + use<>,
+    ) {
+        use async_stream::stream;
+
+        let (thinking_tx, mut thinking_rx) = tokio::sync::mpsc::unbounded_channel();
+        let (content_tx, mut content_rx) = tokio::sync::mpsc::unbounded_channel();
+        let api = self.clone();
+        tokio::spawn(async move {
+            let chunks = api.complete_stream(
+                chat_id,
+                prompt,
+                parent_message_id,
+                search,
+                thinking,
+                ref_file_ids,
+            );
+            let mut chunks = std::pin::pin!(chunks);
+            while let Some(chunk) = chunks.next().await {
+                match chunk {
+                    Ok(StreamChunk::Thinking(text)) => {
+                        let _ = thinking_tx.send(Ok(text));
+                    }
+                    Ok(StreamChunk::Content(text)) => {
+                        let _ = content_tx.send(Ok(text));
+                    }
+                    Ok(_) => (),
+                    Err(e) => {
+                        let _ = thinking_tx.send(Err(e.clone()));
+                        let _ = content_tx.send(Err(e));
+                        break;
+                    }
+                }
+                if thinking_tx.is_closed() && content_tx.is_closed() {
+                    // Both consumers dropped their streams
+                    break;
+                }
+            }
+        });
+        let thinking = stream! {
+            while let Some(text) = thinking_rx.recv().await {
+                yield text;
+            }
+        };
+        let content = stream! {
+            while let Some(text) = content_rx.recv().await {
+                yield text;
+            }
+        };
+        (thinking, content)
+    }
+
+    /// Like [`complete_stream`](Self::complete_stream), but sends the initial request
+    /// up front and returns its HTTP metadata before any chunk is consumed.
+    ///
+    /// The metadata describes the initial completion request only; continuation
+    /// requests issued by the returned stream are not reflected in it.
+    ///
+    /// # Errors
+    /// Returns an error if the Proof‑of‑Work challenge cannot be solved or the
+    /// initial request fails. Each yielded `Result` may contain an error as
+    /// described for [`complete_stream`](Self::complete_stream).
+    pub async fn complete_stream_with_meta(
+        &self,
+        chat_id: String,
+        prompt: String,
+        parent_message_id: Option<i64>,
+        search: bool,
+        thinking: bool,
+        ref_file_ids: Vec<String>,
+    ) -> Result<
+        (
+            ResponseMeta,
+            impl futures_util::Stream<Item = Result<StreamChunk, DeepSeekError>> + '_,
+        ),
+        DeepSeekError,
+    > {
+        let mut request = completion_request(
+            &chat_id,
+            prompt,
+            parent_message_id,
+            CompletionOptions {
+                search,
+                thinking,
+                ref_file_ids,
+                ..CompletionOptions::default()
+            },
+        );
+        let mut fallback_request = (thinking && self.thinking_fallback).then(|| request.clone());
+        let mut warning = None;
+        let permit = self.acquire_permit().await;
+        let path = self.api_path(COMPLETION_ENDPOINT);
+        let mut request_id = new_request_id();
+        let response = match self
+            .send_stream_request(&path, &request, &request_id, &header::HeaderMap::new())
+            .await
+        {
+            Ok(response) => response,
+            Err(e) if fallback_request.is_some() && thinking_rejection(&e).is_some() => {
+                // Retry once without thinking, as `complete_stream` would
+                fallback_request = None;
+                request["thinking_enabled"] = json!(false);
+                warning =
+                    thinking_rejection(&e).map(|rejection| CompletionWarning::ThinkingDisabled {
+                        reason: rejection.to_string(),
+                    });
+                request_id = new_request_id();
+                self.send_stream_request(&path, &request, &request_id, &header::HeaderMap::new())
+                    .await?
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let meta = ResponseMeta::from_response(&response, &request_id);
+        let initial = async_stream::stream! {
+            // Held until the response has been read, as in `request_stream`
+            let _permit = permit;
+            let mut events = Box::pin(self.response_events(response, None));
+            while let Some(event) = events.next().await {
+                yield event.map_err(|e| with_request_context(e, &request_id, &path));
+            }
+        };
+        Ok((
+            meta,
+            into_chunks(
+                self.auto_continue(
+                    chat_id,
+                    initial,
+                    fallback_request,
+                    warning,
+                    header::HeaderMap::new(),
+                    self.max_continuations,
+                ),
+                self.detect_images,
+            ),
+        ))
+    }
+
+    /// Completes a chat message (streaming), yielding the full lifecycle of the
+    /// completion as [`CompletionEvent`]s.
+    ///
+    /// This is a richer alternative to [`complete_stream`](Self::complete_stream):
+    /// besides content and thinking it reports the message id as soon as it is
+    /// known, search results, token usage and continuation requests. The stream
+    /// ends with [`CompletionEvent::Finished`].
+    ///
+    /// # Errors
+    /// Each yielded `Result` may contain an error as described for
+    /// [`complete_stream`](Self::complete_stream).
+    pub fn complete_events(
+        &self,
+        chat_id: String,
+        prompt: String,
+        parent_message_id: Option<i64>,
+        search: bool,
+        thinking: bool,
+        ref_file_ids: Vec<String>,
+    ) -> impl futures_util::Stream<Item = Result<CompletionEvent, DeepSeekError>> + '_ {
+        typed_errors(self.complete_events_with(
+            chat_id,
+            prompt,
+            parent_message_id,
+            CompletionOptions {
+                search,
+                thinking,
+                ref_file_ids,
+                ..CompletionOptions::default()
+            },
+        ))
+    }
+
+    /// Sends a completion request and yields its event stream as sent by the
+    /// server, without interpreting it, e.g. to inspect changes to the protocol.
+    ///
+    /// Each `data:` line is yielded with the name of the event it belongs to, and
+    /// an `event:` line without data with empty `data`; other lines are skipped.
+    /// The stream ends with the response. Unlike
+    /// [`complete_stream`](Self::complete_stream), incomplete messages are not
+    /// continued, thinking is not retried without it, stop sequences are not
+    /// applied and errors reported in the stream are passed through as data. The
+    /// configured heartbeat timeout still applies.
+    ///
+    /// # Errors
+    /// Each yielded `Result` may contain an error if the
+    /// Proof‑of‑Work challenge cannot be solved, the API request fails, or the
+    /// connection fails or stalls.
+    pub fn complete_raw_stream(
+        &self,
+        chat_id: String,
+        prompt: String,
+        parent_message_id: Option<i64>,
+        mut options: CompletionOptions,
+    ) -> impl futures_util::Stream<Item = Result<RawSseEvent, DeepSeekError>> + '_ {
+        use async_stream::stream;
+
+        typed_errors(stream! {
+            let headers = std::mem::take(&mut options.headers);
+            let request = completion_request(&chat_id, prompt, parent_message_id, options);
+            // Held until the response has been read
+            let _permit = self.acquire_permit().await;
+            let path = self.api_path(COMPLETION_ENDPOINT);
+            let request_id = new_request_id();
+            let response = match self.send_stream_request(&path, &request, &request_id, &headers).await {
+                Ok(response) => response,
+                Err(e) => {
+                    yield Err(e);
+                    return;
+                }
+            };
+            let mut events = std::pin::pin!(raw_sse_events(response_lines(response, self.heartbeat_timeout)));
+            while let Some(event) = events.next().await {
+                yield event.map_err(|e| with_request_context(e, &request_id, &path));
+            }
+        })
+    }
+
+    /// Like [`complete_events`](Self::complete_events), taking the request
+    /// settings as [`CompletionOptions`].
+    fn complete_events_with(
+        &self,
+        chat_id: String,
+        prompt: String,
+        parent_message_id: Option<i64>,
+        mut options: CompletionOptions,
+    ) -> impl futures_util::Stream<Item = Result<CompletionEvent>> + '_ {
+        let thinking = options.thinking;
+        let max_continuations = self.continuation_limit(&options);
+        let headers = std::mem::take(&mut options.headers);
+        let stop_sequences = std::mem::take(&mut options.stop_sequences);
+        let request = completion_request(&chat_id, prompt, parent_message_id, options);
+        let fallback_request = (thinking && self.thinking_fallback).then(|| request.clone());
+        let initial = self.request_stream(COMPLETION_ENDPOINT, request, headers.clone(), None);
+        let events = self.auto_continue(
+            chat_id.clone(),
+            initial,
+            fallback_request,
+            None,
+            headers,
+            max_continuations,
+        );
+        self.stop_at(chat_id, events, stop_sequences)
+    }
+
+    /// How many continuations a completion with `options` may request.
+    fn continuation_limit(&self, options: &CompletionOptions) -> u32 {
+        if options.no_continue {
+            0
+        } else {
+            options.max_continuations.unwrap_or(self.max_continuations)
+        }
+    }
+
+    /// Drives `initial` to completion, transparently issuing up to
+    /// `max_continuations` continuation requests while the final message is
+    /// incomplete. An incomplete message past that ends the stream, preceded by a
+    /// [`CompletionWarning::ContinuationLimit`].
+    ///
+    /// If `fallback_request` is set and the first event is a rejection of thinking,
+    /// it is re‑sent once with thinking disabled. A pending `warning` is yielded
+    /// before anything else. Retried and continuation requests carry `headers`. The
+    /// whole stream, continuations included, is bounded by the configured total
+    /// timeout.
+    ///
+    /// Text a continuation streams again after resuming from an earlier checkpoint
+    /// is dropped, see [`Resume`].
+    fn auto_continue<'a>(
+        &'a self,
+        chat_id: String,
+        initial: impl futures_util::Stream<Item = Result<CompletionEvent>> + 'a,
+        mut fallback_request: Option<serde_json::Value>,
+        warning: Option<CompletionWarning>,
+        headers: header::HeaderMap,
+        max_continuations: u32,
+    ) -> impl futures_util::Stream<Item = Result<CompletionEvent>> + 'a {
+        use async_stream::stream;
+        use futures_util::future::Either;
+
+        let events = stream! {
+            if let Some(warning) = warning {
+                yield Ok(CompletionEvent::Warning(warning));
+            }
+            let mut current_stream = Either::Left(Box::pin(initial));
+            let mut message_id_for_continuation: Option<i64> = None;
+            let mut resume = None;
+            // The message being continued, and how to resume it again after a token refresh
+            let mut continued = None;
+            let mut retry_continuation = None;
+            let mut continuations = 0;
+            let mut started = false;
+            let mut message_id = None;
+            let mut limiter = self.response_limit.map(|(max_bytes, action)| ResponseLimiter::new(max_bytes, action));
+
+            loop {
+                while let Some(event) = current_stream.next().await {
+                    let event = match event {
+                        Ok(event) => event,
+                        Err(mut e) => {
+                            if mark_unauthorized(&mut e, continued)
+                                && let (Some(msg_id), Some(resume)) = (continued, retry_continuation.take())
+                            {
+                                if let Err(refresh) = self.refresh_token().await {
+                                    yield Err(e.context(format!("{refresh:#}")));
+                                    return;
+                                }
+                                current_stream = Either::Right(Box::pin(self.request_stream(CONTINUE_ENDPOINT, continue_request(&chat_id, msg_id), headers.clone(), resume)));
+                                continue;
+                            }
+                            if let Some(mut request) = fallback_request.take()
+                                && let Some(rejection) = thinking_rejection(&e)
+                            {
+                                // Retry the initial request once without thinking
+                                request["thinking_enabled"] = json!(false);
+                                yield Ok(CompletionEvent::Warning(CompletionWarning::ThinkingDisabled {
+                                    reason: rejection.to_string(),
+                                }));
+                                current_stream =
+                                    Either::Right(Box::pin(self.request_stream(COMPLETION_ENDPOINT, request, headers.clone(), None)));
+                                continue;
+                            }
+                            yield Err(e);
+                            return;
+                        }
+                    };
+                    // Only a rejection of the initial request can be retried
+                    fallback_request = None;
+                    retry_continuation = None;
+                    match event {
+                        CompletionEvent::Started { .. } if started => {
+                            // Continuations announce the same message again
+                        }
+                        CompletionEvent::Started { message_id: id } => {
+                            started = true;
+                            message_id = id;
+                            yield Ok(CompletionEvent::Started { message_id: id });
+                        }
+                        CompletionEvent::Finished(msg) => {
+                            let incomplete = msg.status == Some(models::MessageStatus::Incomplete);
+                            if incomplete && continuations < max_continuations {
+                                continuations += 1;
+                                message_id_for_continuation = msg.message_id;
+                                resume = Some(Resume::new(&msg));
+                                break; // exit inner while to start continuation
+                            }
+                            if incomplete {
+                                let limit = max_continuations;
+                                yield Ok(CompletionEvent::Warning(CompletionWarning::ContinuationLimit { limit }));
+                            }
+                            yield Ok(CompletionEvent::Finished(msg));
+                            return;
+                        }
+                        event => {
+                            let Some(limiter) = &mut limiter else {
+                                yield Ok(event);
+                                continue;
+                            };
+                            match limiter.admit(event) {
+                                Admission::Within(event) => yield Ok(event),
+                                Admission::Exceeded(partial) => {
+                                    if let Some(message_id) = message_id {
+                                        // Best effort: the response is cut off either way
+                                        let _ = self.stop_generation(&chat_id, message_id).await;
+                                    }
+                                    for event in limiter.exceeded(partial, message_id) {
+                                        yield event;
+                                    }
+                                    return;
+                                }
+                            }
+                        }
+                    }
+                }
+
+                let Some(msg_id) = message_id_for_continuation.take() else {
+                    // No continuation ID – should not happen, but break to be safe
+                    break;
+                };
+                // Start continuation
+                yield Ok(CompletionEvent::ContinuationStarted { message_id: msg_id });
+                continued = Some(msg_id);
+                retry_continuation = self.token_refresh.is_some().then(|| resume.clone());
+                current_stream = Either::Right(Box::pin(self.request_stream(CONTINUE_ENDPOINT, continue_request(&chat_id, msg_id), headers.clone(), resume.take())));
+                // Loop again to process this new stream
+            }
+        };
+        with_total_timeout(events, self.total_timeout)
+    }
+
+    /// Ends `events` once `cancel` is triggered, asking the server to stop
+    /// generating the announced message.
+    fn cancellable<'a>(
+        &'a self,
+        chat_id: String,
+        events: impl futures_util::Stream<Item = Result<CompletionEvent>> + 'a,
+        cancel: CancellationToken,
+    ) -> impl futures_util::Stream<Item = Result<CompletionEvent>> + 'a {
+        use async_stream::stream;
+
+        stream! {
+            let mut events = Box::pin(events);
+            let mut message_id = None;
+            loop {
+                let event = tokio::select! {
+                    biased;
+                    () = cancel.cancelled() => {
+                        if let Some(message_id) = message_id {
+                            // Best effort: the stream ends whatever the outcome
+                            let _ = self.stop_generation(&chat_id, message_id).await;
+                        }
+                        return;
+                    }
+                    event = events.next() => event,
+                };
+                let Some(event) = event else {
+                    return;
+                };
+                if let Ok(CompletionEvent::Started { message_id: Some(id) }) = &event {
+                    message_id = Some(*id);
+                }
+                yield event;
+            }
+        }
+    }
+
+    /// Ends `events` at the first of `stop_sequences` in the content, asking the
+    /// server to stop generating, see [`CompletionOptions::stop_sequences`].
+    fn stop_at<'a>(
+        &'a self,
+        chat_id: String,
+        events: impl futures_util::Stream<Item = Result<CompletionEvent>> + 'a,
+        stop_sequences: Vec<String>,
+    ) -> impl futures_util::Stream<Item = Result<CompletionEvent>> + 'a {
+        use async_stream::stream;
+
+        stream! {
+            let mut events = Box::pin(events);
+            let mut matcher = StopMatcher::new(stop_sequences);
+            let mut message_id = None;
+            while let Some(event) = events.next().await {
+                let Some(matcher) = &mut matcher else {
+                    yield event;
+                    continue;
+                };
+                match event {
+                    Ok(CompletionEvent::Started { message_id: id }) => {
+                        message_id = id;
+                        yield Ok(CompletionEvent::Started { message_id: id });
+                    }
+                    Ok(CompletionEvent::Thinking(text)) => {
+                        matcher.thinking.push_str(&text);
+                        yield Ok(CompletionEvent::Thinking(text));
+                    }
+                    Ok(CompletionEvent::Content(text)) => {
+                        let (text, sequence) = matcher.push(&text);
+                        if !text.is_empty() {
+                            yield Ok(CompletionEvent::Content(text));
+                        }
+                        if let Some(sequence) = sequence {
+                            if let Some(message_id) = message_id {
+                                // Best effort: the response is cut off either way
+                                let _ = self.stop_generation(&chat_id, message_id).await;
+                            }
+                            yield Ok(CompletionEvent::Warning(CompletionWarning::StopSequence { sequence }));
+                            yield Ok(CompletionEvent::Finished(matcher.message(message_id)));
+                            return;
+                        }
+                    }
+                    Ok(CompletionEvent::Finished(message)) => {
+                        let held = matcher.flush();
+                        if !held.is_empty() {
+                            yield Ok(CompletionEvent::Content(held));
+                        }
+                        yield Ok(CompletionEvent::Finished(message));
+                    }
+                    event => yield event,
+                }
+            }
+        }
+    }
+
+    /// Asks the server to stop generating the message `message_id` of the chat
+    /// `chat_id`, e.g. when the user leaves while a response is streamed.
+    ///
+    /// Dropping a completion stream only stops reading it locally; the server
+    /// keeps generating until asked to stop. The message id is announced early in
+    /// the stream by [`CompletionEvent::Started`]. Streams started with
+    /// [`complete_stream_with_handle`](Self::complete_stream_with_handle) call
+    /// this when aborted. Like completion requests, the stop request carries a
+    /// `PoW` solution for its path.
+    ///
+    /// # Errors
+    /// Returns an error if the `PoW` challenge cannot be solved, the API request
+    /// fails or the response indicates an error.
+    pub async fn stop_generation(
+        &self,
+        chat_id: &str,
+        message_id: i64,
+    ) -> Result<(), DeepSeekError> {
+        #[derive(serde::Deserialize)]
+        struct StopStreamResponse {
+            code: i64,
+            msg: String,
+        }
+        let path = self.api_path("chat/stop_stream");
+        let request_id = new_request_id();
+        let request = self
+            .client
+            .post(format!("{}{path}", self.base_url))
+            .header(REQUEST_ID_HEADER, &request_id)
+            .json(&json!({
+                "chat_session_id": chat_id,
+                "message_id": message_id,
+            }));
+        let request = self.set_pow_header(request, &path, &request_id).await?;
+        let response: StopStreamResponse =
+            self.send(request).await?.error_for_status()?.json().await?;
+
+        if response.code != 0 {
+            return Err(classify_api_error(&response.msg, Some(response.code)));
+        }
+        Ok(())
+    }
+
+    /// Generates a new answer in place of the assistant message `message_id`
+    /// (streaming), reusing the prompt it answered.
+    ///
+    /// The new answer gets a new `message_id`, and its `parent_id` is the same user
+    /// message as the original's, so the two are sibling branches, see
+    /// [`branch_from`](Self::branch_from). The original answer stays in the
+    /// history, and the session's `current_message_id` moves to the new one. Like
+    /// [`complete_stream`](Self::complete_stream), the request needs a `PoW` and
+    /// incomplete answers are continued automatically.
+    ///
+    /// # Errors
+    /// Each yielded `Result` may contain an error as described for
+    /// [`complete_stream`](Self::complete_stream).
+    pub fn regenerate(
+        &self,
+        chat_id: String,
+        message_id: i64,
+    ) -> impl futures_util::Stream<Item = Result<StreamChunk, DeepSeekError>> + '_ {
+        into_chunks(
+            self.regenerate_events(chat_id, message_id),
+            self.detect_images,
+        )
+    }
+
+    /// Like [`regenerate`](Self::regenerate), but waits for the new answer and
+    /// returns it (non‑streaming).
+    ///
+    /// # Errors
+    /// Returns an error as described for [`complete`](Self::complete).
+    pub async fn regenerate_message(
+        &self,
+        chat_id: &str,
+        message_id: i64,
+    ) -> Result<models::Message, DeepSeekError> {
+        Ok(
+            collect_result(self.regenerate_events(chat_id.to_string(), message_id))
+                .await?
+                .message,
+        )
+    }
+
+    /// Streams the events of regenerating the message `message_id`, continuing
+    /// it as [`complete_events`](Self::complete_events) does.
+    fn regenerate_events(
+        &self,
+        chat_id: String,
+        message_id: i64,
+    ) -> impl futures_util::Stream<Item = Result<CompletionEvent>> + '_ {
+        let request = json!({
+            "chat_session_id": chat_id,
+            "message_id": message_id,
+        });
+        let initial =
+            self.request_stream(REGENERATE_ENDPOINT, request, header::HeaderMap::new(), None);
+        self.auto_continue(
+            chat_id,
+            initial,
+            None,
+            None,
+            header::HeaderMap::new(),
+            self.max_continuations,
+        )
+    }
+
+    /// Continues an incomplete message (streaming).
+    ///
+    /// This method is used internally by `complete_stream` for auto‑continuation,
+    /// but can also be called manually if desired.
+    ///
+    /// # Errors
+    /// Each yielded `Result` may contain an error if:
+    /// - The Proof‑of‑Work challenge cannot be solved.
+    /// - The API request fails.
+    /// - The streaming response cannot be parsed.
+    pub fn continue_stream(
+        &self,
+        chat_id: String,
+        message_id: i64,
+        fallback_to_resume: bool,
+    ) -> impl futures_util::Stream<Item = Result<StreamChunk, DeepSeekError>> + '_ {
+        let request = json!({
+            "chat_session_id": serde_json::Value::String(chat_id),
+            "message_id": message_id,
+            "fallback_to_resume": fallback_to_resume,
+        });
+        into_chunks(
+            self.request_stream(CONTINUE_ENDPOINT, request, header::HeaderMap::new(), None),
+            self.detect_images,
+        )
+    }
+
+    /// Solves a `PoW` challenge for `endpoint`, posts `request` to it with the extra
+    /// `headers` and streams the parsed response, continuing the message of
+    /// `resume` if set.
+    ///
+    /// Every error yielded carries the [`RequestContext`] of the request.
+    fn request_stream(
+        &self,
+        endpoint: &'static str,
+        request: serde_json::Value,
+        headers: header::HeaderMap,
+        resume: Option<Resume>,
+    ) -> impl futures_util::Stream<Item = Result<CompletionEvent>> + '_ {
+        use async_stream::stream;
+
+        stream! {
+            // Held until the response has been read
+            let _permit = self.acquire_permit().await;
+            let path = self.api_path(endpoint);
+            let request_id = new_request_id();
+            let response = match self.send_stream_request(&path, &request, &request_id, &headers).await {
+                Ok(r) => r,
+                Err(e) => {
+                    yield Err(e);
+                    return;
+                }
+            };
+
+            let mut stream = Box::pin(self.response_events(response, resume));
+            while let Some(event) = stream.next().await {
+                yield event.map_err(|e| with_request_context(e, &request_id, &path));
+            }
+        }
+    }
+
+    /// Waits for a slot if the client was built with
+    /// [`DeepSeekAPIBuilder::max_concurrency`].
+    async fn acquire_permit(&self) -> Option<tokio::sync::OwnedSemaphorePermit> {
+        match &self.concurrency {
+            // The semaphore is never closed
+            Some(semaphore) => Arc::clone(semaphore).acquire_owned().await.ok(),
+            None => None,
+        }
+    }
+
+    /// Parses `response` into events, on a separate task when the client was built
+    /// with [`DeepSeekAPIBuilder::stream_buffer`].
+    fn response_events(
+        &self,
+        response: reqwest::Response,
+        resume: Option<Resume>,
+    ) -> impl futures_util::Stream<Item = Result<CompletionEvent>> + use<> {
+        use futures_util::future::Either;
+
+        let events = response_to_event_stream(
+            response,
+            self.heartbeat_timeout,
+            self.lenient_parsing,
+            resume,
+        );
+        match self.stream_buffer {
+            None => Either::Left(events),
+            Some(capacity) => Either::Right(buffered(events, capacity)),
+        }
+    }
+
+    /// Solves a `PoW` challenge for `path` and posts `request` to it with the extra
+    /// `headers`, returning the checked streaming response.
+    ///
+    /// Both requests are tagged with `request_id`, which is also attached to any
+    /// error as a [`RequestContext`].
+    async fn send_stream_request(
+        &self,
+        path: &str,
+        request: &serde_json::Value,
+        request_id: &str,
+        headers: &header::HeaderMap,
+    ) -> Result<reqwest::Response> {
+        self.send_stream_request_timed(path, request, request_id, headers)
+            .await
+            .map(|(response, _)| response)
+    }
+
+    /// Like [`send_stream_request`](Self::send_stream_request), also returning how
+    /// long it took to obtain the `PoW` header.
+    async fn send_stream_request_timed(
+        &self,
+        path: &str,
+        request: &serde_json::Value,
+        request_id: &str,
+        headers: &header::HeaderMap,
+    ) -> Result<(reqwest::Response, Duration)> {
+        let send = async {
+            // The response is streamed, so it is not bounded by the request timeout
+            let (response, pow) = self
+                .send_retrying(self.retry, move || async move {
+                    let request_builder = self
+                        .client
+                        .post(format!("{}{path}", self.base_url))
+                        .headers(extra_headers(headers))
+                        .header(REQUEST_ID_HEADER, request_id);
+                    let pow_started = std::time::Instant::now();
+                    let request_builder = self
+                        .set_pow_header(request_builder, path, request_id)
+                        .await?;
+                    let pow = pow_started.elapsed();
+                    let request_builder = request_builder
+                        // The event stream is framed on raw bytes, so ask for it uncompressed
+                        .header(header::ACCEPT_ENCODING, "identity")
+                        .json(request);
+                    Ok((request_builder, pow))
+                })
+                .await?;
+            Ok((check_stream_response(response).await?, pow))
+        };
+        tracing::debug!(request_id, path, "sending request");
+        send.instrument(tracing::debug_span!("deepseek_request", request_id, path))
+            .await
+            .map_err(|e| with_request_context(e, request_id, path))
+    }
+
+    // Removed handle_property_update; logic moved to StreamingMessageBuilder
+
+    /// Uploads a file to the server and waits for it to finish processing.
+    ///
+    /// This method will poll the server until the file status becomes `SUCCESS` or `ERROR`,
+    /// with a maximum of 60 attempts (2 seconds apart, total up to 2 minutes).
+    ///
+    /// # Arguments
+    /// * `file_data` - The file content as bytes.
+    /// * `filename` - The name of the file.
+    /// * `mime_type` - Optional MIME type; if `None`, it is guessed from the file
+    ///   extension, or from the content if the extension is unknown, and is
+    ///   `application/octet-stream` if neither is recognized.
+    ///
+    /// # Errors
+    /// Returns an error if the `PoW` challenge fails, the upload request fails, the response
+    /// cannot be parsed, or the file processing fails or times out. Errors from the
+    /// upload itself carry a [`RequestContext`], see
+    /// [`DeepSeekError::request_context`].
+    pub async fn upload_file(
+        &self,
+        file_data: Vec<u8>,
+        filename: &str,
+        mime_type: Option<&str>,
+    ) -> Result<models::FileInfo, DeepSeekError> {
+        self.upload_file_with_headers(file_data, filename, mime_type, &header::HeaderMap::new())
+            .await
+    }
+
+    /// Like [`upload_file`](Self::upload_file), but sends the upload request with
+    /// additional HTTP `headers`, with the same precedence as
+    /// [`CompletionOptions::headers`].
+    ///
+    /// # Errors
+    /// Returns an error as described for [`upload_file`](Self::upload_file).
+    pub async fn upload_file_with_headers(
+        &self,
+        file_data: Vec<u8>,
+        filename: &str,
+        mime_type: Option<&str>,
+        headers: &header::HeaderMap,
+    ) -> Result<models::FileInfo, DeepSeekError> {
+        let file_size = file_data.len() as u64;
+        let mime_type = mime_type.unwrap_or_else(|| guess_mime_type(filename, Some(&file_data)));
+        Ok(self
+            .upload(
+                UploadBody::Bytes(file_data.into()),
+                file_size,
+                filename,
+                Some(mime_type),
+                headers,
+                &upload_poll(),
+            )
+            .await?)
+    }
+
+    /// Like [`upload_file`](Self::upload_file), but waits for the file to be
+    /// processed polling up to `max_attempts` times, `delay` apart, instead of
+    /// the default 60 times, 2 seconds apart.
+    ///
+    /// The returned file has the status `SUCCESS` and can be referenced in
+    /// [`CompletionOptions::ref_file_ids`] right away. Use
+    /// [`wait_for_file_processing_with`](Self::wait_for_file_processing_with) on
+    /// the id of a pending file for other polling strategies.
+    ///
+    /// # Errors
+    /// Returns an error as described for [`upload_file`](Self::upload_file), or
+    /// if the file is still not processed after `max_attempts` polls.
+    pub async fn upload_file_and_wait(
+        &self,
+        file_data: Vec<u8>,
+        filename: &str,
+        mime_type: Option<&str>,
+        max_attempts: usize,
+        delay: Duration,
+    ) -> Result<models::FileInfo, DeepSeekError> {
+        let file_size = file_data.len() as u64;
+        let mime_type = mime_type.unwrap_or_else(|| guess_mime_type(filename, Some(&file_data)));
+        let strategy = poll::ConstantDelay::new(max_attempts, delay);
+        Ok(self
+            .upload(
+                UploadBody::Bytes(file_data.into()),
+                file_size,
+                filename,
+                Some(mime_type),
+                &header::HeaderMap::new(),
+                &strategy,
+            )
+            .await?)
+    }
+
+    /// Like [`upload_file`](Self::upload_file), but reads the file from `stream`
+    /// while it is sent instead of holding it in memory, e.g. for large documents.
+    ///
+    /// `size` must be the total number of bytes `stream` yields: the server
+    /// expects the file size before the content. A file can be streamed from disk
+    /// with `tokio_util::io::ReaderStream`. Without `mime_type`, the MIME type is
+    /// guessed from the file extension only, as the content is not known upfront.
+    ///
+    /// # Errors
+    /// Returns an error as described for [`upload_file`](Self::upload_file), or if
+    /// `stream` fails while the file is sent.
+    pub async fn upload_file_stream<S>(
+        &self,
+        stream: S,
+        size: u64,
+        filename: &str,
+        mime_type: Option<&str>,
+    ) -> Result<models::FileInfo, DeepSeekError>
+    where
+        S: futures_util::TryStream + Send + 'static,
+        S::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+        bytes::Bytes: From<S::Ok>,
+    {
+        let body = UploadBody::Stream(reqwest::Body::wrap_stream(stream));
+        Ok(self
+            .upload(
+                body,
+                size,
+                filename,
+                mime_type,
+                &header::HeaderMap::new(),
+                &upload_poll(),
+            )
+            .await?)
+    }
+
+    /// Uploads the `file_size` bytes of `body` and waits for the file to be
+    /// processed, polling as decided by `strategy`.
+    async fn upload(
+        &self,
+        body: UploadBody,
+        file_size: u64,
+        filename: &str,
+        mime_type: Option<&str>,
+        headers: &header::HeaderMap,
+        strategy: &dyn PollStrategy,
+    ) -> Result<models::FileInfo> {
+        let path = self.api_path(UPLOAD_ENDPOINT);
+        let request_id = new_request_id();
+        tracing::debug!(request_id, path, "sending request");
+        let permit = self.acquire_permit().await;
+        let file_id = self
+            .send_upload(body, file_size, filename, mime_type, &request_id, headers)
+            .instrument(tracing::debug_span!("deepseek_request", request_id, path))
+            .await
+            .map_err(|e| with_request_context(e, &request_id, &path))?;
+        drop(permit);
+
+        Ok(self
+            .wait_for_file_processing_with(&file_id, strategy)
+            .await?)
+    }
+
+    /// Like [`upload_file`](Self::upload_file), but gives up as soon as `cancel` is
+    /// triggered.
+    ///
+    /// Cancelling while the file is being sent closes the connection, which stops
+    /// sending its bytes promptly. `DeepSeek` has no endpoint to discard an upload,
+    /// so the server is not notified: a file it had already received in full keeps
+    /// being processed and stays on the account, though its id is not returned.
+    ///
+    /// # Errors
+    /// Returns [`DeepSeekError::Cancelled`] if `cancel` is triggered before the file
+    /// is processed, and otherwise errors as described for
+    /// [`upload_file`](Self::upload_file).
+    pub async fn upload_file_cancellable(
+        &self,
+        file_data: Vec<u8>,
+        filename: &str,
+        mime_type: Option<&str>,
+        cancel: CancellationToken,
+    ) -> Result<models::FileInfo, DeepSeekError> {
+        tokio::select! {
+            biased;
+            () = cancel.cancelled() => Err(DeepSeekError::Cancelled),
+            result = self.upload_file(file_data, filename, mime_type) => result,
+        }
+    }
+
+    /// Solves the `PoW` challenge and posts the upload, returning the id of the
+    /// pending file.
+    ///
+    /// An upload held in memory is sent again after transient failures, with a
+    /// fresh `PoW`; a streamed one is sent only once.
+    async fn send_upload(
+        &self,
+        body: UploadBody,
+        file_size: u64,
+        filename: &str,
+        mime_type: Option<&str>,
+        request_id: &str,
+        headers: &header::HeaderMap,
+    ) -> Result<String> {
+        // Define response structs
+        #[derive(serde::Deserialize)]
+        struct UploadResponse {
+            data: UploadData,
+        }
+        #[derive(serde::Deserialize)]
+        struct UploadData {
+            biz_data: models::FileInfo,
+        }
+
+        // 1. Guess MIME type if not provided
+        let mime = mime_type.unwrap_or_else(|| guess_mime_type(filename, None));
+
+        let (retry, bytes, mut stream) = match body {
+            UploadBody::Bytes(bytes) => (self.retry, Some(bytes), None),
+            UploadBody::Stream(body) => (RetryPolicy::none(), None, Some(body)),
+        };
+        let path = &self.api_path(UPLOAD_ENDPOINT);
+        let (response, ()) = self
+            .send_retrying(retry, || {
+                let body = match &bytes {
+                    Some(bytes) => Some(reqwest::Body::from(bytes.clone())),
+                    None => stream.take(),
+                };
+                async move {
+                    // 2. Prepare multipart form
+                    let body = body.context("A streamed upload cannot be sent again")?;
+                    let part = multipart::Part::stream_with_length(body, file_size)
+                        .file_name(filename.to_string())
+                        .mime_str(mime)?;
+                    let form = multipart::Form::new().part("file", part);
+
+                    // 3. Solve the PoW challenge and build the upload request
+                    let request_builder = self
+                        .client
+                        .post(self.api_url(UPLOAD_ENDPOINT))
+                        .headers(extra_headers(headers))
+                        .header(REQUEST_ID_HEADER, request_id);
+                    let request_builder = self
+                        .set_pow_header(request_builder, path, request_id)
+                        .await?
+                        .header("x-file-size", file_size.to_string())
+                        .multipart(form);
+                    Ok((self.timed(request_builder), ()))
+                }
+            })
+            .await?;
+        let response = response.error_for_status()?;
+
+        // 4. Parse initial response (file is now pending)
+        let upload: UploadResponse = response.json().await?;
+        Ok(upload.data.biz_data.id)
+    }
+
+    /// Fetches information about a file by its ID.
+    ///
+    /// Only the metadata is available: the API has no endpoint returning the
+    /// content of an uploaded file, so keep a copy of the bytes passed to
+    /// [`upload_file`](Self::upload_file) if they are needed again.
+    ///
+    /// # Errors
+    /// Returns an error if the request fails, the response indicates an error, or the file is not found.
+    pub async fn fetch_file_info(&self, file_id: &str) -> Result<models::FileInfo, DeepSeekError> {
+        use anyhow::anyhow;
+
+        // Define response structs
+        #[derive(serde::Deserialize)]
+        struct FetchResponse {
+            data: FetchData,
+        }
+        #[derive(serde::Deserialize)]
+        struct FetchData {
+            biz_data: FetchBizData,
+        }
+        #[derive(serde::Deserialize)]
+        struct FetchBizData {
+            files: Vec<models::FileInfo>,
+        }
+
+        let url = format!("{}?file_ids={file_id}", self.api_url("file/fetch_files"));
+        let request = self.client.get(&url);
+        let resp: FetchResponse = self.send(request).await?.error_for_status()?.json().await?;
+        resp.data
+            .biz_data
+            .files
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow!("No file found with ID {file_id}").into())
+    }
+
+    /// Waits for a file to finish processing (status `SUCCESS`).
+    ///
+    /// # Arguments
+    /// * `file_id` - The file ID.
+    /// * `max_attempts` - Maximum number of polling attempts.
+    /// * `delay` - Delay between attempts (e.g., `std::time::Duration::from_millis(500)`).
+    ///
+    /// # Errors
+    /// Returns an error if the file status becomes `ERROR`, or if the maximum attempts are exceeded.
+    pub async fn wait_for_file_processing(
+        &self,
+        file_id: &str,
+        max_attempts: usize,
+        delay: std::time::Duration,
+    ) -> Result<models::FileInfo, DeepSeekError> {
+        self.wait_for_file_processing_with(file_id, &poll::ConstantDelay::new(max_attempts, delay))
+            .await
+    }
+
+    /// Waits for a file to finish processing (status `SUCCESS`), polling as
+    /// decided by `strategy`.
+    ///
+    /// # Errors
+    /// Returns an error if the file status becomes `ERROR`, or if `strategy` gives
+    /// up before processing finishes.
+    pub async fn wait_for_file_processing_with(
+        &self,
+        file_id: &str,
+        strategy: &dyn PollStrategy,
+    ) -> Result<models::FileInfo, DeepSeekError> {
+        for attempt in 1.. {
+            let info = self.fetch_file_info(file_id).await?;
+            match info.status.as_str() {
+                "SUCCESS" => return Ok(info),
+                "ERROR" => {
+                    return Err(
+                        anyhow::anyhow!("File processing error: {:?}", info.error_code).into(),
+                    );
+                }
+                status => match strategy.next_delay(attempt, status) {
+                    Some(delay) => tokio::time::sleep(delay).await,
+                    None => {
+                        return Err(anyhow::anyhow!(
+                            "File processing timed out after {attempt} attempts"
+                        )
+                        .into());
+                    }
+                },
+            }
+        }
+        unreachable!()
+    }
+}
+
+/// The content of an upload.
+enum UploadBody {
+    /// Held in memory, so the upload can be sent again.
+    Bytes(bytes::Bytes),
+    /// Read from a stream while it is sent, so the upload is sent only once.
+    Stream(reqwest::Body),
+}
+
+/// Converts the errors of `stream` into [`DeepSeekError`]s, for the streams of
+/// the public API.
+pub(crate) fn typed_errors<T>(
+    stream: impl futures_util::Stream<Item = Result<T>>,
+) -> impl futures_util::Stream<Item = Result<T, DeepSeekError>> {
+    stream.map(|item| item.map_err(DeepSeekError::from))
+}
+
+/// Turns a response with status 401 into [`DeepSeekError::Unauthorized`] and
+/// one with status 429 into [`DeepSeekError::RateLimited`].
+fn check_status(response: reqwest::Response) -> Result<reqwest::Response> {
+    match response.status() {
+        reqwest::StatusCode::UNAUTHORIZED => Err(DeepSeekError::Unauthorized {
+            message_id: None,
+            request: None,
+        }
+        .into()),
+        reqwest::StatusCode::TOO_MANY_REQUESTS => {
+            let retry_after = retry::retry_after(response.headers());
+            Err(DeepSeekError::RateLimited {
+                retry_after,
+                request: None,
+            }
+            .into())
+        }
+        _ => Ok(response),
+    }
+}
+
+/// How [`DeepSeekAPI::upload_file`] waits for processing: up to 60 polls, 2
+/// seconds apart.
+fn upload_poll() -> poll::ConstantDelay {
+    poll::ConstantDelay::new(60, Duration::from_secs(2))
+}
+
+/// Guesses the MIME type of an upload from the extension of `filename`, or
+/// from the magic number at the start of `content` if the extension is unknown.
+fn guess_mime_type(filename: &str, content: Option<&[u8]>) -> &'static str {
+    let extension = std::path::Path::new(filename)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(str::to_ascii_lowercase);
+    let by_extension = match extension.as_deref() {
+        Some("png") => Some("image/png"),
+        Some("jpg" | "jpeg") => Some("image/jpeg"),
+        Some("gif") => Some("image/gif"),
+        Some("webp") => Some("image/webp"),
+        Some("bmp") => Some("image/bmp"),
+        Some("svg") => Some("image/svg+xml"),
+        Some("pdf") => Some("application/pdf"),
+        Some("txt" | "log") => Some("text/plain"),
+        Some("md" | "markdown") => Some("text/markdown"),
+        Some("csv") => Some("text/csv"),
+        Some("tsv") => Some("text/tab-separated-values"),
+        Some("html" | "htm") => Some("text/html"),
+        Some("xml") => Some("application/xml"),
+        Some("json") => Some("application/json"),
+        Some("yaml" | "yml") => Some("application/yaml"),
+        Some("rtf") => Some("application/rtf"),
+        Some("epub") => Some("application/epub+zip"),
+        Some("doc") => Some("application/msword"),
+        Some("docx") => {
+            Some("application/vnd.openxmlformats-officedocument.wordprocessingml.document")
+        }
+        Some("xls") => Some("application/vnd.ms-excel"),
+        Some("xlsx") => Some("application/vnd.openxmlformats-officedocument.spreadsheetml.sheet"),
+        Some("ppt") => Some("application/vnd.ms-powerpoint"),
+        Some("pptx") => {
+            Some("application/vnd.openxmlformats-officedocument.presentationml.presentation")
+        }
+        _ => None,
+    };
+    by_extension
+        .or_else(|| infer::get(content?).map(|kind| kind.mime_type()))
+        .unwrap_or("application/octet-stream")
+}
+
+/// Options of a completion request.
+#[derive(Debug, Clone, Default)]
+pub struct CompletionOptions {
+    /// Enables web search.
+    ///
+    /// The server picks how many sources are searched; the API has no parameter
+    /// controlling it.
+    pub search: bool,
+    /// Enables thinking (reasoning).
+    pub thinking: bool,
+    /// IDs of uploaded files to reference.
+    pub ref_file_ids: Vec<String>,
+    /// Sequences that end the response when they appear in its content.
+    ///
+    /// The API has no such parameter, so they are applied by the client: content
+    /// that may be the start of a sequence is held back until it is known not to
+    /// be, and at the first occurrence of any sequence the server is asked to stop
+    /// generating. The stream then ends with a [`CompletionWarning::StopSequence`]
+    /// and a final message holding the content before the sequence. Only content
+    /// is checked, not thinking, and empty sequences are ignored. The message kept
+    /// in the chat history may contain text past the sequence, generated before
+    /// the server stopped.
+    pub stop_sequences: Vec<String>,
+    /// Ends the completion with the incomplete message instead of requesting
+    /// continuations when the server stops before the message is complete.
+    ///
+    /// The final message then has the status [`models::MessageStatus::Incomplete`] and is preceded by a
+    /// [`CompletionWarning::ContinuationLimit`] with a limit of 0; pass its
+    /// `message_id` to [`DeepSeekAPI::continue_stream`] to resume it. Off by
+    /// default, so that incomplete messages are continued transparently.
+    pub no_continue: bool,
+    /// The number of continuations allowed, overriding
+    /// [`DeepSeekAPIBuilder::max_continuations`].
+    pub max_continuations: Option<u32>,
+    /// Additional fields sent as-is in the completion request, for parameters the
+    /// crate does not model yet.
+    ///
+    /// They never override a field the crate sets itself, such as `prompt` or
+    /// `thinking_enabled`.
+    pub extra: serde_json::Map<String, serde_json::Value>,
+    /// Additional HTTP headers sent with the completion request and its
+    /// continuations, e.g. a tenant id required by a gateway.
+    ///
+    /// They are added on top of the client's default headers, replacing those with
+    /// the same name. The headers the crate manages cannot be overridden and are
+    /// dropped from this map: `Authorization`, `x-ds-pow-response`, `x-request-id`
+    /// and `Accept-Encoding`.
+    pub headers: header::HeaderMap,
+}
+
+/// The outcome of a non‑streaming completion, see [`DeepSeekAPI::complete_detailed`].
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct CompletionResult {
+    /// The final message.
+    pub message: models::Message,
+    /// How many continuation requests were issued because the response was
+    /// incomplete. Each one costs an extra `PoW` solve and request.
+    pub continuations: u32,
+    /// The session changes the server reported in the stream, if any, so that
+    /// callers need not re-fetch the session with [`DeepSeekAPI::get_chat_info`].
+    /// `current_message_id` falls back to the id of `message`.
+    pub session: Option<models::SessionUpdate>,
+    /// Whether the response exceeded
+    /// [`DeepSeekAPIBuilder::max_response_size`] and `message` was truncated.
+    pub truncated: bool,
+    /// How many times the completion was retried because it finished without
+    /// content, see [`DeepSeekAPIBuilder::retry_empty_response`].
+    pub retries: u32,
+    /// The stop sequence that ended the response, see
+    /// [`CompletionOptions::stop_sequences`].
+    pub stop_sequence: Option<String>,
+}
+
+/// Where the time of a completion was spent, see
+/// [`DeepSeekAPI::complete_profiled`].
+///
+/// All durations except `pow` are measured from the start of the call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct LatencyProfile {
+    /// Time taken to request and solve the `PoW` challenge of the initial request,
+    /// or to take a prefetched solution; zero when `PoW` is disabled.
+    pub pow: Duration,
+    /// Time until the response headers of the initial request arrived, including
+    /// `pow`.
+    pub first_byte: Duration,
+    /// Time until the first fragment of content or thinking arrived, if any did.
+    pub first_content: Option<Duration>,
+    /// Time until the final message was received, continuations included.
+    pub total: Duration,
+    /// How many continuation requests were issued.
+    pub continuations: u32,
+}
+
+/// A line of a completion's event stream as sent by the server, see
+/// [`DeepSeekAPI::complete_raw_stream`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RawSseEvent {
+    /// The name set by the last `event:` line, or `None` if there was none since
+    /// the last blank line.
+    pub event: Option<String>,
+    /// The payload of a `data:` line, usually JSON, or empty for an `event:` line
+    /// without data.
+    pub data: String,
+}
+
+/// Represents a chunk from the streaming response.
+///
+/// New variants may be added, so matches need a catch-all arm.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum StreamChunk {
+    /// A fragment of the answer, in the order the server sent it; only the
+    /// final message reorders sequenced fragments, see
+    /// [`StreamingUpdate::seq`](models::StreamingUpdate::seq).
+    Content(String),
+    /// A fragment of the model's reasoning, in the order the server sent it.
+    Thinking(String),
+    Message(models::Message),
+    Warning(CompletionWarning),
+    /// Web search results the answer is based on, yielded when search is enabled.
+    /// The final message holds them as [`citations`](models::Message::citations).
+    SearchResults(Vec<models::SearchCitation>),
+    /// The accumulated token usage of the message so far, yielded whenever the
+    /// server updates it; the final message holds the last value as
+    /// [`accumulated_token_usage`](models::Message::accumulated_token_usage).
+    Usage(i64),
+    /// An image embedded in the content, yielded after the content chunk that
+    /// completes its markup, only if enabled with
+    /// [`DeepSeekAPIBuilder::detect_images`]. The markup stays in the content; the
+    /// final message lists all images with
+    /// [`Message::inline_images`](models::Message::inline_images).
+    ///
+    /// The server sends no separate field for images: they are recognized by a
+    /// heuristic on the content, so a Markdown image the model writes is reported
+    /// like one the server generated.
+    Image(models::InlineImage),
+}
+
+/// A lifecycle event of a completion, yielded by [`DeepSeekAPI::complete_events`].
+#[derive(Debug)]
+pub enum CompletionEvent {
+    /// The server announced the message being generated.
+    Started { message_id: Option<i64> },
+    /// A fragment of the answer, in arrival order, as for
+    /// [`StreamChunk::Content`].
+    Content(String),
+    /// A fragment of the model's reasoning, in arrival order.
+    Thinking(String),
+    /// Web search results used for the answer.
+    SearchResults(Vec<models::SearchCitation>),
+    /// The accumulated token usage of the message so far.
+    Usage(i64),
+    /// The response was incomplete and a continuation request was issued.
+    ContinuationStarted { message_id: i64 },
+    /// A non‑fatal condition, see [`CompletionWarning`].
+    Warning(CompletionWarning),
+    /// Session metadata the server reported for this completion, right before
+    /// [`Finished`](Self::Finished).
+    SessionUpdated(models::SessionUpdate),
+    /// The final, complete message.
+    Finished(models::Message),
+}
+
+impl CompletionEvent {
+    fn into_chunk(self) -> Option<StreamChunk> {
+        match self {
+            Self::Started { .. } | Self::ContinuationStarted { .. } | Self::SessionUpdated(_) => {
+                None
+            }
+            Self::Content(c) => Some(StreamChunk::Content(c)),
+            Self::Thinking(t) => Some(StreamChunk::Thinking(t)),
+            Self::Warning(w) => Some(StreamChunk::Warning(w)),
+            Self::SearchResults(results) => Some(StreamChunk::SearchResults(results)),
+            Self::Usage(tokens) => Some(StreamChunk::Usage(tokens)),
+            Self::Finished(msg) => Some(StreamChunk::Message(msg)),
+        }
+    }
+}
+
+/// Handle that aborts a stream returned by
+/// [`DeepSeekAPI::complete_stream_with_handle`].
+///
+/// The handle is backed by a [`CancellationToken`], which can be obtained with
+/// [`AbortHandle::cancellation_token`] to tie the stream into an existing
+/// cancellation tree.
+#[derive(Debug, Clone)]
+pub struct AbortHandle {
+    token: CancellationToken,
+}
+
+impl AbortHandle {
+    /// Stops the associated stream and asks the server to stop generating.
+    pub fn abort(&self) {
+        self.token.cancel();
+    }
+
+    /// Returns whether [`abort`](Self::abort) has been called.
+    #[must_use]
+    pub fn is_aborted(&self) -> bool {
+        self.token.is_cancelled()
+    }
+
+    /// Returns the token backing this handle; cancelling it aborts the stream.
+    #[must_use]
+    pub fn cancellation_token(&self) -> CancellationToken {
+        self.token.clone()
+    }
+}
+
+/// HTTP metadata of a streaming response.
+#[derive(Debug, Clone)]
+pub struct ResponseMeta {
+    /// The HTTP status code.
+    pub status: reqwest::StatusCode,
+    /// The request id assigned by the server, if any (`x-request-id` or `x-trace-id`),
+    /// useful for correlating a request with `DeepSeek` support.
+    pub request_id: Option<String>,
+    /// The id the client sent in the `x-request-id` request header, also carried by
+    /// the [`RequestContext`] of errors yielded by the stream.
+    pub client_request_id: String,
+    /// All response headers.
+    pub headers: header::HeaderMap,
+}
+
+impl ResponseMeta {
+    fn from_response(response: &reqwest::Response, client_request_id: &str) -> Self {
+        let headers = response.headers().clone();
+        let request_id = ["x-request-id", "x-trace-id"]
+            .iter()
+            .find_map(|name| headers.get(*name)?.to_str().ok())
+            .map(str::to_string);
+        Self {
+            status: response.status(),
+            request_id,
+            client_request_id: client_request_id.to_string(),
+            headers,
+        }
+    }
+}
+
+/// A non‑fatal condition reported while streaming a completion.
+///
+/// Serializes as an object with a `kind` field, e.g.
+/// `{"kind":"response_truncated","limit":1024}`. New variants may be added, so
+/// matches need a catch-all arm.
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum CompletionWarning {
+    /// Thinking was rejected by the server and the request was retried without it.
+    ThinkingDisabled { reason: String },
+    /// The response exceeded the limit set with
+    /// [`DeepSeekAPIBuilder::max_response_size`]; generation was stopped and the
+    /// final message holds the text received up to `limit` bytes.
+    ResponseTruncated { limit: usize },
+    /// The content contained `sequence`, one of
+    /// [`CompletionOptions::stop_sequences`]; generation was stopped and the final
+    /// message holds the content before it.
+    StopSequence { sequence: String },
+    /// The message was still incomplete after `limit` continuations, see
+    /// [`DeepSeekAPIBuilder::max_continuations`]; the final message is the
+    /// incomplete one.
+    ContinuationLimit { limit: u32 },
+}
+
+/// What to do when a response exceeds
+/// [`DeepSeekAPIBuilder::max_response_size`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OversizeAction {
+    /// End the stream with a [`CompletionWarning::ResponseTruncated`] followed by
+    /// the truncated message.
+    Truncate,
+    /// End the stream with a [`DeepSeekError::ResponseTooLarge`] error.
+    Error,
+}
+
+/// Builds the [`CompletionRequest`] for the positional arguments of
+/// [`DeepSeekAPI::complete`] and its variants.
+fn completion(
+    chat_id: impl Into<String>,
+    prompt: impl Into<String>,
+    parent_message_id: Option<i64>,
+    search: bool,
+    thinking: bool,
+    ref_file_ids: Vec<String>,
+) -> CompletionRequest {
+    CompletionRequest {
+        parent_message_id,
+        ..CompletionRequest::new(chat_id, prompt)
+            .search(search)
+            .thinking(thinking)
+            .ref_files(ref_file_ids)
+    }
+}
+
+/// The body of a request continuing the incomplete message `message_id`.
+fn continue_request(chat_id: &str, message_id: i64) -> serde_json::Value {
+    json!({
+        "chat_session_id": chat_id,
+        "message_id": message_id,
+        "fallback_to_resume": true,
+    })
+}
+
+/// Records the continued `message_id` on a [`DeepSeekError::Unauthorized`]
+/// error, returning whether `e` is one.
+fn mark_unauthorized(e: &mut anyhow::Error, continued: Option<i64>) -> bool {
+    match e.downcast_mut::<DeepSeekError>() {
+        Some(DeepSeekError::Unauthorized { message_id, .. }) => {
+            *message_id = continued;
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Outcome of [`ResponseLimiter::admit`].
+enum Admission {
+    /// The event fits within the limit.
+    Within(CompletionEvent),
+    /// The limit was exceeded; holds the part of the event that still fits, if any.
+    Exceeded(Option<CompletionEvent>),
+}
+
+/// Tracks the size of a response against the configured limit, keeping the text
+/// received so far to build a truncated message.
+struct ResponseLimiter {
+    max_bytes: usize,
+    action: OversizeAction,
+    content: String,
+    thinking: String,
+}
+
+impl ResponseLimiter {
+    fn new(max_bytes: usize, action: OversizeAction) -> Self {
+        Self {
+            max_bytes,
+            action,
+            content: String::new(),
+            thinking: String::new(),
+        }
+    }
+
+    /// Counts the text of `event` against the limit.
+    fn admit(&mut self, event: CompletionEvent) -> Admission {
+        let (text, is_content) = match event {
+            CompletionEvent::Content(text) => (text, true),
+            CompletionEvent::Thinking(text) => (text, false),
+            event => return Admission::Within(event),
+        };
+        let remaining = self.max_bytes - self.content.len() - self.thinking.len();
+        let fits = text.len() <= remaining;
+        let text = if fits {
+            text
+        } else {
+            text[..text.floor_char_boundary(remaining)].to_string()
+        };
+        let (buffer, event): (_, fn(String) -> CompletionEvent) = if is_content {
+            (&mut self.content, CompletionEvent::Content)
+        } else {
+            (&mut self.thinking, CompletionEvent::Thinking)
+        };
+        buffer.push_str(&text);
+        match (fits, text.is_empty()) {
+            (true, _) => Admission::Within(event(text)),
+            (false, true) => Admission::Exceeded(None),
+            (false, false) => Admission::Exceeded(Some(event(text))),
+        }
+    }
+
+    /// The events ending a response that exceeded the limit: the part of the last
+    /// event that still fits, then an error or the truncated message.
+    fn exceeded(
+        &mut self,
+        partial: Option<CompletionEvent>,
+        message_id: Option<i64>,
+    ) -> Vec<Result<CompletionEvent>> {
+        let limit = self.max_bytes;
+        let mut events: Vec<_> = partial.into_iter().map(Ok).collect();
+        match self.action {
+            OversizeAction::Error => {
+                events.push(Err(DeepSeekError::ResponseTooLarge { limit }.into()));
+            }
+            OversizeAction::Truncate => {
+                events.push(Ok(CompletionEvent::Warning(
+                    CompletionWarning::ResponseTruncated { limit },
+                )));
+                events.push(Ok(CompletionEvent::Finished(self.message(message_id))));
+            }
+        }
+        events
+    }
+
+    /// Builds the truncated message from the text received so far.
+    fn message(&mut self, message_id: Option<i64>) -> models::Message {
+        partial_message(
+            message_id,
+            std::mem::take(&mut self.content),
+            std::mem::take(&mut self.thinking),
+        )
+    }
+}
+
+/// Watches the content of a response for stop sequences, see
+/// [`CompletionOptions::stop_sequences`].
+struct StopMatcher {
+    stop_sequences: Vec<String>,
+    /// The content received so far, up to any stop sequence.
+    content: String,
+    /// How much of `content` was passed on; the rest may start a stop sequence.
+    passed: usize,
+    thinking: String,
+}
+
+impl StopMatcher {
+    /// Returns a matcher for the non-empty `stop_sequences`, if there are any.
+    fn new(mut stop_sequences: Vec<String>) -> Option<Self> {
+        stop_sequences.retain(|sequence| !sequence.is_empty());
+        (!stop_sequences.is_empty()).then(|| Self {
+            stop_sequences,
+            content: String::new(),
+            passed: 0,
+            thinking: String::new(),
+        })
+    }
+
+    /// Adds `text` to the content, returning the content that can be passed on
+    /// and the stop sequence it ends at, if any.
+    fn push(&mut self, text: &str) -> (String, Option<String>) {
+        self.content.push_str(text);
+        let pending = &self.content[self.passed..];
+        let found = self
+            .stop_sequences
+            .iter()
+            .filter_map(|sequence| pending.find(sequence.as_str()).map(|at| (at, sequence)))
+            .min_by_key(|(at, _)| *at);
+        if let Some((at, sequence)) = found {
+            let sequence = sequence.clone();
+            let end = self.passed + at;
+            self.content.truncate(end);
+            let text = self.content[self.passed..].to_string();
+            self.passed = end;
+            return (text, Some(sequence));
+        }
+        // Hold back the longest tail that a stop sequence starts with
+        let held = (1..=pending.len())
+            .rev()
+            .map(|len| pending.len() - len)
+            .filter(|&start| pending.is_char_boundary(start))
+            .find(|&start| {
+                self.stop_sequences
+                    .iter()
+                    .any(|sequence| sequence.starts_with(&pending[start..]))
+            })
+            .map_or(0, |start| pending.len() - start);
+        let end = self.content.len() - held;
+        let text = self.content[self.passed..end].to_string();
+        self.passed = end;
+        (text, None)
+    }
+
+    /// Returns the content held back, now that the response has ended.
+    fn flush(&mut self) -> String {
+        let held = self.content[self.passed..].to_string();
+        self.passed = self.content.len();
+        held
+    }
+
+    /// Builds the message ending before the stop sequence.
+    fn message(&mut self, message_id: Option<i64>) -> models::Message {
+        partial_message(
+            message_id,
+            std::mem::take(&mut self.content),
+            std::mem::take(&mut self.thinking),
+        )
+    }
+}
+
+/// Builds a message the client cut short from the text received so far.
+fn partial_message(message_id: Option<i64>, content: String, thinking: String) -> models::Message {
+    models::Message {
+        message_id,
+        parent_id: None,
+        role: None,
+        inserted_at: None,
+        content,
+        thinking_content: (!thinking.is_empty()).then_some(thinking),
+        status: None,
+        accumulated_token_usage: None,
+        citations: None,
+    }
+}
+
+impl Clone for DeepSeekAPI {
+    fn clone(&self) -> Self {
+        Self {
+            client: self.client.clone(),
+            pow_solver: self.pow_solver.clone(),
+            pow_response: self.pow_response.clone(),
+            token: Arc::clone(&self.token),
+            authorization: Arc::clone(&self.authorization),
+            token_refresh: self.token_refresh.clone(),
+            thinking_fallback: self.thinking_fallback,
+            detect_images: self.detect_images,
+            stream_buffer: self.stream_buffer,
+            pow_config: self.pow_config.clone(),
+            api_version: self.api_version.clone(),
+            base_url: self.base_url.clone(),
+            clock_skew: Arc::clone(&self.clock_skew),
+            stream_cancellation: Arc::clone(&self.stream_cancellation),
+            pow_pool: Arc::clone(&self.pow_pool),
+            middleware: Arc::clone(&self.middleware),
+            response_limit: self.response_limit,
+            empty_response_retries: self.empty_response_retries,
+            lenient_parsing: self.lenient_parsing,
+            concurrency: self.concurrency.clone(),
+            total_timeout: self.total_timeout,
+            heartbeat_timeout: self.heartbeat_timeout,
+            request_timeout: self.request_timeout,
+            max_continuations: self.max_continuations,
+            retry: self.retry,
+        }
+    }
+}
+
+/// A solved `PoW` challenge, ready to be sent.
+struct SolvedPow {
+    /// The `x-ds-pow-response` header value.
+    header: String,
+    /// When the challenge expires, in milliseconds since the epoch.
+    expire_at: i64,
+}
+
+/// The message a continuation resumes, to drop text the server streams again.
+///
+/// A continuation normally starts with a snapshot of the message holding all of
+/// its text so far, then appends the rest. When the server instead resumes from
+/// an earlier checkpoint, the snapshot of a field is only a non-empty, strict
+/// prefix of the text already received, and the remainder is streamed again
+/// before any new text. A snapshot without the field only appends new text.
+/// That replay is matched against the text already received and skipped, so the
+/// continuation only yields new text and the final message holds it once. Should
+/// the replay diverge from what was received, the remaining appends are kept
+/// as sent.
+#[derive(Clone)]
+struct Resume {
+    fields: Vec<ResumedField>,
+}
+
+/// A text field of the message a continuation resumes.
+#[derive(Clone)]
+struct ResumedField {
+    /// The path the field is streamed to.
+    path: &'static str,
+    /// The text received before the continuation.
+    prior: String,
+    /// The text still expected to be streamed again.
+    replay: String,
+}
+
+impl Resume {
+    /// Resumes `message`, the incomplete message of the previous response.
+    fn new(message: &models::Message) -> Self {
+        let field = |path, prior: &str| ResumedField {
+            path,
+            prior: prior.to_string(),
+            replay: String::new(),
+        };
+        let mut fields = vec![field("response/content", &message.content)];
+        if let Some(thinking) = &message.thinking_content {
+            fields.push(field("response/thinking_content", thinking));
+        }
+        Self { fields }
+    }
+
+    /// Checks the snapshot that starts the continuation for fields resumed from
+    /// a checkpoint, restoring their text received so far.
+    fn start(&mut self, builder: &mut models::StreamingMessageBuilder) -> Result<()> {
+        for field in &mut self.fields {
+            let snapshot = builder.text_at(field.path).unwrap_or_default();
+            if snapshot.len() >= field.prior.len() {
+                continue;
+            }
+            if !snapshot.is_empty() {
+                let Some(replay) = field.prior.strip_prefix(snapshot) else {
+                    continue;
+                };
+                field.replay = replay.to_string();
+            }
+            builder.apply_update(&models::StreamingUpdate {
+                p: Some(field.path.to_string()),
+                v: Some(serde_json::Value::String(field.prior.clone())),
+                o: Some("SET".to_string()),
+                seq: None,
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Removes the replayed text from `update`, returning `false` if nothing is
+    /// left to apply.
+    fn dedupe(&mut self, update: &mut models::StreamingUpdate) -> bool {
+        let Some(field) = self
+            .fields
+            .iter_mut()
+            .find(|field| update.p.as_deref() == Some(field.path))
+        else {
+            return true;
+        };
+        if field.replay.is_empty() {
+            return true;
+        }
+        let (Some("APPEND"), Some(serde_json::Value::String(text))) =
+            (update.o.as_deref(), &mut update.v)
+        else {
+            // A new value for the field ends the replay
+            field.replay.clear();
+            return true;
+        };
+        if let Some(rest) = field.replay.strip_prefix(text.as_str()) {
+            field.replay = rest.to_string();
+            return false;
+        }
+        if let Some(new) = text.strip_prefix(field.replay.as_str()) {
+            *text = new.to_string();
+        }
+        field.replay.clear();
+        !text.is_empty()
+    }
+}
+
+struct SseParser {
+    builder: crate::models::StreamingMessageBuilder,
+    current_property: Option<String>,
+    toast_error: Option<DeepSeekError>,
+    resume: Option<Resume>,
+}
+
+impl SseParser {
+    fn new() -> Self {
+        Self {
+            builder: crate::models::StreamingMessageBuilder::default(),
+            current_property: None,
+            toast_error: None,
+            resume: None,
+        }
+    }
+
+    /// Applies a data line to the message being built. API errors reported in the
+    /// line must be checked for first, with [`data_line_error`].
+    fn process_data_line(&mut self, data_json: &[u8]) -> Result<Option<CompletionEvent>> {
+        let data: crate::models::StreamingUpdate = serde_json::from_slice(data_json)?;
+        // Handle case where the entire data is a plain JSON object (not a patch)
+        if data.v.is_none() && data.p.is_none() {
+            let full_value: serde_json::Value = serde_json::from_slice(data_json)?;
+            if full_value.get("response").is_some() {
+                return self.start(full_value);
+            }
+            return Ok(None);
+        }
+
+        let is_new_object = data
+            .v
+            .as_ref()
+            .is_some_and(|v| v.is_object() && data.p.as_deref().unwrap_or("").is_empty());
+        let path = data.p.clone().unwrap_or_default();
+
+        if is_new_object {
+            if let Some(v) = data.v.as_ref()
+                && v.get("response").is_some()
+            {
+                return self.start(v.clone());
+            }
+            return Ok(None);
+        }
+
+        if path.is_empty() {
+            let Some(cur) = self.current_property.clone() else {
+                return Ok(None);
+            };
+            let mut update = data;
+            update.p = Some(cur);
+            update.o = Some("APPEND".to_string());
+            self.apply(update)
+        } else {
+            self.current_property = Some(path);
+            self.apply(data)
+        }
+    }
+
+    /// Starts the message from the snapshot `value`.
+    fn start(&mut self, value: serde_json::Value) -> Result<Option<CompletionEvent>> {
+        self.builder = crate::models::StreamingMessageBuilder::from_value(value)?;
+        if let Some(resume) = &mut self.resume {
+            resume.start(&mut self.builder)?;
+        }
+        Ok(Some(self.started()))
+    }
+
+    /// Applies `update` to the message, returning the event it produces.
+    fn apply(
+        &mut self,
+        mut update: crate::models::StreamingUpdate,
+    ) -> Result<Option<CompletionEvent>> {
+        if let Some(resume) = &mut self.resume
+            && !resume.dedupe(&mut update)
+        {
+            return Ok(None);
+        }
+        // Checked first, so that a skipped line leaves the message unchanged
+        let event = path_event(update.p.as_deref().unwrap_or_default(), update.v.as_ref())?;
+        self.builder.apply_update(&update)?;
+        Ok(event)
+    }
+
+    /// Records the error described by the data line of a `toast` event, to be
+    /// returned by [`finish`](Self::finish).
+    fn process_toast_line(&mut self, data_json: &[u8]) {
+        #[derive(serde::Deserialize)]
+        struct Toast {
+            #[serde(default, rename = "type")]
+            kind: Option<String>,
+            #[serde(default)]
+            content: Option<String>,
+            #[serde(default)]
+            code: Option<serde_json::Value>,
+        }
+        let Ok(toast) = serde_json::from_slice::<Toast>(data_json) else {
+            return;
+        };
+        if toast.kind.as_deref().is_some_and(|kind| kind != "error") {
+            return;
+        }
+        let code = toast.code.map(|code| match code {
+            serde_json::Value::String(code) => code,
+            code => code.to_string(),
+        });
+        let message = toast.content.unwrap_or_default();
+        self.toast_error = Some(classify_toast(&message, code.as_deref()));
+    }
+
+    fn started(&self) -> CompletionEvent {
+        CompletionEvent::Started {
+            message_id: self.builder.message_id(),
+        }
+    }
+
+    fn finish(self) -> Result<models::Message> {
+        if let Some(err) = self.toast_error {
+            return Err(err.into());
+        }
+        Ok(self.builder.build()?)
+    }
+}
+
+/// Returns the event to emit for a value written to `path`, if any.
+///
+/// # Errors
+/// Returns an error if search results cannot be parsed.
+fn path_event(path: &str, value: Option<&serde_json::Value>) -> Result<Option<CompletionEvent>> {
+    let Some(value) = value else {
+        return Ok(None);
+    };
+    Ok(match path {
+        "response/content" => value
+            .as_str()
+            .map(|s| CompletionEvent::Content(s.to_string())),
+        "response/thinking_content" => value
+            .as_str()
+            .map(|s| CompletionEvent::Thinking(s.to_string())),
+        "response/search_results" => Some(CompletionEvent::SearchResults(
+            serde_json::from_value(value.clone()).context("Invalid search results")?,
+        )),
+        "response/accumulated_token_usage" => value.as_i64().map(CompletionEvent::Usage),
+        _ => None,
+    })
+}
+
+/// Drives `events` to the final message, summarizing the completion.
+async fn collect_result(
+    events: impl futures_util::Stream<Item = Result<CompletionEvent>>,
+) -> Result<CompletionResult> {
+    use tokio::pin;
+
+    pin!(events);
+
+    let mut continuations = 0;
+    let mut session: Option<models::SessionUpdate> = None;
+    let mut truncated = false;
+    let mut stop_sequence = None;
+    let mut thinking = String::new();
+    while let Some(event) = events.next().await {
+        match event? {
+            CompletionEvent::Thinking(text) => thinking.push_str(&text),
+            CompletionEvent::ContinuationStarted { .. } => continuations += 1,
+            CompletionEvent::Warning(CompletionWarning::ResponseTruncated { .. }) => {
+                truncated = true;
+            }
+            CompletionEvent::Warning(CompletionWarning::StopSequence { sequence }) => {
+                stop_sequence = Some(sequence);
+            }
+            CompletionEvent::SessionUpdated(update) => match &mut session {
+                Some(session) => session.merge(update),
+                None => session = Some(update),
+            },
+            CompletionEvent::Finished(mut message) => {
+                if let Some(session) = &mut session {
+                    session.current_message_id = session.current_message_id.or(message.message_id);
+                }
+                // A later update may have replaced thinking that was streamed before
+                if message
+                    .thinking_content
+                    .as_ref()
+                    .is_none_or(|t| t.len() < thinking.len())
+                {
+                    message.thinking_content = (!thinking.is_empty()).then_some(thinking);
+                }
+                return Ok(CompletionResult {
+                    message,
+                    continuations,
+                    session,
+                    truncated,
+                    retries: 0,
+                    stop_sequence,
+                });
+            }
+            _ => (),
+        }
+    }
+
+    anyhow::bail!("No final message received")
+}
+
+/// Ends `events` with a [`DeepSeekError::StreamTimedOut`] error if it has not
+/// finished within `timeout`.
+fn with_total_timeout(
+    events: impl futures_util::Stream<Item = Result<CompletionEvent>>,
+    timeout: Option<Duration>,
+) -> impl futures_util::Stream<Item = Result<CompletionEvent>> {
+    use async_stream::stream;
+
+    stream! {
+        let mut events = Box::pin(events);
+        let deadline = timeout.map(|timeout| (tokio::time::Instant::now() + timeout, timeout));
+        loop {
+            let event = match deadline {
+                Some((deadline, timeout)) => {
+                    let Ok(event) = tokio::time::timeout_at(deadline, events.next()).await else {
+                        yield Err(DeepSeekError::StreamTimedOut { timeout }.into());
+                        return;
+                    };
+                    event
+                }
+                None => events.next().await,
+            };
+            match event {
+                Some(event) => yield event,
+                None => return,
+            }
+        }
+    }
+}
+
+/// Builds the JSON body of a completion request.
+///
+/// Fields of `options.extra` are added unless the crate sets them itself.
+fn completion_request(
+    chat_id: &str,
+    prompt: String,
+    parent_message_id: Option<i64>,
+    options: CompletionOptions,
+) -> serde_json::Value {
+    let mut request = json!({
+        "chat_session_id": chat_id,
+        "prompt": serde_json::Value::String(prompt),
+        "parent_message_id": parent_message_id,
+        "ref_file_ids": serde_json::Value::from(options.ref_file_ids),
+        "search_enabled": options.search,
+        "thinking_enabled": options.thinking,
+    });
+    if let Some(fields) = request.as_object_mut() {
+        for (key, value) in options.extra {
+            fields.entry(key).or_insert(value);
+        }
+    }
+    request
+}
+
+/// A chat session and its messages, as returned by the history endpoint.
+#[derive(serde::Deserialize)]
+struct ChatHistory<M = models::Message> {
+    chat_session: models::ChatSession,
+    #[serde(default = "Vec::new")]
+    chat_messages: Vec<M>,
+}
+
+/// Picks the message with id `current_message_id` from `messages`, or the one
+/// with the highest id if there is no such message.
+fn last_message(
+    current_message_id: Option<i64>,
+    messages: Vec<models::Message>,
+) -> Option<models::Message> {
+    if let Some(current) = current_message_id
+        && let Some(index) = messages.iter().position(|m| m.message_id == Some(current))
+    {
+        return messages.into_iter().nth(index);
+    }
+    messages.into_iter().max_by_key(|m| m.message_id)
+}
+
+/// Summarizes `messages`, taking the token usage of the current message (see
+/// [`last_message`]).
+fn session_stats(
+    current_message_id: Option<i64>,
+    messages: Vec<models::Message>,
+) -> models::SessionStats {
+    let timestamps = messages.iter().filter_map(|m| m.inserted_at);
+    let first_message_at = timestamps.clone().reduce(f64::min);
+    let last_message_at = timestamps.reduce(f64::max);
+    let message_count = messages.len();
+    let total_tokens = last_message(current_message_id, messages)
+        .and_then(|m| m.accumulated_token_usage)
+        .unwrap_or_default();
+    models::SessionStats {
+        message_count,
+        total_tokens,
+        first_message_at,
+        last_message_at,
+    }
+}
+
+/// Returns the messages from the root of the conversation to the current
+/// message (see [`last_message`]), following `parent_id` links.
+fn current_thread(
+    current_message_id: Option<i64>,
+    messages: Vec<models::Message>,
+) -> Vec<models::Message> {
+    let mut by_id: HashMap<_, _> = messages
+        .iter()
+        .filter_map(|m| m.message_id.map(|id| (id, m.clone())))
+        .collect();
+    let mut thread = Vec::new();
+    let mut next = last_message(current_message_id, messages);
+    while let Some(message) = next {
+        next = message.parent_id.and_then(|parent| by_id.remove(&parent));
+        thread.push(message);
+    }
+    thread.reverse();
+    thread
+}
+
+/// Returns the last `count` messages by id that are older than
+/// `before_message_id`, oldest first.
+fn messages_page(
+    mut messages: Vec<models::Message>,
+    before_message_id: Option<i64>,
+    count: usize,
+) -> Vec<models::Message> {
+    if let Some(before) = before_message_id {
+        messages.retain(|m| m.message_id.is_some_and(|id| id < before));
+    }
+    messages.sort_by_key(|m| m.message_id);
+    let start = messages.len().saturating_sub(count);
+    messages.split_off(start)
+}
+
+/// Returns the error if `err` is the server rejecting thinking as unsupported.
+fn thinking_rejection(err: &anyhow::Error) -> Option<&DeepSeekError> {
+    err.downcast_ref::<DeepSeekError>().filter(
+        |e| matches!(e, DeepSeekError::UnsupportedFeature { feature, .. } if feature == "thinking"),
+    )
+}
+
+/// Returns the caller-supplied `headers` without those the crate manages.
+fn extra_headers(headers: &header::HeaderMap) -> header::HeaderMap {
+    let mut headers = headers.clone();
+    for reserved in [
+        header::AUTHORIZATION.as_str(),
+        "x-ds-pow-response",
+        REQUEST_ID_HEADER,
+        header::ACCEPT_ENCODING.as_str(),
+    ] {
+        headers.remove(reserved);
+    }
+    headers
+}
+
+/// Generates a fresh id for the [`REQUEST_ID_HEADER`] header.
+fn new_request_id() -> String {
+    uuid::Uuid::new_v4().to_string()
+}
+
+/// Attaches the [`RequestContext`] of a request to `err` and logs it.
+fn with_request_context(err: anyhow::Error, request_id: &str, path: &str) -> anyhow::Error {
+    tracing::warn!(request_id, path, error = %format!("{err:#}"), "request failed");
+    err.context(RequestContext {
+        request_id: request_id.to_string(),
+        path: path.to_string(),
+    })
+}
+
+/// Checks the status of a streaming response.
+///
+/// On errors `DeepSeek` may answer with a JSON envelope instead of an event
+/// stream; such a response is turned into an error carrying its message.
+///
+/// Streaming requests ask for identity encoding. A response that is still
+/// compressed (i.e. one the HTTP client did not decode) is rejected, as its
+/// bytes cannot be framed into events.
+async fn check_stream_response(response: reqwest::Response) -> Result<reqwest::Response> {
+    #[derive(serde::Deserialize)]
+    struct ErrorEnvelope {
+        #[serde(default)]
+        code: i64,
+        #[serde(default)]
+        msg: String,
+        data: Option<ErrorData>,
+    }
+    #[derive(serde::Deserialize)]
+    struct ErrorData {
+        biz_msg: Option<String>,
+    }
+
+    let response = response.error_for_status()?;
+    if let Some(encoding) = response
+        .headers()
+        .get(header::CONTENT_ENCODING)
+        .and_then(|v| v.to_str().ok())
+        .filter(|v| !v.eq_ignore_ascii_case("identity"))
+    {
+        anyhow::bail!("Unsupported content encoding `{encoding}` on streaming response");
+    }
+    let is_json = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("application/json"));
+    if !is_json {
+        return Ok(response);
+    }
+
+    let envelope: ErrorEnvelope = response.json().await?;
+    let message = envelope
+        .data
+        .and_then(|d| d.biz_msg)
+        .filter(|m| !m.is_empty())
+        .unwrap_or(envelope.msg);
+    if message.is_empty() {
+        anyhow::bail!("Unexpected JSON response (code {})", envelope.code);
+    }
+    Err(classify_api_error(&message, Some(envelope.code)).into())
+}
+
+/// Maps completion events to the chunks yielded by the streaming methods,
+/// adding [`StreamChunk::Image`]s if `detect_images` is set.
+fn into_chunks<'a>(
+    events: impl futures_util::Stream<Item = Result<CompletionEvent>> + 'a,
+    detect_images: bool,
+) -> impl futures_util::Stream<Item = Result<StreamChunk, DeepSeekError>> + 'a {
+    use async_stream::stream;
+
+    stream! {
+        let mut events = Box::pin(events);
+        let mut images = detect_images.then(ImageScanner::default);
+        while let Some(event) = events.next().await {
+            let chunk = match event {
+                Ok(event) => event.into_chunk(),
+                Err(e) => {
+                    yield Err(e.into());
+                    continue;
+                }
+            };
+            let Some(chunk) = chunk else {
+                continue;
+            };
+            let found = match (&chunk, images.as_mut()) {
+                (StreamChunk::Content(text), Some(images)) => images.push(text),
+                _ => Vec::new(),
+            };
+            yield Ok(chunk);
+            for image in found {
+                yield Ok(StreamChunk::Image(image));
+            }
+        }
+    }
+}
+
+/// Finds the images embedded in streamed content, see [`StreamChunk::Image`].
+#[derive(Default)]
+struct ImageScanner {
+    /// The content from the first position an image may still start at.
+    pending: String,
+}
+
+impl ImageScanner {
+    /// Adds `text` to the content, returning the images it completes.
+    fn push(&mut self, text: &str) -> Vec<models::InlineImage> {
+        use models::{IMAGE_START, ImageParse};
+
+        self.pending.push_str(text);
+        let mut images = Vec::new();
+        let mut scanned = 0;
+        loop {
+            let rest = &self.pending[scanned..];
+            let Some(start) = rest.find(IMAGE_START) else {
+                // Keep a trailing `!` that may open an image
+                scanned = self.pending.len() - usize::from(rest.ends_with('!'));
+                break;
+            };
+            let start = scanned + start;
+            match models::parse_image(&self.pending[start..]) {
+                ImageParse::Image(image, end) => {
+                    images.push(image);
+                    scanned = start + end;
+                }
+                ImageParse::Incomplete => {
+                    scanned = start;
+                    break;
+                }
+                ImageParse::Invalid => scanned = start + IMAGE_START.len(),
+            }
+        }
+        self.pending.drain(..scanned);
+        images
+    }
+}
+
+/// Drives `events` on a separate task, letting it run up to `capacity` events
+/// ahead of the consumer.
+fn buffered(
+    events: impl futures_util::Stream<Item = Result<CompletionEvent>> + Send + 'static,
+    capacity: usize,
+) -> impl futures_util::Stream<Item = Result<CompletionEvent>> {
+    use async_stream::stream;
+
+    let (tx, mut rx) = tokio::sync::mpsc::channel(capacity.max(1));
+    tokio::spawn(async move {
+        let mut events = std::pin::pin!(events);
+        while let Some(event) = events.next().await {
+            if tx.send(event).await.is_err() {
+                // The consumer dropped the stream
+                break;
+            }
+        }
+    });
+    stream! {
+        while let Some(event) = rx.recv().await {
+            yield event;
+        }
+    }
+}
+
+/// Returns the error reported by a data line of type `error`, if it is one.
+fn data_line_error(data_json: &[u8]) -> Option<anyhow::Error> {
+    let val = serde_json::from_slice::<serde_json::Value>(data_json).ok()?;
+    if val.get("type").and_then(|t| t.as_str()) != Some("error") {
+        return None;
+    }
+    let content = val.get("content").and_then(|c| c.as_str())?;
+    let code = val.get("code").and_then(serde_json::Value::as_i64);
+    Some(classify_api_error(content, code).into())
+}
+
+/// Reads the next chunk of a response body, failing with
+/// [`DeepSeekError::StreamStalled`] if none arrives within `heartbeat_timeout`.
+async fn next_chunk(
+    bytes: &mut (impl futures_util::Stream<Item = reqwest::Result<bytes::Bytes>> + Unpin),
+    heartbeat_timeout: Option<Duration>,
+) -> Result<Option<bytes::Bytes>> {
+    let chunk = match heartbeat_timeout {
+        Some(timeout) => tokio::time::timeout(timeout, bytes.next())
+            .await
+            .map_err(|_| DeepSeekError::StreamStalled { timeout })?,
+        None => bytes.next().await,
+    };
+    Ok(chunk.transpose()?)
+}
+
+/// Splits a response body into lines, without their trailing newline. A last
+/// line that is not terminated by a newline is dropped.
+fn response_lines(
+    response: reqwest::Response,
+    heartbeat_timeout: Option<Duration>,
+) -> impl futures_util::Stream<Item = Result<bytes::BytesMut>> {
+    use async_stream::stream;
+    stream! {
+        let mut buffer = bytes::BytesMut::new();
+        let mut bytes = std::pin::pin!(response.bytes_stream());
+        loop {
+            let chunk = match next_chunk(&mut bytes, heartbeat_timeout).await {
+                Ok(Some(c)) => c,
+                Ok(None) => break,
+                Err(e) => {
+                    yield Err(e);
+                    return;
+                }
+            };
+            buffer.extend_from_slice(&chunk);
+            while let Some(pos) = buffer.iter().position(|&b| b == b'\n') {
+                let line = buffer.split_to(pos);
+                buffer.advance(1); // consume newline
+                yield Ok(line);
+            }
+        }
+    }
+}
+
+/// Turns the lines of an event stream into [`RawSseEvent`]s, see
+/// [`DeepSeekAPI::complete_raw_stream`].
+fn raw_sse_events(
+    lines: impl futures_util::Stream<Item = Result<bytes::BytesMut>>,
+) -> impl futures_util::Stream<Item = Result<RawSseEvent>> {
+    use async_stream::stream;
+    stream! {
+        let mut lines = std::pin::pin!(lines);
+        let mut event: Option<String> = None;
+        // Whether `event` was named by an `event:` line no data line followed yet
+        let mut pending = false;
+        while let Some(line) = lines.next().await {
+            let line = match line {
+                Ok(line) => line,
+                Err(e) => {
+                    yield Err(e);
+                    return;
+                }
+            };
+            let line = String::from_utf8_lossy(&line);
+            let line = line.strip_suffix('\r').unwrap_or(&line);
+            if let Some(name) = line.strip_prefix("event:") {
+                if pending {
+                    yield Ok(RawSseEvent { event: event.clone(), data: String::new() });
+                }
+                event = Some(name.trim().to_string());
+                pending = true;
+            } else if let Some(data) = line.strip_prefix("data:") {
+                let data = data.strip_prefix(' ').unwrap_or(data).to_string();
+                yield Ok(RawSseEvent { event: event.clone(), data });
+                pending = false;
+            } else if line.is_empty() {
+                // A blank line ends the event
+                if pending {
+                    yield Ok(RawSseEvent { event: event.clone(), data: String::new() });
+                }
+                event = None;
+                pending = false;
+            }
+        }
+        if pending {
+            yield Ok(RawSseEvent { event, data: String::new() });
+        }
+    }
+}
+
+/// Logs a data line skipped in lenient parsing mode.
+fn skip_malformed_line(err: &anyhow::Error, data_json: &[u8]) {
+    tracing::warn!(
+        error = %format!("{err:#}"),
+        line = %String::from_utf8_lossy(data_json),
+        "skipping malformed data line"
+    );
+}
+
+// Helper to turn an HTTP response into a stream of completion events.
+//
+// Events are framed by `raw_sse_events`, as for `complete_raw_stream`.
+//
+// The final message is complete at `event: finish`, but the server goes on to
+// report session metadata (`update_session`, `title`) before it sends
+// `event: close` and ends the response. `Finished` is yielded once the response
+// ends, preceded by a `SessionUpdated` event if any metadata was received.
+//
+// With `lenient` set, data lines that cannot be parsed or applied are logged and
+// skipped; API errors and connection failures still end the stream. `resume` is
+// set when the response continues a message, to drop text it repeats.
+fn response_to_event_stream(
+    response: reqwest::Response,
+    heartbeat_timeout: Option<Duration>,
+    lenient: bool,
+    resume: Option<Resume>,
+) -> impl futures_util::Stream<Item = Result<CompletionEvent>> {
+    use async_stream::stream;
+    stream! {
+        let mut parser = SseParser::new();
+        parser.resume = resume;
+        let mut finished = None;
+        let mut session: Option<models::SessionUpdate> = None;
+
+        let lines = response_lines(response, heartbeat_timeout);
+        let mut events = std::pin::pin!(raw_sse_events(lines));
+        while let Some(raw) = events.next().await {
+            let RawSseEvent { event, data } = match raw {
+                Ok(raw) => raw,
+                Err(e) => {
+                    yield Err(e);
+                    return;
+                }
+            };
+            let data_json = data.as_bytes();
+            match event.as_deref() {
+                Some("finish") => {
+                    // Data lines of the finish event, if any, carry nothing new
+                    if finished.is_none() {
+                        let parser = std::mem::replace(&mut parser, SseParser::new());
+                        match parser.finish() {
+                            Ok(final_msg) => finished = Some(final_msg),
+                            Err(e) => {
+                                yield Err(e);
+                                return;
+                            }
+                        }
+                    }
+                    continue;
+                }
+                Some("close") => break,
+                _ if data.is_empty() => continue,
+                Some("toast") => {
+                    parser.process_toast_line(data_json);
+                    continue;
+                }
+                Some(name @ ("update_session" | "title")) => {
+                    let update = session.get_or_insert_default();
+                    match update.apply_event(name.as_bytes(), data_json) {
+                        Ok(()) => {}
+                        Err(e) if lenient => skip_malformed_line(&e, data_json),
+                        Err(e) => {
+                            yield Err(e);
+                            return;
+                        }
+                    }
                     continue;
                 }
-                if !line.starts_with(b"data: ") {
-                    continue;
+                _ if finished.is_some() => continue,
+                // Data of other events is read like that of unnamed ones
+                _ => (),
+            }
+            if let Some(e) = data_line_error(data_json) {
+                yield Err(e);
+                return;
+            }
+            match parser.process_data_line(data_json) {
+                Ok(Some(chunk)) => yield Ok(chunk),
+                Ok(None) => {},
+                Err(e) if lenient => skip_malformed_line(&e, data_json),
+                Err(e) => {
+                    yield Err(e);
+                    return;
+                }
+            }
+        }
+        if finished.is_none()
+            && let Some(err) = parser.toast_error
+        {
+            // The server gave up without finishing the message
+            yield Err(err.into());
+            return;
+        }
+        if let Some(final_msg) = finished {
+            if let Some(session) = session {
+                yield Ok(CompletionEvent::SessionUpdated(session));
+            }
+            yield Ok(CompletionEvent::Finished(final_msg));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(headers: &[(&str, &str)], body: &'static str) -> reqwest::Response {
+        let mut builder = http::Response::builder().status(200);
+        for (name, value) in headers {
+            builder = builder.header(*name, *value);
+        }
+        builder.body(body).unwrap().into()
+    }
+
+    #[tokio::test]
+    async fn compressed_stream_response_is_rejected() {
+        let response = response(
+            &[
+                ("content-type", "text/event-stream"),
+                ("content-encoding", "gzip"),
+            ],
+            "\x1f\u{8b}",
+        );
+        let err = check_stream_response(response).await.unwrap_err();
+        assert!(err.to_string().contains("gzip"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn identity_stream_response_is_accepted() {
+        let response = response(
+            &[
+                ("content-type", "text/event-stream"),
+                ("content-encoding", "identity"),
+            ],
+            "data: {}\n",
+        );
+        assert!(check_stream_response(response).await.is_ok());
+    }
+
+    #[test]
+    fn request_context_keeps_typed_error() {
+        let err = with_request_context(
+            classify_api_error("thinking is not supported", None).into(),
+            "abc",
+            "/api/v0/chat/completion",
+        );
+        let context = err.downcast_ref::<RequestContext>().unwrap();
+        assert_eq!(context.request_id, "abc");
+        assert_eq!(context.path, "/api/v0/chat/completion");
+        assert!(thinking_rejection(&err).is_some());
+    }
+
+    #[tokio::test]
+    async fn lenient_parsing_skips_malformed_lines() {
+        let body = concat!(
+            "data: {\"v\":{\"response\":{\"message_id\":2}}}\n",
+            "data: {\"p\":\"response/content\",\"o\":\"APPEND\",\"v\":\"Hi\"}\n",
+            "data: {\"p\":\"response/content\",\"o\":\"APPEND\",\"v\":7}\n",
+            "data: {\"p\":\"response/content\",\"o\":\"APPEND\",\"v\":\"!\"}\n",
+            "event: finish\n",
+        );
+        let strict: Vec<_> = response_to_event_stream(response(&[], body), None, false, None)
+            .collect()
+            .await;
+        assert!(strict.last().unwrap().is_err());
+
+        let lenient: Vec<_> = response_to_event_stream(response(&[], body), None, true, None)
+            .map(Result::unwrap)
+            .collect()
+            .await;
+        let Some(CompletionEvent::Finished(message)) = lenient.last() else {
+            panic!("expected a final message, got {lenient:?}");
+        };
+        assert_eq!(message.content, "Hi!");
+
+        let error = "data: {\"type\":\"error\",\"content\":\"boom\"}\n";
+        let events: Vec<_> = response_to_event_stream(response(&[], error), None, true, None)
+            .collect()
+            .await;
+        assert!(matches!(events.as_slice(), [Err(_)]));
+    }
+
+    async fn collect_events(body: &'static str) -> Vec<CompletionEvent> {
+        let response = response(&[("content-type", "text/event-stream")], body);
+        response_to_event_stream(response, None, false, None)
+            .map(Result::unwrap)
+            .collect()
+            .await
+    }
+
+    #[tokio::test]
+    async fn event_name_ends_at_blank_line() {
+        let events = collect_events(concat!(
+            "data: {\"v\":{\"response\":{\"message_id\":2}}}\n",
+            "data: {\"p\":\"response/content\",\"o\":\"APPEND\",\"v\":\"Hel\"}\n",
+            "\n",
+            "event: update_session\n",
+            "data: {\"updated_at\":1.5}\n",
+            "\n",
+            "data: {\"p\":\"response/content\",\"o\":\"APPEND\",\"v\":\"lo\"}\n",
+            "data: {\"p\":\"response/status\",\"v\":\"FINISHED\"}\n",
+            "\n",
+            "event: finish\n",
+        ))
+        .await;
+        let Some(CompletionEvent::Finished(message)) = events.last() else {
+            panic!("expected a final message, got {events:?}");
+        };
+        assert_eq!(message.content, "Hello");
+        assert_eq!(message.status, Some(models::MessageStatus::Finished));
+        assert!(
+            events
+                .iter()
+                .any(|event| matches!(event, CompletionEvent::SessionUpdated(_)))
+        );
+    }
+
+    #[tokio::test]
+    async fn finish_event_without_space_is_recognized() {
+        let events = collect_events(concat!(
+            "data: {\"v\":{\"response\":{\"message_id\":2}}}\n",
+            "data: {\"p\":\"response/content\",\"o\":\"APPEND\",\"v\":\"Hi\"}\n",
+            "event:finish\n",
+        ))
+        .await;
+        assert!(matches!(
+            events.last(),
+            Some(CompletionEvent::Finished(msg)) if msg.content == "Hi"
+        ));
+    }
+
+    #[tokio::test]
+    async fn silent_stream_stalls() {
+        let body = futures_util::stream::iter([Ok::<_, std::io::Error>(
+            "data: {\"v\":{\"response\":{\"message_id\":2}}}\n",
+        )])
+        .chain(futures_util::stream::pending());
+        let response: reqwest::Response = http::Response::builder()
+            .body(reqwest::Body::wrap_stream(body))
+            .unwrap()
+            .into();
+        let timeout = Duration::from_millis(50);
+        let events: Vec<_> = response_to_event_stream(response, Some(timeout), false, None)
+            .collect()
+            .await;
+        assert!(matches!(
+            events.as_slice(),
+            [Ok(CompletionEvent::Started { message_id: Some(2) }), Err(e)]
+                if e.downcast_ref::<DeepSeekError>()
+                    == Some(&DeepSeekError::StreamStalled { timeout })
+        ));
+    }
+
+    #[tokio::test]
+    async fn slow_stream_times_out() {
+        let events = futures_util::stream::iter([Ok(CompletionEvent::Content("Hi".to_string()))])
+            .chain(futures_util::stream::pending());
+        let timeout = Duration::from_millis(50);
+        let events: Vec<_> = with_total_timeout(events, Some(timeout)).collect().await;
+        assert!(matches!(
+            events.as_slice(),
+            [Ok(CompletionEvent::Content(text)), Err(e)]
+                if text == "Hi"
+                    && e.downcast_ref::<DeepSeekError>() == Some(&DeepSeekError::StreamTimedOut { timeout })
+        ));
+    }
+
+    #[tokio::test]
+    async fn unknown_event_is_ignored() {
+        let events = collect_events(concat!(
+            "data: {\"v\":{\"response\":{\"message_id\":2}}}\n",
+            "event: ping\n",
+            "data: {\"p\":\"response/content\",\"o\":\"APPEND\",\"v\":\"Hi\"}\n",
+            "event: finish\n",
+        ))
+        .await;
+        assert!(matches!(events[1], CompletionEvent::Content(ref text) if text == "Hi"));
+        assert!(matches!(events.last(), Some(CompletionEvent::Finished(_))));
+    }
+
+    fn test_message(id: i64) -> models::Message {
+        models::Message {
+            message_id: Some(id),
+            parent_id: None,
+            role: None,
+            inserted_at: None,
+            content: id.to_string(),
+            thinking_content: None,
+            status: None,
+            accumulated_token_usage: None,
+            citations: None,
+        }
+    }
+
+    #[test]
+    fn last_message_follows_current_message_id() {
+        let messages = vec![test_message(1), test_message(3), test_message(2)];
+        assert_eq!(
+            last_message(Some(2), messages.clone()).unwrap().content,
+            "2"
+        );
+        assert_eq!(last_message(None, messages).unwrap().content, "3");
+        assert!(last_message(None, Vec::new()).is_none());
+    }
+
+    #[test]
+    fn session_stats_summarize_messages() {
+        let mut messages: Vec<_> = [1, 2, 3].map(test_message).into();
+        for (message, (at, tokens)) in messages.iter_mut().zip([(10.0, 5), (30.0, 40), (20.0, 25)])
+        {
+            message.inserted_at = Some(at);
+            message.accumulated_token_usage = Some(tokens);
+        }
+        let stats = session_stats(Some(2), messages);
+        assert_eq!(stats.message_count, 3);
+        assert_eq!(stats.total_tokens, 40);
+        assert_eq!(stats.first_message_at, Some(10.0));
+        assert_eq!(stats.last_message_at, Some(30.0));
+        assert_eq!(
+            session_stats(None, Vec::new()),
+            models::SessionStats::default()
+        );
+    }
+
+    #[test]
+    fn current_thread_follows_parents() {
+        let mut messages: Vec<_> = [1, 2, 3, 4].map(test_message).into();
+        // 1 <- 2 <- 4, with 3 on another branch from 1
+        messages[1].parent_id = Some(1);
+        messages[2].parent_id = Some(1);
+        messages[3].parent_id = Some(2);
+        let ids: Vec<_> = current_thread(Some(4), messages)
+            .into_iter()
+            .filter_map(|m| m.message_id)
+            .collect();
+        assert_eq!(ids, [1, 2, 4]);
+    }
+
+    #[test]
+    fn messages_page_walks_backwards() {
+        let messages: Vec<_> = [4, 1, 3, 5, 2].map(test_message).into();
+        let ids = |page: Vec<models::Message>| -> Vec<_> {
+            page.into_iter().filter_map(|m| m.message_id).collect()
+        };
+        assert_eq!(ids(messages_page(messages.clone(), None, 2)), [4, 5]);
+        assert_eq!(ids(messages_page(messages.clone(), Some(4), 2)), [2, 3]);
+        assert_eq!(ids(messages_page(messages.clone(), Some(2), 2)), [1]);
+        assert!(messages_page(messages, Some(1), 2).is_empty());
+    }
+
+    #[tokio::test]
+    async fn disabled_pow_builds_without_solver() {
+        let api = DeepSeekAPI::builder("token")
+            .pow_response("proxy")
+            .build()
+            .await
+            .unwrap();
+        assert!(api.pow_solver.is_none());
+        let request = api
+            .set_pow_header(
+                api.client.post(DEFAULT_BASE_URL),
+                "/api/v0/chat/completion",
+                "abc",
+            )
+            .await
+            .unwrap()
+            .build()
+            .unwrap();
+        assert_eq!(request.headers()["x-ds-pow-response"], "proxy");
+    }
+
+    #[tokio::test]
+    async fn session_metadata_after_finish_is_reported() {
+        let events = collect_events(concat!(
+            "data: {\"v\":{\"response\":{\"message_id\":2}}}\n",
+            "event: finish\n",
+            "data: {\"click_behavior\":\"none\"}\n",
+            "event: update_session\n",
+            "data: {\"updated_at\":1700000000.5}\n",
+            "event: title\n",
+            "data: {\"content\":\"Greeting\"}\n",
+            "event: close\n",
+            "data: {\"click_behavior\":\"none\"}\n",
+        ))
+        .await;
+        let [
+            ..,
+            CompletionEvent::SessionUpdated(session),
+            CompletionEvent::Finished(_),
+        ] = events.as_slice()
+        else {
+            panic!("unexpected events: {events:?}");
+        };
+        assert_eq!(session.updated_at, Some(1_700_000_000.5));
+        assert_eq!(session.title.as_deref(), Some("Greeting"));
+    }
+
+    #[tokio::test]
+    async fn cancel_all_aborts_streams() {
+        let stopped = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let api = DeepSeekAPI::builder("token")
+            .disable_pow()
+            .middleware({
+                let stopped = Arc::clone(&stopped);
+                move |request: reqwest::Request, _next| {
+                    let stopped = Arc::clone(&stopped);
+                    async move {
+                        if request.url().path().ends_with("/chat/stop_stream") {
+                            stopped.store(true, std::sync::atomic::Ordering::SeqCst);
+                            return Ok(response(&[], r#"{"code":0,"msg":""}"#));
+                        }
+                        let body = futures_util::stream::iter([Ok::<_, std::io::Error>(concat!(
+                            "data: {\"v\":{\"response\":{\"message_id\":2}}}\n",
+                            "data: {\"p\":\"response/content\",\"o\":\"APPEND\",\"v\":\"Hi\"}\n",
+                        ))])
+                        .chain(futures_util::stream::pending());
+                        Ok(http::Response::builder()
+                            .header("content-type", "text/event-stream")
+                            .body(reqwest::Body::wrap_stream(body))?
+                            .into())
+                    }
+                }
+            })
+            .build()
+            .await
+            .unwrap();
+
+        let (handle, stream) = api.complete_stream_with_handle(
+            "chat".to_string(),
+            "Hi".to_string(),
+            None,
+            false,
+            false,
+            vec![],
+        );
+        let mut stream = std::pin::pin!(stream);
+        assert!(matches!(
+            stream.next().await,
+            Some(Ok(StreamChunk::Content(ref text))) if text == "Hi"
+        ));
+        api.clone().cancel_all();
+        assert!(stream.next().await.is_none());
+        assert!(handle.is_aborted());
+        assert!(stopped.load(std::sync::atomic::Ordering::SeqCst));
+
+        let (handle, _stream) = api.complete_stream_with_handle(
+            "chat".to_string(),
+            "Hi".to_string(),
+            None,
+            false,
+            false,
+            vec![],
+        );
+        assert!(!handle.is_aborted());
+    }
+
+    #[tokio::test]
+    async fn resumed_continuation_is_deduplicated() {
+        let api = DeepSeekAPI::builder("token")
+            .disable_pow()
+            .middleware(|request: reqwest::Request, _next| async move {
+                let body = if request.url().path().ends_with("/chat/continue") {
+                    // Resumed from a checkpoint before "wor", which is streamed again
+                    concat!(
+                        "data: {\"v\":{\"response\":{\"message_id\":2,\"content\":\"Hello \"}}}\n",
+                        "data: {\"p\":\"response/content\",\"o\":\"APPEND\",\"v\":\"wo\"}\n",
+                        "data: {\"v\":\"rld!\"}\n",
+                        "data: {\"p\":\"response/status\",\"v\":\"FINISHED\"}\n",
+                        "event: finish\n",
+                    )
+                } else {
+                    concat!(
+                        "data: {\"v\":{\"response\":{\"message_id\":2}}}\n",
+                        "data: {\"p\":\"response/content\",\"o\":\"APPEND\",\"v\":\"Hello wor\"}\n",
+                        "data: {\"p\":\"response/status\",\"v\":\"INCOMPLETE\"}\n",
+                        "event: finish\n",
+                    )
+                };
+                Ok(response(&[("content-type", "text/event-stream")], body))
+            })
+            .build()
+            .await
+            .unwrap();
+
+        let chunks: Vec<_> = api
+            .complete_stream(
+                "chat".to_string(),
+                "Hi".to_string(),
+                None,
+                false,
+                false,
+                vec![],
+            )
+            .map(Result::unwrap)
+            .collect()
+            .await;
+        let content: String = chunks
+            .iter()
+            .filter_map(|chunk| match chunk {
+                StreamChunk::Content(text) => Some(text.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(content, "Hello world!");
+        let Some(StreamChunk::Message(message)) = chunks.last() else {
+            panic!("expected a final message, got {chunks:?}");
+        };
+        assert_eq!(message.content, "Hello world!");
+    }
+
+    #[tokio::test]
+    async fn continuation_without_snapshot_is_not_replayed() {
+        let api = DeepSeekAPI::mock(|request| {
+            Ok(mock::event_stream(
+                if request.url().path().ends_with("/chat/continue") {
+                    // Only new text, which happens to start like the text so far
+                    concat!(
+                        "data: {\"v\":{\"response\":{\"message_id\":2}}}\n",
+                        "data: {\"p\":\"response/content\",\"o\":\"APPEND\",\"v\":\"Hi\"}\n",
+                        "data: {\"v\":\" there!\"}\n",
+                        "data: {\"p\":\"response/status\",\"v\":\"FINISHED\"}\n",
+                        "event: finish\n",
+                    )
+                } else {
+                    concat!(
+                        "data: {\"v\":{\"response\":{\"message_id\":2}}}\n",
+                        "data: {\"p\":\"response/content\",\"o\":\"APPEND\",\"v\":\"Hi. \"}\n",
+                        "data: {\"p\":\"response/status\",\"v\":\"INCOMPLETE\"}\n",
+                        "event: finish\n",
+                    )
+                },
+            ))
+        });
+
+        let chunks: Vec<_> = api
+            .complete_stream(
+                "chat".to_string(),
+                "Hi".to_string(),
+                None,
+                false,
+                false,
+                vec![],
+            )
+            .map(Result::unwrap)
+            .collect()
+            .await;
+        let content: String = chunks
+            .iter()
+            .filter_map(|chunk| match chunk {
+                StreamChunk::Content(text) => Some(text.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(content, "Hi. Hi there!");
+        let Some(StreamChunk::Message(message)) = chunks.last() else {
+            panic!("expected a final message, got {chunks:?}");
+        };
+        assert_eq!(message.content, "Hi. Hi there!");
+    }
+
+    #[tokio::test]
+    async fn unauthorized_continuation_refreshes_token() {
+        // Rejects the first continuation request of each client
+        let middleware = || {
+            let rejected = Arc::new(std::sync::atomic::AtomicBool::new(false));
+            move |request: reqwest::Request, _next| {
+                let rejected = Arc::clone(&rejected);
+                async move {
+                    let body = if request.url().path().ends_with("/chat/continue") {
+                        if !rejected.swap(true, std::sync::atomic::Ordering::SeqCst) {
+                            return Ok(http::Response::builder()
+                                .status(401)
+                                .body("")
+                                .unwrap()
+                                .into());
+                        }
+                        concat!(
+                            "data: {\"v\":{\"response\":{\"message_id\":2,\"content\":\"Hello \"}}}\n",
+                            "data: {\"p\":\"response/content\",\"o\":\"APPEND\",\"v\":\"world\"}\n",
+                            "data: {\"p\":\"response/status\",\"v\":\"FINISHED\"}\n",
+                            "event: finish\n",
+                        )
+                    } else {
+                        concat!(
+                            "data: {\"v\":{\"response\":{\"message_id\":2,\"content\":\"Hello \"}}}\n",
+                            "data: {\"p\":\"response/status\",\"v\":\"INCOMPLETE\"}\n",
+                            "event: finish\n",
+                        )
+                    };
+                    Ok(response(&[("content-type", "text/event-stream")], body))
+                }
+            }
+        };
+        let complete = |api: DeepSeekAPI| async move {
+            api.complete("chat", "Hi", None, false, false, vec![]).await
+        };
+
+        let api = DeepSeekAPI::builder("token")
+            .disable_pow()
+            .middleware(middleware())
+            .build()
+            .await
+            .unwrap();
+        let err = complete(api).await.unwrap_err();
+        assert!(matches!(
+            &err,
+            DeepSeekError::Unauthorized { message_id: Some(2), request: Some(request) }
+                if request.path == "/api/v0/chat/continue"
+        ));
+
+        let refreshes = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let api = DeepSeekAPI::builder("token")
+            .disable_pow()
+            .middleware(middleware())
+            .token_refresh({
+                let refreshes = Arc::clone(&refreshes);
+                move || {
+                    refreshes.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                    async { Ok("fresh".to_string()) }
+                }
+            })
+            .build()
+            .await
+            .unwrap();
+        let message = complete(api.clone()).await.unwrap();
+        assert_eq!(message.content, "Hello world");
+        assert_eq!(refreshes.load(std::sync::atomic::Ordering::SeqCst), 1);
+        assert_eq!(api.token.lock().unwrap().as_str(), "fresh");
+    }
+
+    #[tokio::test]
+    async fn stop_sequence_ends_response() {
+        let stopped = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let api = DeepSeekAPI::builder("token")
+            .disable_pow()
+            .middleware({
+                let stopped = Arc::clone(&stopped);
+                move |request: reqwest::Request, _next| {
+                    let stopped = Arc::clone(&stopped);
+                    async move {
+                        if request.url().path().ends_with("/chat/stop_stream") {
+                            stopped.store(true, std::sync::atomic::Ordering::SeqCst);
+                            return Ok(response(&[], r#"{"code":0,"msg":""}"#));
+                        }
+                        let body = concat!(
+                            "data: {\"v\":{\"response\":{\"message_id\":2}}}\n",
+                            "data: {\"p\":\"response/content\",\"o\":\"APPEND\",\"v\":\"a < b </\"}\n",
+                            "data: {\"v\":\"end> more\"}\n",
+                            "event: finish\n",
+                        );
+                        Ok(response(&[("content-type", "text/event-stream")], body))
+                    }
+                }
+            })
+            .build()
+            .await
+            .unwrap();
+
+        let events: Vec<_> = api
+            .complete_events_with(
+                "chat".to_string(),
+                "Hi".to_string(),
+                None,
+                CompletionOptions {
+                    stop_sequences: vec!["</end>".to_string()],
+                    ..CompletionOptions::default()
+                },
+            )
+            .map(Result::unwrap)
+            .collect()
+            .await;
+        let content: Vec<_> = events
+            .iter()
+            .filter_map(|event| match event {
+                CompletionEvent::Content(text) => Some(text.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(content, ["a < b "]);
+        assert!(stopped.load(std::sync::atomic::Ordering::SeqCst));
+
+        let result = collect_result(futures_util::stream::iter(events.into_iter().map(Ok)))
+            .await
+            .unwrap();
+        assert_eq!(result.message.content, "a < b ");
+        assert_eq!(result.stop_sequence.as_deref(), Some("</end>"));
+    }
+
+    #[tokio::test]
+    async fn warm_up_opens_connection_on_build() {
+        let requests = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let record = |requests: &Arc<std::sync::Mutex<Vec<String>>>| {
+            let requests = Arc::clone(requests);
+            move |request: reqwest::Request, _next| {
+                let requests = Arc::clone(&requests);
+                async move {
+                    requests.lock().unwrap().push(format!(
+                        "{} {}",
+                        request.method(),
+                        request.url()
+                    ));
+                    Ok(response(&[], ""))
                 }
-                let data_json = &line[6..];
-                match parser.process_data_line(data_json) {
-                    Ok(Some(chunk)) => yield Ok(chunk),
-                    Ok(None) => {},
-                    Err(e) => {
-                        yield Err(e);
-                        return;
+            }
+        };
+        DeepSeekAPI::builder("token")
+            .disable_pow()
+            .middleware(record(&requests))
+            .build()
+            .await
+            .unwrap();
+        assert!(requests.lock().unwrap().is_empty());
+
+        DeepSeekAPI::builder("token")
+            .disable_pow()
+            .warm_up(true)
+            .middleware(record(&requests))
+            .build()
+            .await
+            .unwrap();
+        assert_eq!(
+            *requests.lock().unwrap(),
+            ["HEAD https://chat.deepseek.com/"]
+        );
+    }
+
+    #[test]
+    fn images_split_across_chunks_are_found() {
+        let mut scanner = ImageScanner::default();
+        assert!(scanner.push("See ![a](file-").is_empty());
+        let images = scanner.push("x) and !");
+        assert_eq!(images.len(), 1);
+        assert_eq!(images[0].file_id(), Some("file-x"));
+        let images = scanner.push("[b](https://example.com/b.png)!");
+        assert_eq!(images[0].alt, "b");
+        assert_eq!(scanner.pending, "!");
+    }
+
+    #[tokio::test]
+    async fn images_are_detected_when_enabled() {
+        let api = DeepSeekAPI::mock(|_| {
+            Ok(mock::event_stream(concat!(
+                "data: {\"v\":{\"response\":{\"message_id\":2}}}\n",
+                "data: {\"p\":\"response/content\",\"o\":\"APPEND\",\"v\":\"See ![a](file-\"}\n",
+                "data: {\"p\":\"response/content\",\"o\":\"APPEND\",\"v\":\"x)\"}\n",
+                "event: finish\n",
+            )))
+        });
+        let images = |chunks: Vec<Result<StreamChunk, DeepSeekError>>| {
+            chunks
+                .into_iter()
+                .filter_map(|chunk| match chunk.unwrap() {
+                    StreamChunk::Image(image) => Some(image.url_or_data),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let chunks = api
+            .complete_stream(
+                "chat".to_string(),
+                "Hi".to_string(),
+                None,
+                false,
+                false,
+                vec![],
+            )
+            .collect()
+            .await;
+        assert!(images(chunks).is_empty());
+
+        let api = DeepSeekAPI {
+            detect_images: true,
+            ..api
+        };
+        let chunks = api
+            .complete_stream(
+                "chat".to_string(),
+                "Hi".to_string(),
+                None,
+                false,
+                false,
+                vec![],
+            )
+            .collect()
+            .await;
+        assert_eq!(images(chunks), ["file-x"]);
+    }
+
+    #[tokio::test]
+    async fn mock_client_answers_offline() {
+        let api = DeepSeekAPI::mock(|request| {
+            Ok(match request.url().path() {
+                "/api/v0/chat_session/create" => mock::json(&json!({
+                    "data": {"biz_data": {
+                        "id": "chat", "seq_id": 1, "agent": "chat", "title": null,
+                        "title_type": "DEFAULT", "version": 0, "current_message_id": null,
+                        "pinned": false, "inserted_at": 1.0, "updated_at": 1.0
+                    }}
+                })),
+                _ => mock::event_stream(concat!(
+                    "data: {\"v\":{\"response\":{\"message_id\":2}}}\n",
+                    "data: {\"p\":\"response/content\",\"o\":\"APPEND\",\"v\":\"Hi\"}\n",
+                    "event: finish\n",
+                )),
+            })
+        });
+        let chat = api.create_chat().await.unwrap();
+        let message = api
+            .complete(&chat.id, "Hello", None, false, false, vec![])
+            .await
+            .unwrap();
+        assert_eq!(message.content, "Hi");
+        assert!(api.wasm_source().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn api_error_code_is_typed() {
+        let api = DeepSeekAPI::mock(|_| {
+            Ok(mock::json(&json!({
+                "code": 40300, "msg": "Chat session not found", "data": null
+            })))
+        });
+        let err = api.get_chat_info("chat").await.unwrap_err();
+        assert_eq!(
+            err,
+            DeepSeekError::Api {
+                code: Some(40300),
+                message: "Chat session not found".to_string(),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn incomplete_message_ends_stream_without_auto_continue() {
+        let api = DeepSeekAPI::mock(|request| {
+            assert!(
+                !request.url().path().ends_with("/chat/continue"),
+                "continuation requested"
+            );
+            Ok(mock::event_stream(concat!(
+                "data: {\"v\":{\"response\":{\"message_id\":2}}}\n",
+                "data: {\"p\":\"response/content\",\"o\":\"APPEND\",\"v\":\"Hel\"}\n",
+                "data: {\"p\":\"response/status\",\"v\":\"INCOMPLETE\"}\n",
+                "event: finish\n",
+            )))
+        });
+        let chunks: Vec<_> = CompletionRequest::new("chat", "Hi")
+            .auto_continue(false)
+            .stream(&api)
+            .map(Result::unwrap)
+            .collect()
+            .await;
+        let Some(StreamChunk::Message(message)) = chunks.last() else {
+            panic!("expected a final message, got {chunks:?}");
+        };
+        assert_eq!(message.status, Some(models::MessageStatus::Incomplete));
+        assert_eq!(message.message_id, Some(2));
+        assert_eq!(message.content, "Hel");
+    }
+
+    #[tokio::test]
+    async fn continuations_are_capped() {
+        let api = DeepSeekAPI::builder("token")
+            .disable_pow()
+            .max_continuations(2)
+            .middleware(|_, _next| async {
+                Ok(response(
+                    &[("content-type", "text/event-stream")],
+                    concat!(
+                        "data: {\"v\":{\"response\":{\"message_id\":2}}}\n",
+                        "data: {\"p\":\"response/content\",\"o\":\"APPEND\",\"v\":\"more\"}\n",
+                        "data: {\"p\":\"response/status\",\"v\":\"INCOMPLETE\"}\n",
+                        "event: finish\n",
+                    ),
+                ))
+            })
+            .build()
+            .await
+            .unwrap();
+        let result = api
+            .complete_detailed("chat", "Think forever", None, false, false, vec![])
+            .await
+            .unwrap();
+        assert_eq!(result.continuations, 2);
+        assert_eq!(
+            result.message.status,
+            Some(models::MessageStatus::Incomplete)
+        );
+        assert_eq!(result.message.message_id, Some(2));
+
+        let chunks: Vec<_> = CompletionRequest::new("chat", "Think forever")
+            .max_continuations(0)
+            .stream(&api)
+            .map(Result::unwrap)
+            .collect()
+            .await;
+        assert!(matches!(
+            chunks.as_slice(),
+            [
+                ..,
+                StreamChunk::Warning(CompletionWarning::ContinuationLimit { limit: 0 }),
+                StreamChunk::Message(_)
+            ]
+        ));
+    }
+
+    #[tokio::test]
+    async fn search_results_are_streamed() {
+        let api = DeepSeekAPI::mock(|_| {
+            Ok(mock::event_stream(concat!(
+                "data: {\"v\":{\"response\":{\"message_id\":2}}}\n",
+                "data: {\"p\":\"response/search_results\",\"v\":[{\"url\":\"https://example.com\",",
+                "\"title\":\"Example\",\"snippet\":\"An example\",\"cite_index\":1}]}\n",
+                "data: {\"p\":\"response/content\",\"o\":\"APPEND\",\"v\":\"Hi[citation:1]\"}\n",
+                "event: finish\n",
+            )))
+        });
+        let chunks: Vec<_> = CompletionRequest::new("chat", "Hi")
+            .search(true)
+            .stream(&api)
+            .map(Result::unwrap)
+            .collect()
+            .await;
+        let Some(StreamChunk::SearchResults(results)) = chunks.first() else {
+            panic!("expected search results first, got {chunks:?}");
+        };
+        assert_eq!(results[0].url, "https://example.com");
+        assert_eq!(results[0].snippet.as_deref(), Some("An example"));
+        let Some(StreamChunk::Message(message)) = chunks.last() else {
+            panic!("expected a final message, got {chunks:?}");
+        };
+        let citations = message.citations.as_deref().unwrap();
+        assert_eq!(citations.len(), 1);
+        assert_eq!(citations[0].title, "Example");
+    }
+
+    #[tokio::test]
+    async fn malformed_search_results_follow_lenient_parsing() {
+        let body = concat!(
+            "data: {\"v\":{\"response\":{\"message_id\":2}}}\n",
+            "data: {\"p\":\"response/search_results\",\"v\":[{\"url\":1}]}\n",
+            "data: {\"p\":\"response/content\",\"o\":\"APPEND\",\"v\":\"Hi\"}\n",
+            "event: finish\n",
+        );
+        let strict: Vec<_> = response_to_event_stream(response(&[], body), None, false, None)
+            .collect()
+            .await;
+        assert!(matches!(
+            strict.as_slice(),
+            [Ok(CompletionEvent::Started { .. }), Err(_)]
+        ));
+
+        let lenient: Vec<_> = response_to_event_stream(response(&[], body), None, true, None)
+            .map(Result::unwrap)
+            .collect()
+            .await;
+        assert!(
+            !lenient
+                .iter()
+                .any(|event| matches!(event, CompletionEvent::SearchResults(_)))
+        );
+        let Some(CompletionEvent::Finished(message)) = lenient.last() else {
+            panic!("expected a final message, got {lenient:?}");
+        };
+        assert_eq!(message.content, "Hi");
+    }
+
+    #[tokio::test]
+    async fn file_is_uploaded_from_stream() {
+        let api = DeepSeekAPI::mock(|request| {
+            let file = json!({
+                "id": "file-1", "status": "SUCCESS", "file_name": "notes.txt",
+                "previewable": true, "file_size": 11, "token_usage": 3,
+                "error_code": null, "inserted_at": 1.0, "updated_at": 1.0
+            });
+            Ok(match request.url().path() {
+                "/api/v0/file/upload_file" => {
+                    assert_eq!(request.headers()["x-file-size"], "11");
+                    mock::json(&json!({"data": {"biz_data": file}}))
+                }
+                _ => mock::json(&json!({"data": {"biz_data": {"files": [file]}}})),
+            })
+        });
+        let chunks =
+            ["hello ", "world"].map(|chunk| Ok::<_, std::io::Error>(bytes::Bytes::from(chunk)));
+        let file = api
+            .upload_file_stream(futures_util::stream::iter(chunks), 11, "notes.txt", None)
+            .await
+            .unwrap();
+        assert_eq!(file.id, "file-1");
+        assert_eq!(file.status, "SUCCESS");
+    }
+
+    #[tokio::test]
+    async fn upload_waits_for_processing() {
+        let polls = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let api = DeepSeekAPI::mock({
+            let polls = Arc::clone(&polls);
+            move |request| {
+                let mut file = json!({
+                    "id": "file-1", "status": "PENDING", "file_name": "notes.txt",
+                    "previewable": true, "file_size": 5, "token_usage": null,
+                    "error_code": null, "inserted_at": 1.0, "updated_at": 1.0
+                });
+                if request.url().path() == "/api/v0/file/upload_file" {
+                    return Ok(mock::json(&json!({"data": {"biz_data": file}})));
+                }
+                if polls.fetch_add(1, std::sync::atomic::Ordering::SeqCst) > 0 {
+                    file["status"] = json!("SUCCESS");
+                }
+                Ok(mock::json(
+                    &json!({"data": {"biz_data": {"files": [file]}}}),
+                ))
+            }
+        });
+        let file = api
+            .upload_file_and_wait(b"hello".to_vec(), "notes.txt", None, 3, Duration::ZERO)
+            .await
+            .unwrap();
+        assert_eq!(file.status, "SUCCESS");
+        assert_eq!(polls.load(std::sync::atomic::Ordering::SeqCst), 2);
+
+        polls.store(0, std::sync::atomic::Ordering::SeqCst);
+        let err = api
+            .upload_file_and_wait(b"hello".to_vec(), "notes.txt", None, 1, Duration::ZERO)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("timed out after 1 attempts"));
+    }
+
+    #[test]
+    fn mime_types_are_guessed() {
+        let cases = [
+            ("photo.png", "image/png"),
+            ("photo.JPG", "image/jpeg"),
+            ("photo.jpeg", "image/jpeg"),
+            ("anim.gif", "image/gif"),
+            ("photo.webp", "image/webp"),
+            ("paper.pdf", "application/pdf"),
+            ("notes.txt", "text/plain"),
+            ("README.md", "text/markdown"),
+            ("table.csv", "text/csv"),
+            ("data.json", "application/json"),
+            ("page.html", "text/html"),
+            (
+                "report.docx",
+                "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+            ),
+            (
+                "sheet.xlsx",
+                "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+            ),
+            (
+                "slides.pptx",
+                "application/vnd.openxmlformats-officedocument.presentationml.presentation",
+            ),
+            ("archive.unknown", "application/octet-stream"),
+            ("no-extension", "application/octet-stream"),
+        ];
+        for (filename, mime) in cases {
+            assert_eq!(guess_mime_type(filename, None), mime, "{filename}");
+        }
+
+        let png = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+        assert_eq!(guess_mime_type("upload", Some(png)), "image/png");
+        assert_eq!(
+            guess_mime_type("upload.bin", Some(b"%PDF-1.7")),
+            "application/pdf"
+        );
+        // The extension wins over the content
+        assert_eq!(guess_mime_type("image.txt", Some(png)), "text/plain");
+        assert_eq!(
+            guess_mime_type("upload", Some(b"plain")),
+            "application/octet-stream"
+        );
+    }
+
+    #[tokio::test]
+    async fn token_usage_is_streamed() {
+        let api = DeepSeekAPI::mock(|_| {
+            Ok(mock::event_stream(concat!(
+                "data: {\"v\":{\"response\":{\"message_id\":2}}}\n",
+                "data: {\"p\":\"response/content\",\"o\":\"APPEND\",\"v\":\"Hi\"}\n",
+                "data: {\"p\":\"response/accumulated_token_usage\",\"v\":12}\n",
+                "event: finish\n",
+            )))
+        });
+        let chunks: Vec<_> = api
+            .complete_stream(
+                "chat".to_string(),
+                "Hi".to_string(),
+                None,
+                false,
+                false,
+                vec![],
+            )
+            .map(Result::unwrap)
+            .collect()
+            .await;
+        assert!(
+            chunks
+                .iter()
+                .any(|chunk| matches!(chunk, StreamChunk::Usage(12)))
+        );
+        let Some(StreamChunk::Message(message)) = chunks.last() else {
+            panic!("expected a final message, got {chunks:?}");
+        };
+        assert_eq!(message.accumulated_token_usage, Some(12));
+    }
+
+    #[tokio::test]
+    async fn stop_generation_posts_message() {
+        let sent = Arc::new(std::sync::Mutex::new(None));
+        let api = DeepSeekAPI::mock({
+            let sent = Arc::clone(&sent);
+            move |request| {
+                assert_eq!(request.url().path(), "/api/v0/chat/stop_stream");
+                let body = request.body().and_then(reqwest::Body::as_bytes).unwrap();
+                *sent.lock().unwrap() = Some(serde_json::from_slice::<serde_json::Value>(body)?);
+                Ok(mock::json(&json!({"code": 0, "msg": ""})))
+            }
+        });
+        api.stop_generation("chat", 4).await.unwrap();
+        assert_eq!(
+            sent.lock().unwrap().take(),
+            Some(json!({"chat_session_id": "chat", "message_id": 4}))
+        );
+    }
+
+    #[tokio::test]
+    async fn transient_failures_are_retried() {
+        let client = |statuses: &'static [u16]| {
+            let attempts = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+            let api = DeepSeekAPI::mock({
+                let attempts = Arc::clone(&attempts);
+                move |_| {
+                    let attempt = attempts.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                    Ok(match statuses.get(attempt) {
+                        Some(&status) => http::Response::builder()
+                            .status(status)
+                            .body("")
+                            .unwrap()
+                            .into(),
+                        None => mock::json(&json!({"code": 0, "msg": "", "data": {"biz_data": {
+                            "id": "chat", "seq_id": 1, "agent": "chat", "title": null,
+                            "title_type": "DEFAULT", "version": 0, "current_message_id": null,
+                            "pinned": false, "inserted_at": 1.0, "updated_at": 1.0
+                        }}})),
+                    })
+                }
+            });
+            let api = DeepSeekAPI {
+                retry: RetryPolicy::new(3, Duration::ZERO),
+                ..api
+            };
+            (api, attempts)
+        };
+        let attempts = |attempts: &Arc<std::sync::atomic::AtomicUsize>| {
+            attempts.load(std::sync::atomic::Ordering::SeqCst)
+        };
+
+        let (api, sent) = client(&[503, 429]);
+        api.create_chat().await.unwrap();
+        assert_eq!(attempts(&sent), 3);
+
+        let (api, sent) = client(&[502, 502, 502]);
+        api.create_chat().await.unwrap_err();
+        assert_eq!(attempts(&sent), 3);
+
+        let (api, sent) = client(&[401]);
+        let err = api.create_chat().await.unwrap_err();
+        assert_eq!(
+            err,
+            DeepSeekError::Unauthorized {
+                message_id: None,
+                request: None,
+            }
+        );
+        assert_eq!(attempts(&sent), 1);
+
+        // Requests other than the ones named by the policy are sent once
+        let (api, sent) = client(&[503]);
+        api.stop_generation("chat", 4).await.unwrap_err();
+        assert_eq!(attempts(&sent), 1);
+    }
+
+    #[tokio::test]
+    async fn retried_completion_is_rebuilt() {
+        let pow_headers = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let api = DeepSeekAPI::mock({
+            let pow_headers = Arc::clone(&pow_headers);
+            move |request| {
+                let mut pow_headers = pow_headers.lock().unwrap();
+                pow_headers.push(request.headers().get("x-ds-pow-response").cloned());
+                Ok(if pow_headers.len() == 1 {
+                    http::Response::builder()
+                        .status(503)
+                        .body("")
+                        .unwrap()
+                        .into()
+                } else {
+                    mock::event_stream(concat!(
+                        "data: {\"v\":{\"response\":{\"message_id\":2}}}\n",
+                        "data: {\"p\":\"response/content\",\"o\":\"APPEND\",\"v\":\"Hi\"}\n",
+                        "event: finish\n",
+                    ))
+                })
+            }
+        });
+        let api = DeepSeekAPI {
+            retry: RetryPolicy::new(3, Duration::ZERO),
+            pow_response: Some("solved".to_string()),
+            ..api
+        };
+        let message = api
+            .complete("chat", "Hi", None, false, false, vec![])
+            .await
+            .unwrap();
+        assert_eq!(message.content, "Hi");
+        // Each attempt was built anew, with its own PoW header
+        let pow_headers = pow_headers.lock().unwrap();
+        assert_eq!(pow_headers.len(), 2);
+        assert!(
+            pow_headers
+                .iter()
+                .all(|header| header.as_ref().is_some_and(|h| h == "solved"))
+        );
+    }
+
+    #[tokio::test]
+    async fn stop_request_carries_pow() {
+        let api = DeepSeekAPI::mock(|request| {
+            assert_eq!(request.url().path(), "/api/v0/chat/stop_stream");
+            assert_eq!(request.headers()["x-ds-pow-response"], "solved");
+            Ok(mock::json(&json!({"code": 0, "msg": ""})))
+        });
+        let api = DeepSeekAPI {
+            pow_response: Some("solved".to_string()),
+            ..api
+        };
+        api.stop_generation("chat", 2).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn rate_limit_is_typed() {
+        let attempts = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let api = DeepSeekAPI::mock({
+            let attempts = Arc::clone(&attempts);
+            move |_| {
+                attempts.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                Ok(http::Response::builder()
+                    .status(429)
+                    .header("retry-after", "5")
+                    .body("")
+                    .unwrap()
+                    .into())
+            }
+        });
+        // The server asks to wait longer than the policy allows, so it is not retried
+        let api = DeepSeekAPI {
+            retry: RetryPolicy::new(3, Duration::ZERO).max_delay(Duration::from_secs(1)),
+            ..api
+        };
+        let chunks: Vec<_> = api
+            .complete_stream(
+                "chat".to_string(),
+                "Hi".to_string(),
+                None,
+                false,
+                false,
+                vec![],
+            )
+            .collect()
+            .await;
+        let [Err(err)] = chunks.as_slice() else {
+            panic!("expected the rate limit error, got {chunks:?}");
+        };
+        assert!(matches!(
+            err,
+            DeepSeekError::RateLimited { retry_after: Some(retry_after), request: Some(request) }
+                if *retry_after == Duration::from_secs(5) && request.path == "/api/v0/chat/completion"
+        ));
+        assert_eq!(attempts.load(std::sync::atomic::Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn chats_are_listed_across_pages() {
+        let session = |id: &str, updated_at: f64| {
+            json!({
+                "id": id, "seq_id": 1, "agent": "chat", "title": id,
+                "title_type": "DEFAULT", "version": 0, "current_message_id": null,
+                "pinned": false, "inserted_at": 1.0, "updated_at": updated_at
+            })
+        };
+        let api = DeepSeekAPI::mock(move |request| {
+            assert_eq!(request.url().path(), "/api/v0/chat_session/fetch_page");
+            let (sessions, has_more) = match request.url().query() {
+                Some("count=50") => (vec![session("c", 3.0), session("b", 2.0)], true),
+                Some("count=50&lte_cursor.updated_at=2") => {
+                    (vec![session("b", 2.0), session("a", 1.0)], false)
+                }
+                query => panic!("unexpected query {query:?}"),
+            };
+            Ok(mock::json(
+                &json!({"code": 0, "msg": "", "data": {"biz_data": {
+                    "chat_sessions": sessions, "has_more": has_more
+                }}}),
+            ))
+        });
+        let sessions = api.list_chats().await.unwrap();
+        let ids: Vec<_> = sessions.iter().map(|session| session.id.as_str()).collect();
+        assert_eq!(ids, ["c", "b", "a"]);
+    }
+
+    #[tokio::test]
+    async fn deleting_chat_checks_code() {
+        let api = DeepSeekAPI::mock(|request| {
+            assert_eq!(request.url().path(), "/api/v0/chat_session/delete");
+            let body = request.body().and_then(reqwest::Body::as_bytes).unwrap();
+            let body: serde_json::Value = serde_json::from_slice(body)?;
+            Ok(mock::json(&if body == json!({"chat_session_id": "chat"}) {
+                json!({"code": 0, "msg": ""})
+            } else {
+                json!({"code": 40300, "msg": "Chat session not found"})
+            }))
+        });
+        api.delete_chat("chat").await.unwrap();
+        let err = api.delete_chat("missing").await.unwrap_err();
+        assert_eq!(
+            err,
+            DeepSeekError::Api {
+                code: Some(40300),
+                message: "Chat session not found".to_string()
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn renamed_title_persists() {
+        let title = Arc::new(std::sync::Mutex::new(None::<String>));
+        let api = DeepSeekAPI::mock({
+            let title = Arc::clone(&title);
+            move |request| {
+                let session = |title: Option<&str>| {
+                    json!({
+                        "id": "chat", "seq_id": 1, "agent": "chat", "title": title,
+                        "title_type": if title.is_some() { "USER" } else { "DEFAULT" },
+                        "version": 0, "current_message_id": null,
+                        "pinned": false, "inserted_at": 1.0, "updated_at": 1.0
+                    })
+                };
+                let mut title = title.lock().unwrap();
+                Ok(mock::json(&match request.url().path() {
+                    "/api/v0/chat_session/create" => json!({"data": {"biz_data": session(None)}}),
+                    "/api/v0/chat_session/update_title" => {
+                        let body = request.body().and_then(reqwest::Body::as_bytes).unwrap();
+                        let body: serde_json::Value = serde_json::from_slice(body)?;
+                        assert_eq!(body["chat_session_id"], "chat");
+                        *title = body["title"].as_str().map(str::to_string);
+                        json!({"code": 0, "msg": ""})
                     }
+                    "/api/v0/chat/history_messages" => {
+                        json!({"code": 0, "msg": "", "data": {"biz_data": {
+                            "chat_session": session(title.as_deref()), "chat_messages": []
+                        }}})
+                    }
+                    path => panic!("unexpected request to {path}"),
+                }))
+            }
+        });
+        let chat = api.create_chat().await.unwrap();
+        let renamed = api.rename_chat(&chat.id, "Trip plans").await.unwrap();
+        assert_eq!(renamed.title.as_deref(), Some("Trip plans"));
+        let info = api.get_chat_info(&chat.id).await.unwrap();
+        assert_eq!(info.title.as_deref(), Some("Trip plans"));
+        assert_eq!(info.title_type, "USER");
+        assert!(api.rename_chat(&chat.id, " ").await.is_err());
+
+        api.clear_title(&chat.id).await.unwrap();
+        let info = api.get_chat_info(&chat.id).await.unwrap();
+        assert_eq!(info.title.as_deref(), Some(""));
+    }
+
+    #[tokio::test]
+    async fn chat_messages_are_fetched() {
+        let api = DeepSeekAPI::mock(|request| {
+            assert_eq!(request.url().path(), "/api/v0/chat/history_messages");
+            assert_eq!(request.url().query(), Some("chat_session_id=chat"));
+            Ok(mock::json(
+                &json!({"code": 0, "msg": "", "data": {"biz_data": {
+                    "chat_session": {
+                        "id": "chat", "seq_id": 1, "agent": "chat", "title": "Greeting",
+                        "title_type": "SYSTEM", "version": 2, "current_message_id": 2,
+                        "pinned": false, "inserted_at": 1.0, "updated_at": 2.0
+                    },
+                    "chat_messages": [
+                        {
+                            "message_id": 2, "parent_id": 1, "role": "ASSISTANT", "content": "Hello!",
+                            "thinking_content": null, "status": "FINISHED", "inserted_at": 2.0,
+                            "accumulated_token_usage": 12, "search_results": null, "files": [],
+                            "thinking_enabled": false, "search_enabled": false, "feedback": null
+                        },
+                        {
+                            "message_id": 1, "parent_id": null, "role": "USER", "content": "Hi",
+                            "status": "FINISHED", "inserted_at": 1.0, "accumulated_token_usage": 4,
+                            "files": []
+                        }
+                    ]
+                }}}),
+            ))
+        });
+        let messages = api.get_chat_messages("chat").await.unwrap();
+        let transcript: Vec<_> = messages
+            .iter()
+            .map(|message| {
+                (
+                    message.message_id,
+                    message.role.clone(),
+                    message.content.as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            transcript,
+            [
+                (Some(1), Some(models::Role::User), "Hi"),
+                (Some(2), Some(models::Role::Assistant), "Hello!"),
+            ]
+        );
+        assert_eq!(messages[1].parent_id, Some(1));
+        assert_eq!(messages[1].status, Some(models::MessageStatus::Finished));
+        assert_eq!(messages[1].accumulated_token_usage, Some(12));
+    }
+
+    #[tokio::test]
+    async fn regenerated_message_is_continued() {
+        let api = DeepSeekAPI::mock(|request| {
+            let body = request.body().and_then(reqwest::Body::as_bytes).unwrap();
+            let body: serde_json::Value = serde_json::from_slice(body)?;
+            Ok(mock::event_stream(match request.url().path() {
+                "/api/v0/chat/regenerate" => {
+                    assert_eq!(body, json!({"chat_session_id": "chat", "message_id": 2}));
+                    concat!(
+                        "data: {\"v\":{\"response\":{\"message_id\":4,\"parent_id\":1,\"content\":\"Hello \"}}}\n",
+                        "data: {\"p\":\"response/status\",\"v\":\"INCOMPLETE\"}\n",
+                        "event: finish\n",
+                    )
+                }
+                "/api/v0/chat/continue" => {
+                    assert_eq!(body["message_id"], 4);
+                    concat!(
+                        "data: {\"v\":{\"response\":{\"message_id\":4,\"parent_id\":1,\"content\":\"Hello \"}}}\n",
+                        "data: {\"p\":\"response/content\",\"o\":\"APPEND\",\"v\":\"again\"}\n",
+                        "data: {\"p\":\"response/status\",\"v\":\"FINISHED\"}\n",
+                        "event: finish\n",
+                    )
+                }
+                path => panic!("unexpected request to {path}"),
+            }))
+        });
+        let message = api.regenerate_message("chat", 2).await.unwrap();
+        assert_eq!(message.message_id, Some(4));
+        assert_eq!(message.parent_id, Some(1));
+        assert_eq!(message.content, "Hello again");
+    }
+
+    #[tokio::test]
+    async fn raw_events_are_passed_through() {
+        let api = DeepSeekAPI::mock(|request| {
+            assert_eq!(request.url().path(), "/api/v0/chat/completion");
+            Ok(mock::event_stream(concat!(
+                "event: ready\n",
+                "data: {\"request_message_id\":1}\n",
+                "\n",
+                "data: {\"v\":{\"response\":{\"message_id\":2}}}\n",
+                "\n",
+                "data: {\"p\":\"response/new_field\",\"v\":42}\n",
+                "\n",
+                "event: finish\n",
+                "\n",
+                "event: close\n",
+                "data: {\"click_behavior\":\"none\"}\n",
+            )))
+        });
+        let events: Vec<_> = api
+            .complete_raw_stream(
+                "chat".to_string(),
+                "Hi".to_string(),
+                None,
+                CompletionOptions::default(),
+            )
+            .map(Result::unwrap)
+            .collect()
+            .await;
+        let raw = |event: Option<&str>, data: &str| RawSseEvent {
+            event: event.map(str::to_string),
+            data: data.to_string(),
+        };
+        assert_eq!(
+            events,
+            [
+                raw(Some("ready"), r#"{"request_message_id":1}"#),
+                raw(None, r#"{"v":{"response":{"message_id":2}}}"#),
+                raw(None, r#"{"p":"response/new_field","v":42}"#),
+                raw(Some("finish"), ""),
+                raw(Some("close"), r#"{"click_behavior":"none"}"#),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn streamed_thinking_is_kept_on_final_message() {
+        let api = DeepSeekAPI::mock(|request| {
+            let body = request.body().and_then(reqwest::Body::as_bytes).unwrap();
+            let body: serde_json::Value = serde_json::from_slice(body)?;
+            assert_eq!(body["thinking_enabled"], true);
+            Ok(mock::event_stream(concat!(
+                "data: {\"v\":{\"response\":{\"message_id\":2,\"thinking_content\":\"\"}}}\n",
+                "data: {\"p\":\"response/thinking_content\",\"o\":\"APPEND\",\"v\":\"Let me \"}\n",
+                "data: {\"v\":\"think\"}\n",
+                // Superseded by a later update that drops the streamed thinking
+                "data: {\"p\":\"response/thinking_content\",\"o\":\"SET\",\"v\":\"\"}\n",
+                "data: {\"p\":\"response/content\",\"o\":\"APPEND\",\"v\":\"Hi\"}\n",
+                "data: {\"p\":\"response/status\",\"v\":\"FINISHED\"}\n",
+                "event: finish\n",
+            )))
+        });
+        let message = api
+            .complete("chat", "Hi", None, false, true, vec![])
+            .await
+            .unwrap();
+        assert_eq!(message.content, "Hi");
+        assert_eq!(message.thinking_content.as_deref(), Some("Let me think"));
+    }
+
+    #[tokio::test]
+    async fn chat_is_created_with_agent() {
+        let api = DeepSeekAPI::mock(|request| {
+            assert_eq!(request.url().path(), "/api/v0/chat_session/create");
+            let body = request.body().and_then(reqwest::Body::as_bytes).unwrap();
+            let body: serde_json::Value = serde_json::from_slice(body)?;
+            let agent = body.get("agent").cloned().unwrap_or(json!("chat"));
+            Ok(mock::json(&json!({"data": {"biz_data": {
+                "id": "chat", "seq_id": 1, "agent": agent, "title": null,
+                "title_type": "DEFAULT", "version": 0, "current_message_id": null,
+                "pinned": false, "inserted_at": 1.0, "updated_at": 1.0
+            }}})))
+        });
+        assert_eq!(api.create_chat().await.unwrap().agent, "chat");
+        assert_eq!(
+            api.create_chat_with_agent("agent").await.unwrap().agent,
+            "agent"
+        );
+    }
+
+    #[tokio::test]
+    async fn request_timeout_spares_streams() {
+        let timeout = Duration::from_secs(5);
+        let api = DeepSeekAPI {
+            request_timeout: Some(timeout),
+            ..DeepSeekAPI::mock(move |request| {
+                if request.url().path().ends_with("/chat/completion") {
+                    assert_eq!(request.timeout(), None);
+                    Ok(mock::event_stream(concat!(
+                        "data: {\"v\":{\"response\":{\"message_id\":2}}}\n",
+                        "event: finish\n",
+                    )))
+                } else {
+                    assert_eq!(request.timeout(), Some(&timeout));
+                    Ok(mock::json(&json!({"code": 0, "msg": ""})))
                 }
+            })
+        };
+        api.delete_chat("chat").await.unwrap();
+        let message = api
+            .complete("chat", "Hi", None, false, false, vec![])
+            .await
+            .unwrap();
+        assert_eq!(message.message_id, Some(2));
+    }
+
+    #[tokio::test]
+    async fn unparsable_response_is_parse_error() {
+        let api = DeepSeekAPI::mock(|_| Ok(mock::json(&json!({ "unexpected": true }))));
+        let err = api.get_chat_info("chat").await.unwrap_err();
+        assert!(matches!(err, DeepSeekError::Parse(_)), "{err:?}");
+    }
+
+    #[tokio::test]
+    async fn rejected_thinking_falls_back_with_warning() {
+        let requests = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let api = DeepSeekAPI::mock({
+            let requests = Arc::clone(&requests);
+            move |request| {
+                let body = request.body().and_then(reqwest::Body::as_bytes).unwrap();
+                let body: serde_json::Value = serde_json::from_slice(body)?;
+                let thinking = body["thinking_enabled"] == json!(true);
+                requests.lock().unwrap().push(thinking);
+                if thinking {
+                    return Ok(mock::json(&json!({
+                        "code": 40003,
+                        "msg": "Thinking is not supported for this model",
+                        "data": null,
+                    })));
+                }
+                Ok(mock::event_stream(concat!(
+                    "data: {\"v\":{\"response\":{\"message_id\":2}}}\n",
+                    "data: {\"p\":\"response/content\",\"o\":\"APPEND\",\"v\":\"Hi\"}\n",
+                    "event: finish\n",
+                )))
+            }
+        });
+
+        let chunks: Vec<_> = api
+            .complete_stream(
+                "chat".to_string(),
+                "Hi".to_string(),
+                None,
+                false,
+                true,
+                vec![],
+            )
+            .collect()
+            .await;
+        assert!(matches!(
+            chunks.as_slice(),
+            [Err(DeepSeekError::UnsupportedFeature { feature, .. })] if feature == "thinking"
+        ));
+        assert_eq!(*requests.lock().unwrap(), [true]);
+
+        requests.lock().unwrap().clear();
+        let api = DeepSeekAPI {
+            thinking_fallback: true,
+            ..api
+        };
+        let chunks: Vec<_> = api
+            .complete_stream(
+                "chat".to_string(),
+                "Hi".to_string(),
+                None,
+                false,
+                true,
+                vec![],
+            )
+            .collect()
+            .await;
+        let [
+            Ok(StreamChunk::Warning(CompletionWarning::ThinkingDisabled { reason })),
+            Ok(StreamChunk::Content(content)),
+            Ok(StreamChunk::Message(message)),
+        ] = chunks.as_slice()
+        else {
+            panic!("expected a warning and the answer without thinking, got {chunks:?}");
+        };
+        assert!(reason.contains("Thinking is not supported"), "{reason}");
+        assert_eq!(content, "Hi");
+        assert_eq!(message.content, "Hi");
+        assert_eq!(*requests.lock().unwrap(), [true, false]);
+    }
+
+    #[tokio::test]
+    async fn split_completion_shares_typed_error() {
+        let api = DeepSeekAPI::mock(|_| {
+            Ok(mock::event_stream(concat!(
+                "data: {\"v\":{\"response\":{\"message_id\":2}}}\n",
+                "event: toast\n",
+                "data: {\"type\":\"error\",\"content\":\"Too many requests, slow down\",\"code\":429}\n",
+                "event: finish\n",
+            )))
+        });
+        let (chunks, message) = api.complete_split(
+            "chat".to_string(),
+            "Hi".to_string(),
+            None,
+            false,
+            false,
+            vec![],
+        );
+        let chunks: Vec<_> = chunks.collect().await;
+        let expected = DeepSeekError::Toast(ToastError::RateLimited(
+            "Too many requests, slow down".to_string(),
+        ));
+        assert!(matches!(chunks.last(), Some(Err(err)) if *err == expected));
+        assert_eq!(message.await.unwrap_err(), expected);
+    }
+
+    #[tokio::test]
+    async fn dual_streams_share_typed_error() {
+        let api = DeepSeekAPI::mock(|_| {
+            Ok(mock::event_stream(concat!(
+                "data: {\"v\":{\"response\":{\"message_id\":2}}}\n",
+                "data: {\"p\":\"response/thinking_content\",\"o\":\"APPEND\",\"v\":\"Hmm\"}\n",
+                "data: {\"p\":\"response/content\",\"o\":\"APPEND\",\"v\":\"Hel\"}\n",
+                "event: toast\n",
+                "data: {\"type\":\"error\",\"content\":\"Too many requests, slow down\",\"code\":429}\n",
+                "event: finish\n",
+            )))
+        });
+        let (thinking, content) = api.complete_dual_stream(
+            "chat".to_string(),
+            "Hi".to_string(),
+            None,
+            false,
+            true,
+            vec![],
+        );
+        let (thinking, content): (Vec<_>, Vec<_>) =
+            tokio::join!(thinking.collect(), content.collect());
+        let expected = DeepSeekError::Toast(ToastError::RateLimited(
+            "Too many requests, slow down".to_string(),
+        ));
+        assert!(
+            matches!(thinking.as_slice(), [Ok(text), Err(err)] if text == "Hmm" && *err == expected)
+        );
+        assert!(
+            matches!(content.as_slice(), [Ok(text), Err(err)] if text == "Hel" && *err == expected)
+        );
+    }
+
+    #[tokio::test]
+    async fn response_meta_has_client_request_id() {
+        let sent = Arc::new(std::sync::Mutex::new(None));
+        let api = DeepSeekAPI::mock({
+            let sent = Arc::clone(&sent);
+            move |request| {
+                *sent.lock().unwrap() = request.headers().get(REQUEST_ID_HEADER).cloned();
+                Ok(mock::event_stream("event: finish\n"))
+            }
+        });
+        let (meta, _) = api
+            .complete_stream_with_meta(
+                "chat".to_string(),
+                "Hi".to_string(),
+                None,
+                false,
+                false,
+                vec![],
+            )
+            .await
+            .unwrap();
+        let sent = sent.lock().unwrap().take().unwrap();
+        assert_eq!(meta.client_request_id, sent.to_str().unwrap());
+    }
+
+    #[tokio::test]
+    async fn toast_error_is_returned() {
+        let api = DeepSeekAPI::mock(|_| {
+            Ok(mock::event_stream(concat!(
+                "data: {\"v\":{\"response\":{\"message_id\":2}}}\n",
+                "data: {\"p\":\"response/content\",\"o\":\"APPEND\",\"v\":\"Hel\"}\n",
+                "event: toast\n",
+                "data: {\"type\":\"error\",\"content\":\"Too many requests, slow down\",\"code\":429}\n",
+                "event: finish\n",
+            )))
+        });
+        let chunks: Vec<_> = api
+            .complete_stream(
+                "chat".to_string(),
+                "Hi".to_string(),
+                None,
+                false,
+                false,
+                vec![],
+            )
+            .collect()
+            .await;
+        assert!(matches!(chunks.first(), Some(Ok(StreamChunk::Content(text))) if text == "Hel"));
+        let Some(Err(err)) = chunks.last() else {
+            panic!("expected the toast error, got {chunks:?}");
+        };
+        assert_eq!(
+            *err,
+            DeepSeekError::Toast(ToastError::RateLimited(
+                "Too many requests, slow down".to_string()
+            ))
+        );
+    }
+
+    #[tokio::test]
+    async fn completion_request_sets_parameters() {
+        let sent = Arc::new(std::sync::Mutex::new(None));
+        let api = DeepSeekAPI::mock({
+            let sent = Arc::clone(&sent);
+            move |request| {
+                let body = request.body().and_then(reqwest::Body::as_bytes).unwrap();
+                *sent.lock().unwrap() = Some(serde_json::from_slice::<serde_json::Value>(body)?);
+                Ok(mock::event_stream(concat!(
+                    "data: {\"v\":{\"response\":{\"message_id\":6}}}\n",
+                    "data: {\"p\":\"response/content\",\"o\":\"APPEND\",\"v\":\"Hi\"}\n",
+                    "event: finish\n",
+                )))
             }
+        });
+        let message = CompletionRequest::new("chat", "Hello")
+            .parent(5)
+            .thinking(true)
+            .ref_files(vec!["file-1".to_string()])
+            .send(&api)
+            .await
+            .unwrap();
+        assert_eq!(message.content, "Hi");
+        let sent = sent.lock().unwrap().take().unwrap();
+        assert_eq!(sent["parent_message_id"], 5);
+        assert_eq!(sent["thinking_enabled"], true);
+        assert_eq!(sent["search_enabled"], false);
+        assert_eq!(sent["ref_file_ids"], json!(["file-1"]));
+    }
+
+    #[test]
+    fn setters_adjust_request_options() {
+        let options = CompletionOptions {
+            search: true,
+            stop_sequences: vec!["END".to_string()],
+            ..CompletionOptions::default()
+        };
+        let request = CompletionRequest::with_options("chat", "Hello", options)
+            .auto_continue(false)
+            .max_continuations(2);
+        assert!(request.options.search);
+        assert_eq!(request.options.stop_sequences, ["END"]);
+        assert!(request.options.no_continue);
+        assert_eq!(request.options.max_continuations, Some(2));
+    }
+
+    #[tokio::test]
+    async fn requests_go_to_base_url() {
+        let (addr, server) = serve_created_chat().await;
+        let api = DeepSeekAPI::builder("token")
+            .disable_pow()
+            .base_url(format!("http://{addr}/"))
+            .build()
+            .await
+            .unwrap();
+        assert_eq!(api.create_chat().await.unwrap().id, "chat");
+        assert!(
+            server
+                .await
+                .unwrap()
+                .starts_with("POST /api/v0/chat_session/create HTTP/1.1\r\n")
+        );
+    }
+
+    #[tokio::test]
+    async fn injected_client_sends_requests() {
+        let (addr, server) = serve_created_chat().await;
+        let client = Client::builder()
+            .default_headers(header::HeaderMap::from_iter([(
+                header::HeaderName::from_static("x-shared"),
+                header::HeaderValue::from_static("yes"),
+            )]))
+            .build()
+            .unwrap();
+        let api = DeepSeekAPI::builder("token")
+            .client(client)
+            .disable_pow()
+            .base_url(format!("http://{addr}"))
+            .build()
+            .await
+            .unwrap();
+        api.create_chat().await.unwrap();
+        let request = server.await.unwrap().to_ascii_lowercase();
+        for header in [
+            "x-shared: yes",
+            "authorization: bearer token",
+            "content-type: application/json",
+        ] {
+            assert!(request.contains(header), "{header} missing from {request}");
         }
     }
+
+    /// Answers one request on a local port with a created chat, returning the
+    /// head of the request.
+    async fn serve_created_chat() -> (std::net::SocketAddr, tokio::task::JoinHandle<String>) {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0; 1024];
+            while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                let n = socket.read(&mut buf).await.unwrap();
+                request.extend_from_slice(&buf[..n]);
+            }
+            let body = json!({"data": {"biz_data": {
+                "id": "chat", "seq_id": 1, "agent": "chat", "title": null,
+                "title_type": "DEFAULT", "version": 0, "current_message_id": null,
+                "pinned": false, "inserted_at": 1.0, "updated_at": 1.0
+            }}})
+            .to_string();
+            let response = format!(
+                "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{body}",
+                body.len()
+            );
+            socket.write_all(response.as_bytes()).await.unwrap();
+            String::from_utf8_lossy(&request).into_owned()
+        });
+        (addr, server)
+    }
+
+    #[tokio::test]
+    async fn expired_prefetched_pow_is_skipped() {
+        let api = DeepSeekAPI::builder("token")
+            .disable_pow()
+            .build()
+            .await
+            .unwrap();
+        let path = "/api/v0/chat/completion";
+        api.pow_pool.lock().unwrap().insert(
+            path.to_string(),
+            VecDeque::from([
+                SolvedPow {
+                    header: "expired".to_string(),
+                    expire_at: 0,
+                },
+                SolvedPow {
+                    header: "valid".to_string(),
+                    expire_at: i64::MAX,
+                },
+            ]),
+        );
+        assert_eq!(api.take_prefetched_pow(path).as_deref(), Some("valid"));
+        assert_eq!(api.take_prefetched_pow(path), None);
+        assert_eq!(api.take_prefetched_pow("/api/v0/file/upload_file"), None);
+    }
+
+    #[tokio::test]
+    async fn empty_response_is_retried() {
+        let calls = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let api = DeepSeekAPI::builder("token")
+            .disable_pow()
+            .retry_empty_response(1)
+            .middleware({
+                let calls = Arc::clone(&calls);
+                move |_request, _next| {
+                    let attempt = calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                    async move {
+                        Ok(response(
+                            &[("content-type", "text/event-stream")],
+                            if attempt == 0 {
+                                "data: {\"v\":{\"response\":{\"message_id\":2}}}\nevent: finish\n"
+                            } else {
+                                concat!(
+                                    "data: {\"v\":{\"response\":{\"message_id\":3}}}\n",
+                                    "data: {\"p\":\"response/content\",\"o\":\"APPEND\",\"v\":\"Hi\"}\n",
+                                    "event: finish\n",
+                                )
+                            },
+                        ))
+                    }
+                }
+            })
+            .build()
+            .await
+            .unwrap();
+        let result = api
+            .complete_detailed("chat", "Hi", None, false, false, vec![])
+            .await
+            .unwrap();
+        assert_eq!(result.message.content, "Hi");
+        assert_eq!(result.retries, 1);
+        assert_eq!(calls.load(std::sync::atomic::Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn max_concurrency_queues_streams() {
+        let calls = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let api = DeepSeekAPI::builder("token")
+            .disable_pow()
+            .max_concurrency(1)
+            .middleware({
+                let calls = Arc::clone(&calls);
+                move |_request, _next| {
+                    let attempt = calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                    async move {
+                        let body = futures_util::stream::iter([Ok::<_, std::io::Error>(
+                            "data: {\"p\":\"response/content\",\"o\":\"APPEND\",\"v\":\"Hi\"}\n",
+                        )]);
+                        let body = if attempt == 0 {
+                            body.chain(futures_util::stream::pending()).boxed()
+                        } else {
+                            body.boxed()
+                        };
+                        Ok(http::Response::builder()
+                            .header("content-type", "text/event-stream")
+                            .body(reqwest::Body::wrap_stream(body))?
+                            .into())
+                    }
+                }
+            })
+            .build()
+            .await
+            .unwrap();
+        let stream = |prompt: &str| {
+            api.complete_stream(
+                "chat".to_string(),
+                prompt.to_string(),
+                None,
+                false,
+                false,
+                vec![],
+            )
+        };
+
+        let mut first = Box::pin(stream("first"));
+        assert!(first.next().await.is_some());
+        let mut second = Box::pin(stream("second"));
+        let waiting = tokio::time::timeout(Duration::from_millis(50), second.next()).await;
+        assert!(waiting.is_err(), "second stream should wait for a slot");
+        drop(first);
+        assert!(second.next().await.is_some());
+        assert_eq!(calls.load(std::sync::atomic::Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn max_concurrency_queues_streams_with_meta() {
+        let calls = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let api = DeepSeekAPI::builder("token")
+            .disable_pow()
+            .max_concurrency(1)
+            .middleware({
+                let calls = Arc::clone(&calls);
+                move |_request, _next| {
+                    calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                    async move {
+                        let body = futures_util::stream::iter([Ok::<_, std::io::Error>(
+                            "data: {\"p\":\"response/content\",\"o\":\"APPEND\",\"v\":\"Hi\"}\n",
+                        )])
+                        .chain(futures_util::stream::pending());
+                        Ok(http::Response::builder()
+                            .header("content-type", "text/event-stream")
+                            .body(reqwest::Body::wrap_stream(body))?
+                            .into())
+                    }
+                }
+            })
+            .build()
+            .await
+            .unwrap();
+        let stream = |prompt: &str| {
+            api.complete_stream_with_meta(
+                "chat".to_string(),
+                prompt.to_string(),
+                None,
+                false,
+                false,
+                vec![],
+            )
+        };
+
+        let (_, first) = stream("first").await.unwrap();
+        let mut first = Box::pin(first);
+        assert!(first.next().await.is_some());
+        let mut second = Box::pin(stream("second"));
+        let waiting = tokio::time::timeout(Duration::from_millis(50), &mut second).await;
+        assert!(waiting.is_err(), "second request should wait for a slot");
+        drop(first);
+        let (_, second) = second.await.unwrap();
+        let mut second = Box::pin(second);
+        assert!(second.next().await.is_some());
+        assert_eq!(calls.load(std::sync::atomic::Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn export_records_referenced_files() {
+        let api = DeepSeekAPI::builder("token")
+            .disable_pow()
+            .middleware(|_request, _next| async move {
+                Ok(response(
+                    &[("content-type", "application/json")],
+                    r#"{"code":0,"msg":"","data":{"biz_data":{
+                        "chat_session":{"id":"chat","seq_id":1,"agent":"chat","title":"T",
+                            "title_type":"SYSTEM","version":2,"current_message_id":2,
+                            "pinned":false,"inserted_at":1.0,"updated_at":2.0},
+                        "chat_messages":[
+                            {"message_id":2,"parent_id":1,"role":"ASSISTANT","content":"Hello"},
+                            {"message_id":1,"role":"USER","content":"Hi","files":[{"id":"file-1"}]}
+                        ]}}}"#,
+                ))
+            })
+            .build()
+            .await
+            .unwrap();
+        let export = api.export_conversation("chat").await.unwrap();
+        let json = serde_json::to_value(&export).unwrap();
+        assert_eq!(json["version"], models::ConversationExport::VERSION);
+        assert_eq!(json["messages"][0]["content"], "Hi");
+        assert_eq!(json["messages"][0]["ref_file_ids"], json!(["file-1"]));
+        assert!(json["messages"][1].get("ref_file_ids").is_none());
+
+        let restored: models::ConversationExport = serde_json::from_value(json).unwrap();
+        assert_eq!(restored.messages[0].ref_file_ids, ["file-1"]);
+        assert_eq!(restored.session.current_message_id, Some(2));
+    }
+
+    #[tokio::test]
+    async fn completion_headers_cannot_override_managed_ones() {
+        let api = DeepSeekAPI::builder("token")
+            .pow_response("proxy")
+            .middleware(|request: reqwest::Request, _next| async move {
+                let headers = request.headers();
+                assert_eq!(headers["x-tenant"], "acme");
+                assert_eq!(headers["x-ds-pow-response"], "proxy");
+                assert_eq!(headers.get_all("x-ds-pow-response").iter().count(), 1);
+                assert!(!headers.contains_key(header::AUTHORIZATION));
+                Ok(response(
+                    &[("content-type", "text/event-stream")],
+                    "data: {\"v\":{\"response\":{\"message_id\":2}}}\nevent: finish\n",
+                ))
+            })
+            .build()
+            .await
+            .unwrap();
+        let mut headers = header::HeaderMap::new();
+        headers.insert("x-tenant", header::HeaderValue::from_static("acme"));
+        headers.insert(
+            "x-ds-pow-response",
+            header::HeaderValue::from_static("forged"),
+        );
+        headers.insert(
+            header::AUTHORIZATION,
+            header::HeaderValue::from_static("forged"),
+        );
+        let options = CompletionOptions {
+            headers,
+            ..CompletionOptions::default()
+        };
+        let message = api
+            .complete_once("chat", "Hi", None, options)
+            .await
+            .unwrap();
+        assert_eq!(message.message_id, Some(2));
+    }
+
+    #[tokio::test]
+    async fn latency_profile_is_ordered() {
+        let api = DeepSeekAPI::builder("token")
+            .disable_pow()
+            .middleware(|_request, _next| async move {
+                Ok(response(
+                    &[("content-type", "text/event-stream")],
+                    concat!(
+                        "data: {\"v\":{\"response\":{\"message_id\":2}}}\n",
+                        "data: {\"p\":\"response/content\",\"o\":\"APPEND\",\"v\":\"Hi\"}\n",
+                        "event: finish\n",
+                    ),
+                ))
+            })
+            .build()
+            .await
+            .unwrap();
+        let (message, profile) = api
+            .complete_profiled("chat", "Hi", None, CompletionOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(message.content, "Hi");
+        assert!(profile.pow <= profile.first_byte);
+        assert!(
+            profile
+                .first_content
+                .is_some_and(|t| profile.first_byte <= t && t <= profile.total)
+        );
+        assert_eq!(profile.continuations, 0);
+    }
+
+    #[tokio::test]
+    async fn cancelled_upload_stops_sending() {
+        let api = DeepSeekAPI::builder("token")
+            .disable_pow()
+            .middleware(|_request, _next| futures_util::future::pending())
+            .build()
+            .await
+            .unwrap();
+        let cancel = CancellationToken::new();
+        let upload = api.upload_file_cancellable(b"data".to_vec(), "a.txt", None, cancel.clone());
+        cancel.cancel();
+        let err = upload.await.unwrap_err();
+        assert_eq!(err, DeepSeekError::Cancelled);
+    }
+
+    #[tokio::test]
+    async fn middleware_wraps_requests() {
+        let api = DeepSeekAPI::builder("token")
+            .disable_pow()
+            .middleware(|request: reqwest::Request, _next| async move {
+                assert_eq!(request.url().path(), "/api/v0/chat_session/create");
+                Ok(response(
+                    &[("content-type", "application/json")],
+                    r#"{"data":{"biz_data":{"id":"chat","seq_id":1,"agent":"chat","title":null,
+                        "title_type":"WIP","version":0,"current_message_id":null,"pinned":false,
+                        "inserted_at":1.0,"updated_at":1.0}}}"#,
+                ))
+            })
+            .build()
+            .await
+            .unwrap();
+        assert_eq!(api.create_chat().await.unwrap().id, "chat");
+    }
+
+    #[test]
+    fn response_limiter_truncates_at_char_boundary() {
+        let mut limiter = ResponseLimiter::new(5, OversizeAction::Truncate);
+        assert!(matches!(
+            limiter.admit(CompletionEvent::Thinking("ab".to_string())),
+            Admission::Within(CompletionEvent::Thinking(_))
+        ));
+        // "é" is two bytes and does not fit into the last byte
+        assert!(matches!(
+            limiter.admit(CompletionEvent::Content("cdé".to_string())),
+            Admission::Exceeded(Some(CompletionEvent::Content(ref text))) if text == "cd"
+        ));
+        let message = limiter.message(Some(7));
+        assert_eq!(message.content, "cd");
+        assert_eq!(message.thinking_content.as_deref(), Some("ab"));
+    }
+
+    #[test]
+    fn extra_fields_do_not_override_request_fields() {
+        let mut extra = serde_json::Map::new();
+        extra.insert("model_class".to_string(), json!("deepseek_v3"));
+        extra.insert("thinking_enabled".to_string(), json!(true));
+        let request = completion_request(
+            "chat",
+            "Hi".to_string(),
+            None,
+            CompletionOptions {
+                extra,
+                ..CompletionOptions::default()
+            },
+        );
+        assert_eq!(request["model_class"], "deepseek_v3");
+        assert_eq!(request["thinking_enabled"], false);
+    }
 }
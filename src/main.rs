@@ -24,11 +24,20 @@ async fn main() -> anyhow::Result<()> {
             Ok(deepseek_api::StreamChunk::Content(text)) => println!("Content: {text}"),
             Ok(deepseek_api::StreamChunk::Thinking(text)) => println!("Thinking: {text}"),
             Ok(deepseek_api::StreamChunk::Message(msg)) => println!("Final message: {msg:#?}"),
+            Ok(deepseek_api::StreamChunk::Warning(warning)) => eprintln!("Warning: {warning:?}"),
+            Ok(deepseek_api::StreamChunk::Image(image)) => println!("Image: {image:?}"),
+            Ok(deepseek_api::StreamChunk::Usage(tokens)) => eprintln!("Tokens used: {tokens}"),
+            Ok(deepseek_api::StreamChunk::SearchResults(results)) => {
+                for result in results {
+                    println!("Source: {} <{}>", result.title, result.url);
+                }
+            }
+            Ok(_) => (),
             Err(e) => eprintln!("Error: {e}"),
         }
     }
 
-    // If the final message's status is "INCOMPLETE", you can continue it by calling:
+    // If the final message's status is `MessageStatus::Incomplete`, you can continue it by calling:
     // let mut continue_stream = api.continue_stream(chat_id.to_string(), final_msg.message_id.unwrap(), true);
     // while let Some(chunk) = continue_stream.next().await { ... }
 
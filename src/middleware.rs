@@ -0,0 +1,82 @@
+//! Middleware wrapping the HTTP requests sent by [`DeepSeekAPI`](crate::DeepSeekAPI).
+//!
+//! Middleware is registered with
+//! [`DeepSeekAPIBuilder::middleware`](crate::DeepSeekAPIBuilder::middleware) and
+//! sees every outgoing request, including `PoW` challenge requests and streaming
+//! completions. Each middleware receives the request and a [`Next`] that passes it
+//! on to the next middleware, or sends it once all have run. A middleware may
+//! modify the request, inspect the response or answer without calling `next`.
+
+use anyhow::Result;
+use futures_util::future::BoxFuture;
+use reqwest::{Client, Request, Response, header};
+use std::future::Future;
+use std::sync::Arc;
+
+/// A layer around the client's HTTP requests.
+///
+/// Implemented for async closures `Fn(Request, Next) -> impl Future<Output =
+/// anyhow::Result<Response>>`.
+pub trait Middleware: Send + Sync + 'static {
+    /// Handles `request`, usually by calling [`Next::run`].
+    fn handle(&self, request: Request, next: Next) -> BoxFuture<'static, Result<Response>>;
+}
+
+impl<F, Fut> Middleware for F
+where
+    F: Fn(Request, Next) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<Response>> + Send + 'static,
+{
+    fn handle(&self, request: Request, next: Next) -> BoxFuture<'static, Result<Response>> {
+        Box::pin(self(request, next))
+    }
+}
+
+/// The rest of the middleware chain, ending with sending the request.
+#[derive(Clone)]
+pub struct Next {
+    client: Client,
+    chain: Arc<[Arc<dyn Middleware>]>,
+    position: usize,
+    /// Added to the request when it is sent, unless a middleware set its own.
+    authorization: header::HeaderValue,
+}
+
+impl Next {
+    pub(crate) fn new(
+        client: Client,
+        chain: Arc<[Arc<dyn Middleware>]>,
+        authorization: header::HeaderValue,
+    ) -> Self {
+        Self {
+            client,
+            chain,
+            position: 0,
+            authorization,
+        }
+    }
+
+    /// Passes `request` to the next middleware, or sends it if there is none.
+    ///
+    /// # Errors
+    /// Returns the error of a later middleware, or of sending the request.
+    pub fn run(mut self, request: Request) -> BoxFuture<'static, Result<Response>> {
+        match self.chain.get(self.position).cloned() {
+            Some(middleware) => {
+                self.position += 1;
+                middleware.handle(request, self)
+            }
+            None => Box::pin(async move {
+                let mut request = request;
+                let headers = request.headers_mut();
+                headers
+                    .entry(header::AUTHORIZATION)
+                    .or_insert(self.authorization);
+                headers
+                    .entry(header::CONTENT_TYPE)
+                    .or_insert(header::HeaderValue::from_static("application/json"));
+                Ok(self.client.execute(request).await?)
+            }),
+        }
+    }
+}
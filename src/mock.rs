@@ -0,0 +1,78 @@
+//! Offline clients for tests, enabled by the `mock` feature.
+//!
+//! [`DeepSeekAPI::mock`] builds a client that never touches the network: `PoW`
+//! is disabled and every request is answered by a closure, typically with
+//! responses built by [`json`] and [`event_stream`]. This lets code holding a
+//! [`DeepSeekAPI`] be tested deterministically.
+//!
+//! ```
+//! use deepseek_api::{DeepSeekAPI, mock};
+//!
+//! let api = DeepSeekAPI::mock(|request| {
+//!     Ok(if request.url().path().ends_with("/chat/completion") {
+//!         mock::event_stream(concat!(
+//!             "data: {\"v\":{\"response\":{\"message_id\":2}}}\n",
+//!             "data: {\"p\":\"response/content\",\"o\":\"APPEND\",\"v\":\"Hi\"}\n",
+//!             "event: finish\n",
+//!         ))
+//!     } else {
+//!         mock::json(&serde_json::json!({"code": 0, "msg": ""}))
+//!     })
+//! });
+//! ```
+
+use anyhow::Result;
+use reqwest::{Request, Response, header};
+
+use crate::DeepSeekAPI;
+use crate::middleware::Next;
+
+impl DeepSeekAPI {
+    /// Builds a client that answers every request with `respond` instead of
+    /// sending it.
+    ///
+    /// `PoW` is disabled, so no challenge is requested and no WebAssembly module
+    /// is loaded. Requests reach `respond` as they would reach a
+    /// [`middleware`](crate::middleware), without the `Authorization` header. An
+    /// error returned by `respond` is returned by the method that sent the request.
+    ///
+    /// # Panics
+    /// Panics if the HTTP client cannot be constructed, which does not depend on
+    /// `respond`.
+    #[must_use]
+    pub fn mock<F>(respond: F) -> Self
+    where
+        F: Fn(&Request) -> Result<Response> + Send + Sync + 'static,
+    {
+        Self::builder("mock")
+            .disable_pow()
+            .middleware(move |request: Request, _next: Next| {
+                let response = respond(&request);
+                async move { response }
+            })
+            .build_with_solver(None)
+            .expect("the HTTP client of a mock cannot be built")
+    }
+}
+
+/// Returns a `200 OK` response with `body` as JSON, as the API answers
+/// non-streaming requests.
+#[must_use]
+pub fn json(body: &serde_json::Value) -> Response {
+    response("application/json", body.to_string())
+}
+
+/// Returns a `200 OK` event stream with the raw server-sent events of `body`, as
+/// the API answers completion requests.
+#[must_use]
+pub fn event_stream(body: impl Into<String>) -> Response {
+    response("text/event-stream", body.into())
+}
+
+fn response(content_type: &'static str, body: String) -> Response {
+    http::Response::builder()
+        .header(header::CONTENT_TYPE, content_type)
+        .body(body)
+        .expect("static response parts are valid")
+        .into()
+}
@@ -1,8 +1,11 @@
 use anyhow::{anyhow, Result};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::DeepSeekError;
 
 /// Information about an uploaded file.
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct FileInfo {
     pub id: String,
     pub status: String,
@@ -15,14 +18,15 @@ pub struct FileInfo {
     pub updated_at: f64,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Message {
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub message_id: Option<i64>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub parent_id: Option<i64>,
+    /// The author of the message, if the server reported one.
     #[serde(default, skip_serializing_if = "Option::is_none")]
-    pub role: Option<String>,
+    pub role: Option<Role>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub inserted_at: Option<f64>,
     #[serde(default)]
@@ -30,13 +34,304 @@ pub struct Message {
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub thinking_content: Option<String>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
-    pub status: Option<String>,
+    pub status: Option<MessageStatus>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub accumulated_token_usage: Option<i64>,
+    /// The web search results the answer is based on, when search was enabled.
+    #[serde(
+        default,
+        rename = "search_results",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub citations: Option<Vec<SearchCitation>>,
+}
+
+/// The author of a [`Message`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(from = "String", into = "String")]
+pub enum Role {
+    /// `"USER"`
+    User,
+    /// `"ASSISTANT"`
+    Assistant,
+    /// `"SYSTEM"`
+    System,
+    /// Any other role, with its raw value.
+    Other(String),
+}
+
+impl Role {
+    /// Returns the role as sent by the server, e.g. `"ASSISTANT"`.
+    #[must_use]
+    pub fn as_str(&self) -> &str {
+        match self {
+            Self::User => "USER",
+            Self::Assistant => "ASSISTANT",
+            Self::System => "SYSTEM",
+            Self::Other(role) => role,
+        }
+    }
+}
+
+impl From<String> for Role {
+    fn from(role: String) -> Self {
+        match role.to_ascii_uppercase().as_str() {
+            "USER" => Self::User,
+            "ASSISTANT" => Self::Assistant,
+            "SYSTEM" => Self::System,
+            _ => Self::Other(role),
+        }
+    }
+}
+
+impl From<Role> for String {
+    fn from(role: Role) -> Self {
+        match role {
+            Role::Other(role) => role,
+            role => role.as_str().to_string(),
+        }
+    }
+}
+
+impl std::fmt::Display for Role {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The generation status of a [`Message`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(from = "String", into = "String")]
+pub enum MessageStatus {
+    /// `"WIP"`: the message is still being generated.
+    Wip,
+    /// `"FINISHED"`: the message is complete.
+    Finished,
+    /// `"INCOMPLETE"`: generation stopped early and can be continued with
+    /// [`DeepSeekAPI::continue_stream`](crate::DeepSeekAPI::continue_stream).
+    Incomplete,
+    /// Any other status, with its raw value.
+    Other(String),
+}
+
+impl MessageStatus {
+    /// Returns the status as sent by the server, e.g. `"FINISHED"`.
+    #[must_use]
+    pub fn as_str(&self) -> &str {
+        match self {
+            Self::Wip => "WIP",
+            Self::Finished => "FINISHED",
+            Self::Incomplete => "INCOMPLETE",
+            Self::Other(status) => status,
+        }
+    }
+}
+
+impl From<String> for MessageStatus {
+    fn from(status: String) -> Self {
+        match status.to_ascii_uppercase().as_str() {
+            "WIP" => Self::Wip,
+            "FINISHED" => Self::Finished,
+            "INCOMPLETE" => Self::Incomplete,
+            _ => Self::Other(status),
+        }
+    }
+}
+
+impl From<MessageStatus> for String {
+    fn from(status: MessageStatus) -> Self {
+        match status {
+            MessageStatus::Other(status) => status,
+            status => status.as_str().to_string(),
+        }
+    }
+}
+
+impl std::fmt::Display for MessageStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl Message {
+    /// Returns `content` with `DeepSeek` markup removed, for plain-text output.
+    ///
+    /// See [`plain_content_with_references`](Self::plain_content_with_references)
+    /// for the markup that is removed. `content` itself is left untouched.
+    #[must_use]
+    pub fn plain_content(&self) -> String {
+        self.plain_content_with_references().0
+    }
+
+    /// Like [`plain_content`](Self::plain_content), but also returns the citation
+    /// indices that were removed, in order of appearance.
+    ///
+    /// The following markup is removed:
+    /// - citation markers `[citation:N]`, inserted when search is enabled; `N` is
+    ///   the [`SearchCitation::cite_index`] of the cited result.
+    /// - special tokens delimited by `<｜` and `｜>` (full-width bars), such as
+    ///   `<｜end▁of▁sentence｜>`, which occasionally leak into the content.
+    ///
+    /// Other bracketed text, including bare `[1]`, is kept as is.
+    #[must_use]
+    pub fn plain_content_with_references(&self) -> (String, Vec<i64>) {
+        const CITATION_START: &str = "[citation:";
+        const TOKEN_START: &str = "<｜";
+        const TOKEN_END: &str = "｜>";
+
+        let mut plain = String::with_capacity(self.content.len());
+        let mut references = Vec::new();
+        let mut rest = self.content.as_str();
+        while !rest.is_empty() {
+            if let Some(after) = rest.strip_prefix(CITATION_START)
+                && let Some((index, tail)) = after.split_once(']')
+                && let Ok(index) = index.trim().parse()
+            {
+                references.push(index);
+                rest = tail;
+            } else if let Some(after) = rest.strip_prefix(TOKEN_START)
+                && let Some((_, tail)) = after.split_once(TOKEN_END)
+            {
+                rest = tail;
+            } else {
+                let mut chars = rest.chars();
+                plain.extend(chars.next());
+                rest = chars.as_str();
+            }
+        }
+        (plain, references)
+    }
+
+    /// Returns the images embedded in the content, in order.
+    ///
+    /// Images are recognized in Markdown image syntax, `![alt](target)`, which is
+    /// how the web app renders them; see [`InlineImage`] for the targets handled.
+    /// This is a heuristic, as the server has no separate field for images: a
+    /// Markdown image the model writes out, e.g. in an example, is returned too.
+    #[must_use]
+    pub fn inline_images(&self) -> Vec<InlineImage> {
+        let mut images = Vec::new();
+        let mut rest = self.content.as_str();
+        while let Some(start) = rest.find(IMAGE_START) {
+            rest = &rest[start..];
+            match parse_image(rest) {
+                ImageParse::Image(image, end) => {
+                    images.push(image);
+                    rest = &rest[end..];
+                }
+                ImageParse::Incomplete | ImageParse::Invalid => rest = &rest[IMAGE_START.len()..],
+            }
+        }
+        images
+    }
+}
+
+/// An image embedded in message content as `![alt](url_or_data)`.
+///
+/// The target is one of:
+/// - a data URI such as `data:image/png;base64,iVBOR…`, see
+///   [`data_uri`](Self::data_uri),
+/// - the id of an uploaded file, starting with `file-`, see
+///   [`file_id`](Self::file_id),
+/// - any other URL, to be fetched by the caller.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InlineImage {
+    /// The target of the image, without any Markdown title.
+    pub url_or_data: String,
+    /// The alternative text, possibly empty.
+    pub alt: String,
+}
+
+impl InlineImage {
+    /// Returns the media type and base64 payload of a `data:` URI target, e.g.
+    /// `("image/png", "iVBOR…")`.
+    #[must_use]
+    pub fn data_uri(&self) -> Option<(&str, &str)> {
+        let (header, data) = self.url_or_data.strip_prefix("data:")?.split_once(',')?;
+        let media_type = header.strip_suffix(";base64")?;
+        Some((media_type, data))
+    }
+
+    /// Returns the file id of a target referencing an uploaded file, recognized by
+    /// the `file-` prefix of the ids the API assigns to uploads.
+    #[must_use]
+    pub fn file_id(&self) -> Option<&str> {
+        self.url_or_data
+            .starts_with("file-")
+            .then_some(self.url_or_data.as_str())
+    }
+}
+
+/// Opens Markdown image syntax.
+pub(crate) const IMAGE_START: &str = "![";
+
+/// Outcome of [`parse_image`].
+pub(crate) enum ImageParse {
+    /// An image, and the length of its markup.
+    Image(InlineImage, usize),
+    /// The text may become an image once more of it is received.
+    Incomplete,
+    /// The text does not start with an image.
+    Invalid,
+}
+
+/// Parses the Markdown image at the start of `text`, which starts with
+/// [`IMAGE_START`]. Images do not span lines.
+pub(crate) fn parse_image(text: &str) -> ImageParse {
+    let after = &text[IMAGE_START.len()..];
+    let line = after.split_once('\n').map_or(after, |(line, _)| line);
+    let complete_line = line.len() < after.len();
+    let incomplete = || {
+        if complete_line {
+            ImageParse::Invalid
+        } else {
+            ImageParse::Incomplete
+        }
+    };
+    let Some((alt, rest)) = line.split_once(']') else {
+        return incomplete();
+    };
+    let Some(rest) = rest.strip_prefix('(') else {
+        return if rest.is_empty() {
+            incomplete()
+        } else {
+            ImageParse::Invalid
+        };
+    };
+    let Some((target, _)) = rest.split_once(')') else {
+        return incomplete();
+    };
+    // Drop an optional title, as in `![alt](url "title")`
+    let Some(url_or_data) = target.split_whitespace().next() else {
+        return ImageParse::Invalid;
+    };
+    let image = InlineImage {
+        url_or_data: url_or_data.to_string(),
+        alt: alt.to_string(),
+    };
+    let end = IMAGE_START.len() + alt.len() + "](".len() + target.len() + ")".len();
+    ImageParse::Image(image, end)
+}
+
+/// A web search result the model used while answering with search enabled.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SearchCitation {
+    #[serde(default)]
+    pub url: String,
+    #[serde(default)]
+    pub title: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub snippet: Option<String>,
+    /// The index used to cite this result in the content, e.g. `[1]`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cite_index: Option<i64>,
 }
 
 /// Chat session information.
-#[derive(Debug, Clone, Deserialize)]
+///
+/// Sessions form a flat list: the API has no folders or projects to organize
+/// them, only [`pinned`](Self::pinned) sessions.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ChatSession {
     pub id: String,
     pub seq_id: i64,
@@ -50,6 +345,101 @@ pub struct ChatSession {
     pub updated_at: f64,
 }
 
+/// A page of the account's chat sessions, see
+/// [`DeepSeekAPI::list_chats_page`](crate::DeepSeekAPI::list_chats_page).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChatSessionPage {
+    /// The sessions of the page, most recently updated first.
+    pub chat_sessions: Vec<ChatSession>,
+    /// Whether older sessions follow this page.
+    pub has_more: bool,
+}
+
+/// Summary of a chat session, see
+/// [`DeepSeekAPI::get_session_stats`](crate::DeepSeekAPI::get_session_stats).
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct SessionStats {
+    /// The number of messages, counting all branches.
+    pub message_count: usize,
+    /// The `accumulated_token_usage` of the current message, or 0.
+    pub total_tokens: i64,
+    /// When the oldest message was sent, as a Unix timestamp in seconds.
+    pub first_message_at: Option<f64>,
+    /// When the newest message was sent, as a Unix timestamp in seconds.
+    pub last_message_at: Option<f64>,
+}
+
+/// A chat session and its messages, exported with
+/// [`DeepSeekAPI::export_conversation`](crate::DeepSeekAPI::export_conversation).
+///
+/// The JSON form is stable: documents written by older versions of the crate can
+/// be imported as long as [`version`](Self::version) is supported.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConversationExport {
+    /// The schema version, [`ConversationExport::VERSION`] when exported.
+    pub version: u32,
+    pub session: ChatSession,
+    /// All messages of the session, including other branches, ordered by id.
+    pub messages: Vec<ExportedMessage>,
+}
+
+impl ConversationExport {
+    /// The current schema version.
+    pub const VERSION: u32 = 1;
+}
+
+/// A message of a [`ConversationExport`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExportedMessage {
+    #[serde(flatten)]
+    pub message: Message,
+    /// IDs of the uploaded files the message referenced.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ref_file_ids: Vec<String>,
+}
+
+/// Session metadata reported at the end of a completion stream.
+///
+/// The stream carries only the fields that changed; use
+/// [`DeepSeekAPI::get_chat_info`](crate::DeepSeekAPI::get_chat_info) for the full
+/// [`ChatSession`].
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SessionUpdate {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub current_message_id: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+}
+
+impl SessionUpdate {
+    /// Overwrites the fields of `self` that are set in `newer`.
+    pub fn merge(&mut self, newer: SessionUpdate) {
+        self.updated_at = newer.updated_at.or(self.updated_at);
+        self.version = newer.version.or(self.version);
+        self.current_message_id = newer.current_message_id.or(self.current_message_id);
+        self.title = newer.title.or(self.title.take());
+    }
+
+    /// Applies the data of an `update_session` or `title` stream event.
+    pub(crate) fn apply_event(&mut self, event: &[u8], data: &[u8]) -> Result<()> {
+        #[derive(Deserialize)]
+        struct Title {
+            content: String,
+        }
+        if event == b"title" {
+            let title: Title = serde_json::from_slice(data)?;
+            self.title = Some(title.content);
+        } else {
+            self.merge(serde_json::from_slice(data)?);
+        }
+        Ok(())
+    }
+}
+
 /// Streaming update from the server.
 #[derive(Debug, Deserialize, Clone)]
 pub struct StreamingUpdate {
@@ -57,19 +447,25 @@ pub struct StreamingUpdate {
     pub p: Option<String>, // JSON pointer path
     pub v: Option<serde_json::Value>, // value
     pub o: Option<String>, // operation (SET, APPEND, etc.)
+    /// Ordering key of the patch, if the server sends one. `APPEND` patches that
+    /// carry it are concatenated in sequence order rather than arrival order
+    /// when the message is built. Only the built message is reordered: streamed
+    /// content and thinking fragments are yielded in arrival order.
+    #[serde(default)]
+    pub seq: Option<u64>,
 }
 
 /// Builder that accumulates patches into a final Message.
 #[derive(Debug)]
 pub struct StreamingMessageBuilder {
     inner: serde_json::Value,
+    /// Sequenced `APPEND` values per path, applied in order by `build`.
+    pending_appends: BTreeMap<String, BTreeMap<u64, String>>,
 }
 
 impl Default for StreamingMessageBuilder {
     fn default() -> Self {
-        Self {
-            inner: serde_json::json!({}),
-        }
+        Self::from_state(serde_json::json!({}))
     }
 }
 
@@ -79,20 +475,71 @@ impl StreamingMessageBuilder {
     /// # Errors
     /// Returns an error if the provided value cannot be interpreted as a valid builder state.
     /// (Currently always returns `Ok`.)
-    pub fn from_value(v: serde_json::Value) -> Result<Self> {
-        Ok(Self { inner: v })
+    pub fn from_value(v: serde_json::Value) -> Result<Self, DeepSeekError> {
+        Ok(Self::from_state(v))
+    }
+
+    fn from_state(inner: serde_json::Value) -> Self {
+        Self {
+            inner,
+            pending_appends: BTreeMap::new(),
+        }
+    }
+
+    /// Returns the id of the message being built, once the server has sent it.
+    #[must_use]
+    pub fn message_id(&self) -> Option<i64> {
+        self.inner
+            .get("response")
+            .unwrap_or(&self.inner)
+            .get("message_id")
+            .and_then(serde_json::Value::as_i64)
+    }
+
+    /// Returns the string at `path` of the message being built, if there is one.
+    ///
+    /// Sequenced appends not yet applied by [`build`](Self::build) are not included.
+    #[must_use]
+    pub fn text_at(&self, path: &str) -> Option<&str> {
+        self.inner
+            .pointer(&format!("/{path}"))
+            .and_then(serde_json::Value::as_str)
     }
 
     /// Applies a streaming update to the builder.
     ///
+    /// `APPEND` updates carrying a [`seq`](StreamingUpdate::seq) are buffered and
+    /// appended in sequence order when the message is built, after any unsequenced
+    /// appends to the same path. A `SET` discards the buffered appends of its path.
+    ///
     /// # Errors
     /// Returns an error if the path is empty or invalid, the operation is unknown,
     /// or an `APPEND` operation is used on a non‑string field.
-    pub fn apply_update(&mut self, update: &StreamingUpdate) -> Result<()> {
+    pub fn apply_update(&mut self, update: &StreamingUpdate) -> Result<(), DeepSeekError> {
         let path = update.p.as_deref().ok_or_else(|| anyhow!("Missing path"))?;
         let value = update.v.as_ref().ok_or_else(|| anyhow!("Missing v"))?;
         let operation = update.o.as_deref().unwrap_or("SET");
 
+        match (operation, update.seq) {
+            ("APPEND", Some(seq)) => {
+                let serde_json::Value::String(append) = value else {
+                    return Err(anyhow!("APPEND only supported on strings at {path}").into());
+                };
+                self.pending_appends
+                    .entry(path.to_string())
+                    .or_default()
+                    .insert(seq, append.clone());
+                return Ok(());
+            }
+            ("SET", _) => {
+                self.pending_appends.remove(path);
+            }
+            _ => (),
+        }
+        Ok(self.apply(path, value, operation)?)
+    }
+
+    fn apply(&mut self, path: &str, value: &serde_json::Value, operation: &str) -> Result<()> {
         let keys: Vec<&str> = path.split('/').collect();
         if keys.is_empty() {
             anyhow::bail!("Empty path");
@@ -145,11 +592,133 @@ impl StreamingMessageBuilder {
     ///
     /// # Errors
     /// Returns an error if the accumulated state cannot be deserialized into a `Message`.
-    pub fn build(self) -> Result<Message> {
+    pub fn build(mut self) -> Result<Message, DeepSeekError> {
+        for (path, appends) in std::mem::take(&mut self.pending_appends) {
+            let value = serde_json::Value::String(appends.into_values().collect());
+            self.apply(&path, &value, "APPEND")?;
+        }
         if let Some(response) = self.inner.get("response") {
             serde_json::from_value(response.clone()).map_err(Into::into)
         } else {
             serde_json::from_value(self.inner).map_err(Into::into)
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roles_are_mapped() {
+        let role =
+            |value: &str| -> Role { serde_json::from_value(serde_json::json!(value)).unwrap() };
+        assert_eq!(role("ASSISTANT"), Role::Assistant);
+        assert_eq!(role("user"), Role::User);
+        assert_eq!(role("TOOL"), Role::Other("TOOL".to_string()));
+        assert_eq!(serde_json::to_value(Role::System).unwrap(), "SYSTEM");
+        let message: Message =
+            serde_json::from_value(serde_json::json!({"role": "ASSISTANT"})).unwrap();
+        assert_eq!(message.role, Some(Role::Assistant));
+    }
+
+    /// Deserializes `value`, re-serializes it and checks that deserializing the
+    /// result gives the same value.
+    fn round_trip<T>(value: &serde_json::Value) -> T
+    where
+        T: serde::de::DeserializeOwned + Serialize + PartialEq + std::fmt::Debug,
+    {
+        let parsed: T = serde_json::from_value(value.clone()).unwrap();
+        let json = serde_json::to_string(&parsed).unwrap();
+        assert_eq!(serde_json::from_str::<T>(&json).unwrap(), parsed);
+        parsed
+    }
+
+    #[test]
+    fn models_round_trip() {
+        let session: ChatSession = round_trip(&serde_json::json!({
+            "id": "chat", "seq_id": 7, "agent": "chat", "title": "Greeting",
+            "title_type": "SYSTEM", "version": 3, "current_message_id": 2,
+            "pinned": false, "inserted_at": 1_740_000_000.123_456, "updated_at": 1_740_000_001.5
+        }));
+        let json = serde_json::to_value(&session).unwrap();
+        assert_eq!(json["inserted_at"], 1_740_000_000.123_456);
+        assert_eq!(json["updated_at"], 1_740_000_001.5);
+
+        let file: FileInfo = round_trip(&serde_json::json!({
+            "id": "file-1", "status": "SUCCESS", "file_name": "notes.txt",
+            "previewable": true, "file_size": 11, "token_usage": 3,
+            "error_code": null, "inserted_at": 1_740_000_000.25, "updated_at": 1_740_000_000.75
+        }));
+        assert_eq!(
+            serde_json::to_value(&file).unwrap()["inserted_at"],
+            1_740_000_000.25
+        );
+
+        let message: Message = round_trip(&serde_json::json!({
+            "message_id": 2, "parent_id": 1, "role": "ASSISTANT", "content": "Hello",
+            "thinking_content": "Greet back", "status": "FINISHED",
+            "inserted_at": 1_740_000_000.5, "accumulated_token_usage": 12,
+            "search_results": [{"url": "https://example.com", "title": "Example", "cite_index": 1}]
+        }));
+        assert_eq!(message.role, Some(Role::Assistant));
+    }
+
+    #[test]
+    fn statuses_are_mapped() {
+        let message: Message =
+            serde_json::from_value(serde_json::json!({"status": "INCOMPLETE"})).unwrap();
+        assert_eq!(message.status, Some(MessageStatus::Incomplete));
+        let status = MessageStatus::from("CONTENT_FILTER".to_string());
+        assert_eq!(status, MessageStatus::Other("CONTENT_FILTER".to_string()));
+        assert_eq!(serde_json::to_value(status).unwrap(), "CONTENT_FILTER");
+        assert_eq!(
+            serde_json::to_value(MessageStatus::Finished).unwrap(),
+            "FINISHED"
+        );
+    }
+
+    #[test]
+    fn plain_content_strips_markup() {
+        let message: Message = serde_json::from_value(serde_json::json!({
+            "content": "Paris[citation:1] is the capital[citation: 3].<｜end▁of▁sentence｜> See [2].",
+        }))
+        .unwrap();
+        let (plain, references) = message.plain_content_with_references();
+        assert_eq!(plain, "Paris is the capital. See [2].");
+        assert_eq!(references, [1, 3]);
+        assert!(message.content.contains("[citation:1]"));
+    }
+
+    #[test]
+    fn inline_images_are_extracted() {
+        let message: Message = serde_json::from_value(serde_json::json!({
+            "content": "A chart: ![Sales](data:image/png;base64,iVBOR \"Q1\") and ![](file-abc) \
+                        but not ![broken]\n(x) nor [link](https://example.com). ![Logo](https://example.com/logo.png)",
+        }))
+        .unwrap();
+        let images = message.inline_images();
+        assert_eq!(images.len(), 3);
+        assert_eq!(images[0].alt, "Sales");
+        assert_eq!(images[0].data_uri(), Some(("image/png", "iVBOR")));
+        assert_eq!(images[1].file_id(), Some("file-abc"));
+        assert_eq!(images[2].url_or_data, "https://example.com/logo.png");
+        assert_eq!(images[2].data_uri(), None);
+    }
+
+    #[test]
+    fn sequenced_appends_are_reordered() {
+        let mut builder = StreamingMessageBuilder::default();
+        for (seq, text) in [(2, "c"), (0, "a"), (1, "b")] {
+            let update: StreamingUpdate = serde_json::from_value(serde_json::json!({
+                "p": "response/content",
+                "o": "APPEND",
+                "v": text,
+                "seq": seq,
+            }))
+            .unwrap();
+            builder.apply_update(&update).unwrap();
+        }
+        assert_eq!(builder.build().unwrap().content, "abc");
+    }
+}
@@ -0,0 +1,104 @@
+//! Strategies for polling the server until an operation completes, see
+//! [`DeepSeekAPI::wait_for_file_processing_with`](crate::DeepSeekAPI::wait_for_file_processing_with)
+//! and [`DeepSeekAPI::wait_for_title`](crate::DeepSeekAPI::wait_for_title).
+
+use std::time::Duration;
+
+/// Decides how long to wait before polling again.
+pub trait PollStrategy: Send + Sync {
+    /// Returns the delay before the next poll, or `None` to give up.
+    ///
+    /// `attempt` is the number of polls made so far (starting at 1) and `status`
+    /// the status reported by the last one, e.g. `PENDING`.
+    fn next_delay(&self, attempt: usize, status: &str) -> Option<Duration>;
+}
+
+/// Polls at a fixed interval, up to a maximum number of attempts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConstantDelay {
+    max_attempts: usize,
+    delay: Duration,
+}
+
+impl ConstantDelay {
+    /// Polls up to `max_attempts` times, `delay` apart.
+    #[must_use]
+    pub fn new(max_attempts: usize, delay: Duration) -> Self {
+        Self {
+            max_attempts,
+            delay,
+        }
+    }
+}
+
+impl PollStrategy for ConstantDelay {
+    fn next_delay(&self, attempt: usize, _status: &str) -> Option<Duration> {
+        (attempt < self.max_attempts).then_some(self.delay)
+    }
+}
+
+/// Polls quickly at first, doubling the delay after every attempt up to a cap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExponentialBackoff {
+    max_attempts: usize,
+    initial_delay: Duration,
+    max_delay: Duration,
+}
+
+impl ExponentialBackoff {
+    /// Polls up to `max_attempts` times, waiting `initial_delay` after the first
+    /// attempt and twice as long after each subsequent one, but never more than
+    /// `max_delay`.
+    #[must_use]
+    pub fn new(max_attempts: usize, initial_delay: Duration, max_delay: Duration) -> Self {
+        Self {
+            max_attempts,
+            initial_delay,
+            max_delay,
+        }
+    }
+}
+
+impl PollStrategy for ExponentialBackoff {
+    fn next_delay(&self, attempt: usize, _status: &str) -> Option<Duration> {
+        if attempt >= self.max_attempts {
+            return None;
+        }
+        let exponent = u32::try_from(attempt.saturating_sub(1)).unwrap_or(u32::MAX);
+        let delay = 2u32
+            .checked_pow(exponent)
+            .and_then(|factor| self.initial_delay.checked_mul(factor))
+            .unwrap_or(self.max_delay);
+        Some(delay.min(self.max_delay))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn constant_delay_gives_up_after_max_attempts() {
+        let strategy = ConstantDelay::new(2, Duration::from_secs(2));
+        assert_eq!(
+            strategy.next_delay(1, "PENDING"),
+            Some(Duration::from_secs(2))
+        );
+        assert_eq!(strategy.next_delay(2, "PENDING"), None);
+    }
+
+    #[test]
+    fn exponential_backoff_doubles_up_to_cap() {
+        let strategy =
+            ExponentialBackoff::new(40, Duration::from_millis(100), Duration::from_millis(500));
+        let delays: Vec<_> = (1..=5)
+            .map(|attempt| strategy.next_delay(attempt, "PENDING").unwrap().as_millis())
+            .collect();
+        assert_eq!(delays, [100, 200, 400, 500, 500]);
+        assert_eq!(
+            strategy.next_delay(39, "PENDING"),
+            Some(Duration::from_millis(500))
+        );
+        assert_eq!(strategy.next_delay(40, "PENDING"), None);
+    }
+}
@@ -3,10 +3,37 @@
 use anyhow::{Context, Result, anyhow};
 use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
 use serde::{Deserialize, Serialize};
-use wasmtime::{Engine, Instance, Memory, Module, Store, TypedFunc};
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::{Mutex, OwnedMutexGuard};
+use wasmtime::{
+    Config, Engine, Instance, InstanceAllocationStrategy, Memory, Module, PoolingAllocationConfig,
+    Store, StoreLimits, StoreLimitsBuilder, TypedFunc,
+};
 
-use crate::wasm_download::get_wasm_path;
+use crate::error::{DeepSeekError, Shared};
+use crate::wasm_download::WasmSource;
+#[cfg(not(feature = "embed-wasm"))]
+use crate::wasm_download::get_wasm_in;
 
+/// The `PoW` WASM module embedded with the `embed-wasm` feature.
+#[cfg(feature = "embed-wasm")]
+static EMBEDDED_WASM: &[u8] = include_bytes!(env!(
+    "DEEPSEEK_POW_WASM",
+    "the `embed-wasm` feature needs DEEPSEEK_POW_WASM set to the absolute path of the PoW WASM module"
+));
+
+/// The `PoW` algorithm implemented by the `DeepSeek` WASM module.
+pub const SUPPORTED_ALGORITHM: &str = "DeepSeekHashV1";
+
+/// How long before its expiry a challenge is no longer solved, since the
+/// solution would likely reach the server too late.
+pub const EXPIRY_MARGIN: Duration = Duration::from_secs(2);
+
+/// A `PoW` challenge issued by the server for a request to `target_path`.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 
 pub struct Challenge {
@@ -20,38 +47,347 @@ pub struct Challenge {
     pub target_path: String,
 }
 
-#[derive(Debug, Serialize)]
+impl Challenge {
+    /// Returns whether the challenge has expired, given the server clock is
+    /// `skew` seconds ahead of the local one.
+    #[must_use]
+    pub fn is_expired(&self, skew: f64) -> bool {
+        is_expired(self.expire_at, skew)
+    }
+
+    /// Checks that the challenge expires later than [`EXPIRY_MARGIN`] from now,
+    /// given the server clock is `skew` seconds ahead of the local one.
+    ///
+    /// # Errors
+    /// Returns [`DeepSeekError::PowChallengeExpired`] otherwise.
+    pub fn check_expiry(&self, skew: f64) -> Result<(), DeepSeekError> {
+        if expires_within(self.expire_at, skew, EXPIRY_MARGIN) {
+            return Err(DeepSeekError::PowChallengeExpired {
+                expire_at: self.expire_at,
+            });
+        }
+        Ok(())
+    }
+
+    /// Describes the challenge for error messages, shortening its value.
+    fn summary(&self) -> String {
+        const MAX_CHARS: usize = 16;
+        let mut value: String = self.value.chars().take(MAX_CHARS).collect();
+        if self.value.chars().nth(MAX_CHARS).is_some() {
+            value.push('…');
+        }
+        format!(
+            "PoW challenge {value} (algorithm {}, salt {}, difficulty {})",
+            self.algorithm, self.salt, self.difficulty
+        )
+    }
+
+    /// Checks that the challenge uses the algorithm the WASM module implements,
+    /// which would otherwise silently produce a wrong answer.
+    ///
+    /// # Errors
+    /// Returns [`DeepSeekError::UnsupportedPowAlgorithm`] for any other algorithm.
+    pub fn check_algorithm(&self) -> Result<(), DeepSeekError> {
+        if self.algorithm == SUPPORTED_ALGORITHM {
+            Ok(())
+        } else {
+            Err(DeepSeekError::UnsupportedPowAlgorithm {
+                algorithm: self.algorithm.clone(),
+                supported: SUPPORTED_ALGORITHM.to_string(),
+            })
+        }
+    }
+
+    /// Checks locally that `answer` solves the challenge, without the WASM
+    /// module, e.g. to cross-check the solver when the server rejects a `PoW`.
+    ///
+    /// Verifies the `DeepSeekHashV1` algorithm: the answer is valid if it lies in
+    /// `0..difficulty` (the range the solver searches) and the hash of
+    /// `{salt}_{expire_at}_{answer}`, hex-encoded, equals the challenge value.
+    /// The hash is SHA3-256 with the first of the 24 rounds of the Keccak-f[1600]
+    /// permutation skipped, i.e. Keccak-p[1600, 23] in the SHA3-256 sponge. It
+    /// always returns `false` for challenges of other algorithms.
+    #[must_use]
+    pub fn verify_answer(&self, answer: i64) -> bool {
+        #[allow(clippy::cast_precision_loss)]
+        let in_range = answer >= 0 && (answer as f64) < self.difficulty;
+        if self.algorithm != SUPPORTED_ALGORITHM || !in_range {
+            return false;
+        }
+        let input = format!("{}_{}_{answer}", self.salt, self.expire_at);
+        hex(&sha3_256(input.as_bytes(), 23)).eq_ignore_ascii_case(&self.value)
+    }
+}
+
+/// Encodes `bytes` as lowercase hex.
+fn hex(bytes: &[u8]) -> String {
+    use std::fmt::Write;
+
+    bytes.iter().fold(String::new(), |mut hex, byte| {
+        let _ = write!(hex, "{byte:02x}");
+        hex
+    })
+}
+
+/// Hashes `data` with SHA3-256, running the last `rounds` rounds of the
+/// Keccak-f[1600] permutation (all 24 for standard SHA3-256).
+fn sha3_256(data: &[u8], rounds: usize) -> [u8; 32] {
+    const RATE: usize = 136;
+
+    let mut state = [0u64; 25];
+    let mut absorb = |block: &[u8; RATE]| {
+        for (lane, bytes) in state.iter_mut().zip(block.chunks_exact(8)) {
+            *lane ^= u64::from_le_bytes(bytes.try_into().expect("8-byte chunk"));
+        }
+        keccak::p1600(&mut state, rounds);
+    };
+    let mut blocks = data.chunks_exact(RATE);
+    for block in &mut blocks {
+        absorb(block.try_into().expect("block of RATE bytes"));
+    }
+    // SHA3 domain separation and pad10*1
+    let rest = blocks.remainder();
+    let mut last = [0u8; RATE];
+    last[..rest.len()].copy_from_slice(rest);
+    last[rest.len()] ^= 0x06;
+    last[RATE - 1] ^= 0x80;
+    absorb(&last);
+
+    let mut hash = [0u8; 32];
+    for (bytes, lane) in hash.chunks_exact_mut(8).zip(state) {
+        bytes.copy_from_slice(&lane.to_le_bytes());
+    }
+    hash
+}
+
+/// Returns whether a challenge expiring at `expire_at` has expired, given the
+/// server clock is `skew` seconds ahead of the local one.
+pub fn is_expired(expire_at: i64, skew: f64) -> bool {
+    expires_within(expire_at, skew, Duration::ZERO)
+}
+
+/// Returns whether a challenge expiring at `expire_at` expires within `margin`
+/// from now, given the server clock is `skew` seconds ahead of the local one.
+fn expires_within(expire_at: i64, skew: f64, margin: Duration) -> bool {
+    let Ok(now) = SystemTime::now().duration_since(UNIX_EPOCH) else {
+        return false;
+    };
+    let now = now + margin;
+    let server_now = if skew >= 0.0 {
+        now.checked_add(Duration::from_secs_f64(skew))
+    } else {
+        now.checked_sub(Duration::from_secs_f64(-skew))
+    }
+    .unwrap_or(now);
+    // `expire_at` is in milliseconds since the epoch
+    u128::try_from(expire_at).is_ok_and(|expire_at| server_now.as_millis() >= expire_at)
+}
+
+/// A solved `PoW` challenge, sent base64-encoded as the `x-ds-pow-response` header.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct SolveResponse {
     pub algorithm: String,
     pub challenge: String,
     pub salt: String,
+    /// The nonce found by the solver.
     pub answer: i64,
     pub signature: String,
     pub target_path: String,
 }
 
+impl SolveResponse {
+    /// Decodes the value of an `x-ds-pow-response` header.
+    ///
+    /// # Errors
+    /// Returns an error if `header` is not valid base64 or does not contain a
+    /// solve response.
+    pub fn from_header(header: &str) -> Result<Self, DeepSeekError> {
+        let json = BASE64
+            .decode(header.trim())
+            .context("PoW response is not valid base64")?;
+        Ok(serde_json::from_slice(&json).context("PoW response is not a valid solve response")?)
+    }
+}
+
+/// Configuration of the WebAssembly engine that solves `PoW` challenges.
+///
+/// The default leaves every setting at wasmtime's defaults. The `DeepSeek`
+/// module is a small wasm‑bindgen build whose linear memory starts at around
+/// 1 MiB and barely grows while solving; a `max_memory_size` below 2 MiB may
+/// make instantiation or solving fail.
+#[derive(Debug, Clone, Default)]
+pub struct PowConfig {
+    /// Upper bound on the module's linear memory in bytes; growing beyond it fails.
+    pub max_memory_size: Option<usize>,
+    /// Address space reserved for linear memory up front
+    /// (see [`wasmtime::Config::memory_reservation`]).
+    pub memory_reservation: Option<u64>,
+    /// Size of the guard region after linear memory
+    /// (see [`wasmtime::Config::memory_guard_size`]).
+    pub memory_guard_size: Option<u64>,
+    /// Enables the WebAssembly SIMD proposals; `None` keeps wasmtime's default.
+    pub simd: Option<bool>,
+    /// Enables the WebAssembly reference types proposal; `None` keeps wasmtime's default.
+    pub reference_types: Option<bool>,
+    /// Uses wasmtime's pooling allocator, sized for the instances of the client's
+    /// solvers.
+    pub pooling_allocator: bool,
+    /// Directory the WASM module is cached in, overriding the `DEEPSEEK_WASM_DIR`
+    /// environment variable and the user's cache directory (see
+    /// [`get_wasm_path`](crate::wasm_download::get_wasm_path)). Ignored with the
+    /// `embed-wasm` feature.
+    pub cache_dir: Option<PathBuf>,
+}
+
+impl PowConfig {
+    /// Returns the engine configuration for `instances` solvers.
+    fn to_wasmtime(&self, instances: usize) -> Config {
+        let mut config = Config::new();
+        if let Some(bytes) = self.memory_reservation {
+            config.memory_reservation(bytes);
+        }
+        if let Some(bytes) = self.memory_guard_size {
+            config.memory_guard_size(bytes);
+        }
+        if let Some(enable) = self.simd {
+            config.wasm_simd(enable);
+            if !enable {
+                // Relaxed SIMD depends on SIMD
+                config.wasm_relaxed_simd(false);
+            }
+        }
+        if let Some(enable) = self.reference_types {
+            config.wasm_reference_types(enable);
+        }
+        if self.pooling_allocator {
+            let mut pooling = PoolingAllocationConfig::default();
+            let instances = u32::try_from(instances).unwrap_or(u32::MAX);
+            pooling
+                .total_core_instances(instances)
+                .total_memories(instances)
+                .total_tables(instances);
+            if let Some(bytes) = self.max_memory_size {
+                pooling.max_memory_size(bytes);
+            }
+            config.allocation_strategy(InstanceAllocationStrategy::Pooling(pooling));
+        }
+        config
+    }
+}
+
+/// Durations of recent solves, used to estimate the time of future ones.
+///
+/// The solver tries nonces until one meets the target, which takes `difficulty`
+/// attempts on average, so the expected solve time is modelled as proportional
+/// to the difficulty. The cost of one attempt is estimated as the total elapsed
+/// time of the recorded solves divided by their total difficulty.
+#[derive(Debug, Default)]
+struct SolveTimings {
+    samples: VecDeque<(f64, Duration)>,
+}
+
+impl SolveTimings {
+    /// How many of the most recent solves are kept.
+    const MAX_SAMPLES: usize = 32;
+
+    fn record(&mut self, difficulty: f64, elapsed: Duration) {
+        if self.samples.len() == Self::MAX_SAMPLES {
+            self.samples.pop_front();
+        }
+        self.samples.push_back((difficulty, elapsed));
+    }
+
+    fn estimate(&self, difficulty: f64) -> Option<Duration> {
+        let (total_difficulty, total_elapsed) =
+            self.samples
+                .iter()
+                .fold((0.0, 0.0), |(difficulty, elapsed), sample| {
+                    (difficulty + sample.0, elapsed + sample.1.as_secs_f64())
+                });
+        if total_difficulty <= 0.0 {
+            return None;
+        }
+        Duration::try_from_secs_f64(difficulty * total_elapsed / total_difficulty).ok()
+    }
+}
+
+/// Loads the WASM module, returning its bytes, where it was loaded from and how
+/// it was obtained.
+#[cfg(not(feature = "embed-wasm"))]
+async fn load_wasm(config: &PowConfig) -> Result<(Vec<u8>, String, WasmSource)> {
+    let (wasm_path, source) = get_wasm_in(config.cache_dir.as_deref()).await?;
+    let wasm_bytes = tokio::fs::read(&wasm_path)
+        .await
+        .with_context(|| format!("Failed to read WASM file at {}", wasm_path.display()))?;
+    Ok((wasm_bytes, wasm_path.display().to_string(), source))
+}
+
+/// Returns the WASM module embedded at build time.
+#[cfg(feature = "embed-wasm")]
+#[allow(clippy::unused_async)]
+async fn load_wasm(_config: &PowConfig) -> Result<(Vec<u8>, String, WasmSource)> {
+    Ok((
+        EMBEDDED_WASM.to_vec(),
+        "embedded module".to_string(),
+        WasmSource::Embedded,
+    ))
+}
+
 /// Solver for `DeepSeek` Proof of Work challenges.
 pub struct POWSolver {
-    store: Store<()>,
+    store: Store<StoreLimits>,
     memory: Memory,
     wasm_solve: TypedFunc<(i32, i32, i32, i32, i32, f64), ()>,
     alloc: TypedFunc<(i32, i32), i32>,
     add_stack: TypedFunc<(i32,), i32>,
+    source: WasmSource,
+    timings: SolveTimings,
 }
 
 impl POWSolver {
-    /// Creates a new `PoW` solver, loading the WASM module from cache or downloading it.
-    pub async fn new() -> Result<Self> {
-        let wasm_path = get_wasm_path().await?;
-        let wasm_bytes = tokio::fs::read(&wasm_path)
-            .await
-            .with_context(|| format!("Failed to read WASM file at {}", wasm_path.display()))?;
+    /// Creates `count` `PoW` solvers, loading the WASM module from cache or
+    /// downloading it, or using the embedded module with the `embed-wasm` feature.
+    ///
+    /// The solvers share one WebAssembly engine configured by `config` and one
+    /// compiled module; each has an instance of its own.
+    pub async fn instances(config: &PowConfig, count: usize) -> Result<Vec<Self>, DeepSeekError> {
+        let (wasm_bytes, location, source) = load_wasm(config).await?;
+
+        let engine =
+            Engine::new(&config.to_wasmtime(count)).context("Invalid PoW engine configuration")?;
+        let module = match Module::new(&engine, wasm_bytes) {
+            Ok(module) => module,
+            Err(e) => {
+                if source != WasmSource::Embedded {
+                    // Otherwise the corrupt module would be loaded again on every run
+                    if let Err(e) = tokio::fs::remove_file(&location).await {
+                        tracing::warn!(path = location, error = %e, "Could not remove invalid PoW WASM module");
+                    }
+                }
+                return Err(e.context(DeepSeekError::WasmInvalid { location }).into());
+            }
+        };
+        let solvers = (0..count)
+            .map(|_| Self::instantiate(&engine, &module, config, source))
+            .collect::<Result<_>>()?;
+        Ok(solvers)
+    }
 
-        let engine = Engine::default();
-        let module = Module::new(&engine, wasm_bytes)?;
-        let mut store = Store::new(&engine, ());
+    /// Instantiates `module` in a store of its own.
+    fn instantiate(
+        engine: &Engine,
+        module: &Module,
+        config: &PowConfig,
+        source: WasmSource,
+    ) -> Result<Self> {
+        let mut limits = StoreLimitsBuilder::new();
+        if let Some(bytes) = config.max_memory_size {
+            limits = limits.memory_size(bytes);
+        }
+        let mut store = Store::new(engine, limits.build());
+        store.limiter(|limits| limits);
 
-        let instance = Instance::new(&mut store, &module, &[])?;
+        let instance = Instance::new(&mut store, module, &[])?;
 
         let memory = instance
             .get_memory(&mut store, "memory")
@@ -70,58 +406,120 @@ impl POWSolver {
             wasm_solve,
             alloc,
             add_stack,
+            source,
+            timings: SolveTimings::default(),
         })
     }
 
+    /// Runs the solver with its output at `out_ptr`, returning the answer.
+    fn solve_on_stack(&mut self, out_ptr: i32, challenge: &Challenge) -> Result<f64> {
+        let prefix = format!("{}_{}_", challenge.salt, challenge.expire_at);
+        let (challenge_ptr, challenge_len) = self.write_str_to_memory(&challenge.value)?;
+        let (prefix_ptr, prefix_len) = self.write_str_to_memory(&prefix)?;
+
+        self.wasm_solve
+            .call(
+                &mut self.store,
+                (
+                    out_ptr,
+                    challenge_ptr,
+                    challenge_len,
+                    prefix_ptr,
+                    prefix_len,
+                    challenge.difficulty,
+                ),
+            )
+            .context("WASM solve trapped")?;
+
+        // Read status (first 4 bytes) and answer (bytes 8-16)
+        let mem = self.memory.data(&self.store);
+        let out_ptr_usize = usize::try_from(out_ptr).context("out_ptr negative")?;
+        let out = mem
+            .get(out_ptr_usize..(out_ptr_usize + 16))
+            .context("WASM output out of bounds")?;
+        let status = i32::from_le_bytes(out[..4].try_into()?);
+        if status == 0 {
+            anyhow::bail!("WASM solve returned status 0 (failure)");
+        }
+        Ok(f64::from_le_bytes(out[8..].try_into()?))
+    }
+
+    /// Returns whether the WASM module was downloaded or loaded from the cache.
+    pub fn source(&self) -> WasmSource {
+        self.source
+    }
+
+    /// Estimates how long solving a challenge of `difficulty` takes, based on the
+    /// last solves of this solver, or `None` before the first solve.
+    ///
+    /// The estimate is the mean solve time, assuming time grows linearly with
+    /// difficulty on this machine. The number of attempts a single solve needs is
+    /// random, so individual solves commonly take half or twice as long.
+    pub fn estimate_solve_time(&self, difficulty: f64) -> Option<Duration> {
+        self.timings.estimate(difficulty)
+    }
+
     /// Writes a string to WASM linear memory and returns (pointer, length).
     fn write_str_to_memory(&mut self, data: &str) -> Result<(i32, i32)> {
         let bytes = data.as_bytes();
         let len_i32 = i32::try_from(bytes.len()).context("WASM memory size too large")?;
-        let ptr_i32 = self.alloc.call(&mut self.store, (len_i32, 1))?;
+        let ptr_i32 = self
+            .alloc
+            .call(&mut self.store, (len_i32, 1))
+            .with_context(|| format!("WASM allocation of {len_i32} bytes failed"))?;
 
         let ptr_usize = usize::try_from(ptr_i32).context("pointer negative")?;
         let len_usize = usize::try_from(len_i32).context("length negative")?;
         let mem = self.memory.data_mut(&mut self.store);
-        mem[ptr_usize..(ptr_usize + len_usize)].copy_from_slice(bytes);
+        mem.get_mut(ptr_usize..(ptr_usize + len_usize))
+            .context("WASM allocation out of bounds")?
+            .copy_from_slice(bytes);
 
         Ok((ptr_i32, len_i32))
     }
 
     /// Solves a challenge, returning the base64-encoded response.
-    pub fn solve_challenge(&mut self, challenge: Challenge) -> Result<String> {
-        let prefix = format!("{}_{}_", challenge.salt, challenge.expire_at);
-        let out_ptr = self.add_stack.call(&mut self.store, (-16,))?;
-
-        let (challenge_ptr, challenge_len) = self.write_str_to_memory(&challenge.value)?;
-        let (prefix_ptr, prefix_len) = self.write_str_to_memory(&prefix)?;
-
-        self.wasm_solve.call(
-            &mut self.store,
-            (
-                out_ptr,
-                challenge_ptr,
-                challenge_len,
-                prefix_ptr,
-                prefix_len,
-                challenge.difficulty,
-            ),
-        )?;
+    ///
+    /// A challenge expiring within [`EXPIRY_MARGIN`], given the server clock is
+    /// `skew` seconds ahead of the local one, is rejected without solving it.
+    ///
+    /// # Errors
+    /// Returns [`DeepSeekError::UnsupportedPowAlgorithm`] or
+    /// [`DeepSeekError::PowChallengeExpired`] for challenges that are not solved,
+    /// and [`DeepSeekError::PowSolve`] if solving fails. Solving errors, including
+    /// WASM traps, carry a description of the challenge.
+    pub fn solve_challenge(
+        &mut self,
+        challenge: Challenge,
+        skew: f64,
+    ) -> Result<String, DeepSeekError> {
+        challenge.check_algorithm()?;
+        challenge.check_expiry(skew)?;
+        self.solve(challenge)
+            .map_err(|err| DeepSeekError::PowSolve(Shared::new(err)))
+    }
 
-        // Read status (first 4 bytes) and answer (bytes 8-16)
-        let mem = self.memory.data(&self.store);
-        let out_ptr_usize = usize::try_from(out_ptr).context("out_ptr negative")?;
-        let status = i32::from_le_bytes(mem[out_ptr_usize..(out_ptr_usize + 4)].try_into()?);
-        if status == 0 {
-            // Restore stack pointer before bailing
-            self.add_stack.call(&mut self.store, (16,))?;
-            anyhow::bail!("WASM solve returned status 0 (failure)");
-        }
+    fn solve(&mut self, challenge: Challenge) -> Result<String> {
+        let started = Instant::now();
+        let difficulty = challenge.difficulty;
+        let out_ptr = self
+            .add_stack
+            .call(&mut self.store, (-16,))
+            .with_context(|| format!("Failed to reserve WASM stack for {}", challenge.summary()))?;
 
-        let answer_bytes: [u8; 8] = mem[(out_ptr_usize + 8)..(out_ptr_usize + 16)].try_into()?;
-        let answer = f64::from_le_bytes(answer_bytes);
+        let answer = self.solve_on_stack(out_ptr, &challenge);
+        // Restore the stack pointer whether or not solving succeeded
+        let restored = self.add_stack.call(&mut self.store, (16,));
+        let answer = answer.with_context(|| format!("Failed to solve {}", challenge.summary()))?;
+        restored.context("Failed to restore WASM stack pointer")?;
 
-        // Cleanup stack
-        self.add_stack.call(&mut self.store, (16,))?;
+        let elapsed = started.elapsed();
+        self.timings.record(difficulty, elapsed);
+        tracing::debug!(
+            difficulty,
+            elapsed_ms = elapsed.as_millis(),
+            "Solved PoW challenge"
+        );
 
         let response = SolveResponse {
             algorithm: challenge.algorithm,
@@ -139,3 +537,346 @@ impl POWSolver {
         Ok(BASE64.encode(json_string))
     }
 }
+
+/// Solvers used in turn, so that as many challenges as there are solvers can
+/// be solved in parallel.
+pub(crate) struct SolverPool {
+    solvers: Vec<Arc<Mutex<POWSolver>>>,
+    /// The solver to wait for next when all are busy.
+    next: AtomicUsize,
+}
+
+impl SolverPool {
+    /// Creates a pool of `size` solvers, at least one, configured by `config`.
+    pub(crate) async fn new(config: &PowConfig, size: usize) -> Result<Self> {
+        let solvers = POWSolver::instances(config, size.max(1)).await?;
+        Ok(Self {
+            solvers: solvers
+                .into_iter()
+                .map(|solver| Arc::new(Mutex::new(solver)))
+                .collect(),
+            next: AtomicUsize::new(0),
+        })
+    }
+
+    /// Locks an idle solver, or waits for the solvers in turn if all are busy.
+    pub(crate) async fn acquire(&self) -> OwnedMutexGuard<POWSolver> {
+        for solver in &self.solvers {
+            if let Ok(guard) = Arc::clone(solver).try_lock_owned() {
+                return guard;
+            }
+        }
+        let next = self.next.fetch_add(1, Ordering::Relaxed) % self.solvers.len();
+        Arc::clone(&self.solvers[next]).lock_owned().await
+    }
+
+    /// Replaces every solver with a new one configured by `config`, keeping the
+    /// current ones if creating them fails.
+    pub(crate) async fn refresh(&self, config: &PowConfig) -> Result<()> {
+        let fresh = POWSolver::instances(config, self.solvers.len()).await?;
+        for (solver, fresh) in self.solvers.iter().zip(fresh) {
+            *solver.lock().await = fresh;
+        }
+        Ok(())
+    }
+
+    /// Returns where the WASM module of the solvers was loaded from.
+    pub(crate) async fn source(&self) -> WasmSource {
+        self.solvers[0].lock().await.source()
+    }
+
+    /// Estimates how long solving a challenge of `difficulty` takes, averaged
+    /// over the solvers that have solved one.
+    pub(crate) async fn estimate_solve_time(&self, difficulty: f64) -> Option<Duration> {
+        let mut estimates = Vec::with_capacity(self.solvers.len());
+        for solver in &self.solvers {
+            estimates.extend(solver.lock().await.estimate_solve_time(difficulty));
+        }
+        let count = u32::try_from(estimates.len())
+            .ok()
+            .filter(|&count| count > 0)?;
+        Some(estimates.into_iter().sum::<Duration>() / count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn challenge_expiring_in(offset_ms: i64) -> Challenge {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        Challenge {
+            salt: String::new(),
+            expire_at: i64::try_from(now.as_millis()).unwrap() + offset_ms,
+            value: String::new(),
+            difficulty: 0.0,
+            algorithm: SUPPORTED_ALGORITHM.to_string(),
+            signature: String::new(),
+            target_path: String::new(),
+        }
+    }
+
+    #[test]
+    fn expiry_accounts_for_skew() {
+        let challenge = challenge_expiring_in(60_000);
+        assert!(!challenge.is_expired(0.0));
+        assert!(challenge.is_expired(120.0));
+        assert!(challenge_expiring_in(-60_000).is_expired(0.0));
+        assert!(!challenge_expiring_in(-60_000).is_expired(-120.0));
+    }
+
+    #[test]
+    fn challenges_about_to_expire_are_rejected() {
+        assert!(challenge_expiring_in(60_000).check_expiry(0.0).is_ok());
+        let err = challenge_expiring_in(1_000).check_expiry(0.0).unwrap_err();
+        assert!(matches!(err, DeepSeekError::PowChallengeExpired { .. }));
+        // A server clock a minute behind leaves the challenge a minute longer
+        assert!(challenge_expiring_in(1_000).check_expiry(-60.0).is_ok());
+    }
+
+    #[test]
+    fn unknown_algorithm_is_rejected() {
+        let mut challenge = challenge_expiring_in(60_000);
+        assert!(challenge.check_algorithm().is_ok());
+        challenge.algorithm = "DeepSeekHashV2".to_string();
+        let err = challenge.check_algorithm().unwrap_err();
+        assert!(matches!(
+            err,
+            DeepSeekError::UnsupportedPowAlgorithm { algorithm, .. } if algorithm == "DeepSeekHashV2"
+        ));
+    }
+
+    #[test]
+    fn summary_shortens_challenge() {
+        let mut challenge = challenge_expiring_in(0);
+        challenge.value = "0123456789abcdef0123".to_string();
+        challenge.salt = "salt".to_string();
+        challenge.difficulty = 144_000.0;
+        assert_eq!(
+            challenge.summary(),
+            "PoW challenge 0123456789abcdef… (algorithm DeepSeekHashV1, salt salt, difficulty 144000)"
+        );
+    }
+
+    #[test]
+    fn answers_are_verified() {
+        assert_eq!(
+            hex(&sha3_256(b"", 24)),
+            "a7ffc6f8bf1ed76651c14756a061d662f580ff4de43b49fa82d80a4b80f8434a"
+        );
+        assert_eq!(
+            sha3_256(&[b'a'; 200], 24)[..4],
+            [0xcc, 0xe3, 0x44, 0x85][..]
+        );
+
+        let mut challenge = challenge_expiring_in(60_000);
+        challenge.salt = "salt".to_string();
+        challenge.difficulty = 1000.0;
+        let input = format!("salt_{}_42", challenge.expire_at);
+        challenge.value = hex(&sha3_256(input.as_bytes(), 23));
+        assert!(challenge.verify_answer(42));
+        assert!(!challenge.verify_answer(43));
+        challenge.difficulty = 42.0;
+        assert!(!challenge.verify_answer(42));
+    }
+
+    #[test]
+    fn solve_time_scales_with_difficulty() {
+        let mut timings = SolveTimings::default();
+        assert_eq!(timings.estimate(1000.0), None);
+        timings.record(1000.0, Duration::from_millis(100));
+        timings.record(3000.0, Duration::from_millis(500));
+        assert_eq!(timings.estimate(2000.0), Some(Duration::from_millis(300)));
+        for _ in 0..SolveTimings::MAX_SAMPLES {
+            timings.record(1000.0, Duration::from_millis(10));
+        }
+        assert_eq!(timings.samples.len(), SolveTimings::MAX_SAMPLES);
+        assert_eq!(timings.estimate(1000.0), Some(Duration::from_millis(10)));
+    }
+
+    #[test]
+    fn solve_response_round_trips_through_header() {
+        let response = SolveResponse {
+            algorithm: "DeepSeekHashV1".to_string(),
+            challenge: "abc".to_string(),
+            salt: "salt".to_string(),
+            answer: 42,
+            signature: "sig".to_string(),
+            target_path: "/api/v0/chat/completion".to_string(),
+        };
+        let header = BASE64.encode(serde_json::to_string(&response).unwrap());
+        assert_eq!(SolveResponse::from_header(&header).unwrap(), response);
+        assert!(SolveResponse::from_header("not base64!").is_err());
+    }
+
+    #[cfg(not(feature = "embed-wasm"))]
+    #[tokio::test]
+    async fn invalid_cached_module_is_removed() {
+        let dir = std::env::temp_dir().join(format!("deepseek-wasm-{}", fastrand::u64(..)));
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let path = dir.join(crate::wasm_download::WASM_FILENAME);
+        tokio::fs::write(&path, b"\0asm corrupt").await.unwrap();
+        let config = PowConfig {
+            cache_dir: Some(dir.clone()),
+            ..PowConfig::default()
+        };
+        let Err(err) = POWSolver::instances(&config, 1).await else {
+            panic!("a corrupt module should not load");
+        };
+        assert_eq!(
+            err,
+            DeepSeekError::WasmInvalid {
+                location: path.display().to_string()
+            }
+        );
+        assert!(!path.exists());
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[cfg(feature = "embed-wasm")]
+    #[tokio::test]
+    async fn embedded_module_solves_offline() {
+        let pool = SolverPool::new(&PowConfig::default(), 1).await.unwrap();
+        assert_eq!(pool.source().await, WasmSource::Embedded);
+
+        let challenge = solvable_challenge(1000.0, 421);
+        let header = pool
+            .acquire()
+            .await
+            .solve_challenge(challenge.clone(), 0.0)
+            .unwrap();
+        let answer = SolveResponse::from_header(&header).unwrap().answer;
+        assert!(challenge.verify_answer(answer), "wrong answer {answer}");
+    }
+
+    #[cfg(feature = "embed-wasm")]
+    #[tokio::test]
+    async fn pooled_solvers_solve_in_parallel() {
+        let config = PowConfig {
+            pooling_allocator: true,
+            ..PowConfig::default()
+        };
+        let pool = SolverPool::new(&config, 4).await.unwrap();
+        // Holding a solver leaves the others idle
+        let guards = futures_util::future::join_all((0..4).map(|_| pool.acquire())).await;
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let max_in_flight = Arc::new(AtomicUsize::new(0));
+        // No solve is counted out before all four are counted in
+        let finished = Arc::new(std::sync::Barrier::new(4));
+        let solves = guards.into_iter().zip(0..).map(|(mut solver, i)| {
+            let challenge = solvable_challenge(1000.0, 121 + i * 200);
+            let (in_flight, max_in_flight, finished) = (
+                Arc::clone(&in_flight),
+                Arc::clone(&max_in_flight),
+                Arc::clone(&finished),
+            );
+            tokio::task::spawn_blocking(move || {
+                max_in_flight.fetch_max(
+                    in_flight.fetch_add(1, Ordering::SeqCst) + 1,
+                    Ordering::SeqCst,
+                );
+                let header = solver.solve_challenge(challenge.clone(), 0.0).unwrap();
+                finished.wait();
+                in_flight.fetch_sub(1, Ordering::SeqCst);
+                challenge.verify_answer(SolveResponse::from_header(&header).unwrap().answer)
+            })
+        });
+        let verified = futures_util::future::join_all(solves).await;
+        assert!(verified.into_iter().all(Result::unwrap));
+        assert_eq!(max_in_flight.load(Ordering::SeqCst), 4);
+        assert!(pool.estimate_solve_time(1000.0).await.is_some());
+    }
+
+    /// Compares solving four challenges in sequence and on four pooled solvers.
+    #[cfg(feature = "embed-wasm")]
+    #[tokio::test]
+    #[ignore = "benchmark, run with `--ignored --nocapture`"]
+    async fn bench_pooled_solves() {
+        let config = PowConfig {
+            pooling_allocator: true,
+            ..PowConfig::default()
+        };
+        let pool = SolverPool::new(&config, 4).await.unwrap();
+        // Answers near the end of the searched range, so that each solve takes a while
+        let challenges: Vec<_> = (0..4)
+            .map(|i| solvable_challenge(100_000.0, 99_000 + i))
+            .collect();
+
+        let mut single = pool.acquire().await;
+        let started = Instant::now();
+        for challenge in &challenges {
+            single.solve_challenge(challenge.clone(), 0.0).unwrap();
+        }
+        let sequential = started.elapsed();
+        drop(single);
+
+        let guards = futures_util::future::join_all((0..4).map(|_| pool.acquire())).await;
+        let solves = guards
+            .into_iter()
+            .zip(challenges)
+            .map(|(mut solver, challenge)| {
+                tokio::task::spawn_blocking(move || solver.solve_challenge(challenge, 0.0).unwrap())
+            });
+        let started = Instant::now();
+        futures_util::future::join_all(solves).await;
+        let parallel = started.elapsed();
+        println!("4 solves: {sequential:?} in sequence, {parallel:?} in parallel");
+    }
+
+    #[cfg(feature = "embed-wasm")]
+    #[test]
+    fn solving_does_not_block_the_runtime() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .max_blocking_threads(1)
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let challenge = solvable_challenge(1000.0, 421);
+            let api = crate::DeepSeekAPI::mock(move |_| {
+                Ok(crate::mock::json(&serde_json::json!({
+                    "data": {"biz_data": {"challenge": challenge}}
+                })))
+            });
+            let api = crate::DeepSeekAPI {
+                pow_solver: Some(Arc::new(
+                    SolverPool::new(&PowConfig::default(), 1).await.unwrap(),
+                )),
+                ..api
+            };
+            // Occupy the only blocking thread, so that a solve off the runtime
+            // waits until a task on the runtime frees it
+            let (unblock, wait) = std::sync::mpsc::channel();
+            let blocker = tokio::task::spawn_blocking(move || wait.recv().unwrap());
+            let freed = Arc::new(std::sync::atomic::AtomicBool::new(false));
+            let ticker = tokio::spawn({
+                let freed = Arc::clone(&freed);
+                async move {
+                    tokio::task::yield_now().await;
+                    freed.store(true, Ordering::SeqCst);
+                    unblock.send(()).unwrap();
+                }
+            });
+            api.prefetch_pow("/api/v0/chat/completion", 1)
+                .await
+                .unwrap();
+            assert!(
+                freed.load(Ordering::SeqCst),
+                "the solve finished without letting the runtime run other tasks"
+            );
+            blocker.await.unwrap();
+            ticker.await.unwrap();
+        });
+    }
+
+    /// Returns a challenge of `difficulty` whose answer is `answer`.
+    #[cfg(feature = "embed-wasm")]
+    fn solvable_challenge(difficulty: f64, answer: i64) -> Challenge {
+        let mut challenge = challenge_expiring_in(60_000);
+        challenge.salt = "salt".to_string();
+        challenge.difficulty = difficulty;
+        let input = format!("{}_{}_{answer}", challenge.salt, challenge.expire_at);
+        challenge.value = hex(&sha3_256(input.as_bytes(), 23));
+        challenge
+    }
+}
@@ -0,0 +1,145 @@
+//! Builder for completion requests, see [`CompletionRequest`].
+
+use anyhow::Result;
+
+use crate::{
+    CompletionOptions, CompletionResult, DeepSeekAPI, DeepSeekError, StreamChunk, into_chunks,
+    models,
+};
+
+/// A completion request, configured with named setters instead of the
+/// positional arguments of [`DeepSeekAPI::complete`].
+///
+/// Only the chat and the prompt are required. By default the prompt has no
+/// parent message, so it continues from the session's current message, and
+/// search, thinking and referenced files are off.
+///
+/// ```no_run
+/// use deepseek_api::{CompletionRequest, DeepSeekAPI};
+///
+/// # async fn example(api: &DeepSeekAPI) -> anyhow::Result<()> {
+/// let message = CompletionRequest::new("chat-id", "Hello")
+///     .thinking(true)
+///     .send(api)
+///     .await?;
+/// println!("{}", message.content);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+#[must_use]
+pub struct CompletionRequest {
+    pub(crate) chat_id: String,
+    pub(crate) prompt: String,
+    pub(crate) parent_message_id: Option<i64>,
+    pub(crate) options: CompletionOptions,
+}
+
+impl CompletionRequest {
+    /// Creates a request sending `prompt` to the chat `chat_id`.
+    pub fn new(chat_id: impl Into<String>, prompt: impl Into<String>) -> Self {
+        Self::with_options(chat_id, prompt, CompletionOptions::default())
+    }
+
+    /// Creates a request sending `prompt` to the chat `chat_id` with `options`,
+    /// which the other setters then adjust.
+    pub fn with_options(
+        chat_id: impl Into<String>,
+        prompt: impl Into<String>,
+        options: CompletionOptions,
+    ) -> Self {
+        Self {
+            chat_id: chat_id.into(),
+            prompt: prompt.into(),
+            parent_message_id: None,
+            options,
+        }
+    }
+
+    /// Replies to the message `message_id` instead of the session's current
+    /// message, see [`DeepSeekAPI::branch_from`].
+    pub fn parent(mut self, message_id: i64) -> Self {
+        self.parent_message_id = Some(message_id);
+        self
+    }
+
+    /// Enables or disables web search.
+    pub fn search(mut self, search: bool) -> Self {
+        self.options.search = search;
+        self
+    }
+
+    /// Enables or disables thinking (reasoning).
+    pub fn thinking(mut self, thinking: bool) -> Self {
+        self.options.thinking = thinking;
+        self
+    }
+
+    /// References the uploaded files `file_ids`.
+    pub fn ref_files(mut self, file_ids: Vec<String>) -> Self {
+        self.options.ref_file_ids = file_ids;
+        self
+    }
+
+    /// Enables or disables continuing an incomplete message, on by default. See
+    /// [`CompletionOptions::no_continue`].
+    pub fn auto_continue(mut self, auto_continue: bool) -> Self {
+        self.options.no_continue = !auto_continue;
+        self
+    }
+
+    /// Requests at most `max` continuations of an incomplete message, see
+    /// [`DeepSeekAPIBuilder::max_continuations`](crate::DeepSeekAPIBuilder::max_continuations).
+    pub fn max_continuations(mut self, max: u32) -> Self {
+        self.options.max_continuations = Some(max);
+        self
+    }
+
+    /// Sends the request with `api` and returns the final message, as
+    /// [`DeepSeekAPI::complete`] does.
+    ///
+    /// # Errors
+    /// Returns an error as described for [`DeepSeekAPI::complete`].
+    pub async fn send(self, api: &DeepSeekAPI) -> Result<models::Message, DeepSeekError> {
+        Ok(self.send_detailed(api).await?.message)
+    }
+
+    /// Sends the request with `api` and returns the final message along with
+    /// details about how it was obtained, as [`DeepSeekAPI::complete_detailed`]
+    /// does.
+    ///
+    /// # Errors
+    /// Returns an error as described for [`DeepSeekAPI::complete`].
+    pub async fn send_detailed(self, api: &DeepSeekAPI) -> Result<CompletionResult, DeepSeekError> {
+        let result = api
+            .collect_completion(
+                &self.chat_id,
+                &self.prompt,
+                self.parent_message_id,
+                self.options,
+            )
+            .await?;
+        Ok(result)
+    }
+
+    /// Sends the request with `api`, yielding chunks of content or thinking as
+    /// [`DeepSeekAPI::complete_stream`] does.
+    ///
+    /// # Errors
+    /// Each yielded `Result` may contain an error as described for
+    /// [`DeepSeekAPI::complete_stream`].
+    pub fn stream(
+        self,
+        api: &DeepSeekAPI,
+    ) -> impl futures_util::Stream<Item = Result<StreamChunk, DeepSeekError>> + '_ {
+        into_chunks(
+            api.complete_events_with(
+                self.chat_id,
+                self.prompt,
+                self.parent_message_id,
+                self.options,
+            ),
+            api.detect_images,
+        )
+    }
+}
@@ -0,0 +1,196 @@
+//! Retrying requests that failed for transient reasons, see [`RetryPolicy`].
+
+use std::time::Duration;
+
+/// How often and how long apart requests are retried after a transient failure,
+/// set with [`DeepSeekAPIBuilder::retry`](crate::DeepSeekAPIBuilder::retry).
+///
+/// A request is retried when the connection to the server cannot be
+/// established, or when the server answers with a 5xx status or 429 (too many
+/// requests). If such a response has a `Retry-After` header, the client waits
+/// as long as it asks instead, or gives up at once if that is longer than
+/// `max_delay`; a final 429 response is returned as
+/// [`DeepSeekError::RateLimited`](crate::DeepSeekError::RateLimited). Other
+/// statuses, including 401 and other 4xx errors, are returned at once.
+///
+/// Only `PoW` challenge, chat creation, completion and upload requests are
+/// retried, see [`DeepSeekAPIBuilder::retry`](crate::DeepSeekAPIBuilder::retry),
+/// and only sending them: once a response has been received, errors while
+/// reading it, e.g. in the middle of a streamed completion, end the stream as
+/// before. Uploads with a streamed body, such as those of
+/// [`DeepSeekAPI::upload_file_stream`](crate::DeepSeekAPI::upload_file_stream),
+/// are never retried.
+///
+/// The delay before the `n`-th retry is `base_delay * 2^(n-1)`, capped at
+/// `max_delay`; with jitter, a random delay between half of that and all of it
+/// is used instead, so that clients failing together do not retry together.
+///
+/// The default makes up to 3 attempts, 500 ms and 1 s apart (with jitter), with a
+/// maximum delay of 8 s.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    base_delay: Duration,
+    max_delay: Duration,
+    jitter: bool,
+}
+
+impl RetryPolicy {
+    /// Makes up to `max_attempts` attempts in total, waiting `base_delay` before
+    /// the first retry and twice as long before each subsequent one.
+    #[must_use]
+    pub fn new(max_attempts: u32, base_delay: Duration) -> Self {
+        Self {
+            max_attempts,
+            ..Self::default()
+        }
+        .base_delay(base_delay)
+    }
+
+    /// Never retries.
+    #[must_use]
+    pub fn none() -> Self {
+        Self::new(1, Duration::ZERO)
+    }
+
+    /// Sets the delay before the first retry.
+    #[must_use]
+    pub fn base_delay(mut self, base_delay: Duration) -> Self {
+        self.base_delay = base_delay;
+        self
+    }
+
+    /// Caps the delay between attempts.
+    #[must_use]
+    pub fn max_delay(mut self, max_delay: Duration) -> Self {
+        self.max_delay = max_delay;
+        self
+    }
+
+    /// Enables or disables randomizing the delays, on by default.
+    #[must_use]
+    pub fn jitter(mut self, jitter: bool) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Returns the delay before retrying after `attempt` failed attempts, or
+    /// `None` if no attempts are left.
+    pub(crate) fn delay(&self, attempt: u32) -> Option<Duration> {
+        if attempt >= self.max_attempts {
+            return None;
+        }
+        let delay = 2u32
+            .checked_pow(attempt - 1)
+            .and_then(|factor| self.base_delay.checked_mul(factor))
+            .unwrap_or(self.max_delay)
+            .min(self.max_delay);
+        Some(if self.jitter {
+            delay.mul_f64(fastrand::f64().mul_add(0.5, 0.5))
+        } else {
+            delay
+        })
+    }
+
+    /// Returns how long to wait before retrying after `result`, `backoff` unless
+    /// the response asks for another delay, or `None` if `result` is not a
+    /// transient failure or the server asks to wait longer than `max_delay`.
+    pub(crate) fn retry_delay(
+        &self,
+        result: &anyhow::Result<reqwest::Response>,
+        backoff: Duration,
+    ) -> Option<Duration> {
+        match result {
+            Ok(response) => {
+                let status = response.status();
+                if !status.is_server_error() && status != reqwest::StatusCode::TOO_MANY_REQUESTS {
+                    return None;
+                }
+                match retry_after(response.headers()) {
+                    Some(wait) => (wait <= self.max_delay).then_some(wait),
+                    None => Some(backoff),
+                }
+            }
+            Err(e) => e
+                .downcast_ref::<reqwest::Error>()
+                .is_some_and(reqwest::Error::is_connect)
+                .then_some(backoff),
+        }
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(8),
+            jitter: true,
+        }
+    }
+}
+
+/// Parses the `Retry-After` header of a response, given either in seconds or as
+/// an HTTP date.
+pub(crate) fn retry_after(headers: &reqwest::header::HeaderMap) -> Option<Duration> {
+    let value = headers
+        .get(reqwest::header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = httpdate::parse_http_date(value).ok()?;
+    Some(
+        date.duration_since(std::time::SystemTime::now())
+            .unwrap_or_default(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delays_double_up_to_the_cap() {
+        let policy = RetryPolicy::new(5, Duration::from_secs(1))
+            .max_delay(Duration::from_secs(3))
+            .jitter(false);
+        let delays: Vec<_> = (1..=5).map(|attempt| policy.delay(attempt)).collect();
+        assert_eq!(
+            delays,
+            [
+                Some(Duration::from_secs(1)),
+                Some(Duration::from_secs(2)),
+                Some(Duration::from_secs(3)),
+                Some(Duration::from_secs(3)),
+                None,
+            ]
+        );
+        assert_eq!(RetryPolicy::none().delay(1), None);
+
+        let jittered = RetryPolicy::new(2, Duration::from_secs(1))
+            .delay(1)
+            .unwrap();
+        assert!((Duration::from_millis(500)..=Duration::from_secs(1)).contains(&jittered));
+    }
+
+    #[test]
+    fn retry_after_is_parsed() {
+        let headers = |value: &str| {
+            let mut headers = reqwest::header::HeaderMap::new();
+            headers.insert(reqwest::header::RETRY_AFTER, value.parse().unwrap());
+            headers
+        };
+        assert_eq!(retry_after(&headers("5")), Some(Duration::from_secs(5)));
+        assert_eq!(
+            retry_after(&headers("Wed, 21 Oct 2015 07:28:00 GMT")),
+            Some(Duration::ZERO)
+        );
+        let later = httpdate::fmt_http_date(std::time::SystemTime::now() + Duration::from_mins(1));
+        assert!(retry_after(&headers(&later)).is_some_and(|wait| wait > Duration::from_secs(50)));
+        assert_eq!(retry_after(&headers("soon")), None);
+        assert_eq!(retry_after(&reqwest::header::HeaderMap::new()), None);
+    }
+}
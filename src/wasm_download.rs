@@ -2,38 +2,320 @@
 
 use anyhow::{Context, Result};
 use dirs::cache_dir;
-use std::path::PathBuf;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-const WASM_FILENAME: &str = "sha3_wasm_bg.7b9ca65ddd.wasm";
+use crate::error::DeepSeekError;
+
+pub(crate) const WASM_FILENAME: &str = "sha3_wasm_bg.7b9ca65ddd.wasm";
 const WASM_URL: &str = "https://fe-static.deepseek.com/chat/static/sha3_wasm_bg.7b9ca65ddd.wasm";
+/// The magic number every WebAssembly binary starts with.
+const WASM_MAGIC: &[u8] = b"\0asm";
+
+/// Environment variable overriding the directory the WASM module is cached in.
+pub const WASM_DIR_ENV: &str = "DEEPSEEK_WASM_DIR";
+
+/// Number of download attempts before giving up.
+const DOWNLOAD_ATTEMPTS: u32 = 3;
+/// Delay before the first retry; doubled after each failed attempt.
+const DOWNLOAD_BASE_DELAY: Duration = Duration::from_millis(500);
+
+/// Where the WASM module was obtained from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum WasmSource {
+    /// The module was already in the cache directory.
+    Cached,
+    /// The module was downloaded and written to the cache directory.
+    Downloaded,
+    /// The module was embedded at build time with the `embed-wasm` feature.
+    Embedded,
+}
 
 /// Returns the local filesystem path to the `DeepSeek` WASM module.
-/// Downloads the WASM file if it is not already present in the user's cache directory.
-pub async fn get_wasm_path() -> Result<PathBuf> {
-    let cache_dir = cache_dir()
-        .ok_or_else(|| anyhow::anyhow!("Could not determine cache directory"))?
-        .join("deepseek");
-    tokio::fs::create_dir_all(&cache_dir).await?;
+/// Downloads the WASM file if it is not already present in the cache directory.
+///
+/// The cache directory is the one named by the [`WASM_DIR_ENV`] environment
+/// variable (`DEEPSEEK_WASM_DIR`) if it is set, and `deepseek` in the user's
+/// cache directory otherwise. A directory set with
+/// [`PowConfig::cache_dir`](crate::PowConfig::cache_dir) takes precedence over
+/// both for the solvers of a client. A directory that already contains the
+/// module may be read-only.
+///
+/// # Errors
+/// Returns an error if the cache directory cannot be determined or created,
+/// or if the module cannot be downloaded or written to the cache.
+pub async fn get_wasm_path() -> Result<PathBuf, DeepSeekError> {
+    Ok(get_wasm().await?.0)
+}
 
-    let local_path = cache_dir.join(WASM_FILENAME);
+/// Like [`get_wasm_path`], but also reports whether the module was downloaded.
+///
+/// # Errors
+/// Returns an error in the same cases as [`get_wasm_path`].
+pub async fn get_wasm() -> Result<(PathBuf, WasmSource), DeepSeekError> {
+    Ok(get_wasm_in(None).await?)
+}
+
+/// Like [`get_wasm`], caching the module in `cache_dir` if set.
+pub(crate) async fn get_wasm_in(cache_dir: Option<&Path>) -> Result<(PathBuf, WasmSource)> {
+    let local_path = cached_wasm_path(cache_dir)?;
+    if let Some(cache_dir) = local_path.parent() {
+        tokio::fs::create_dir_all(cache_dir).await?;
+    }
 
     if local_path.exists() {
-        return Ok(local_path);
+        tracing::debug!(path = %local_path.display(), "using cached PoW WASM module");
+        return Ok((local_path, WasmSource::Cached));
     }
 
     // Download the file
-    let response = reqwest::get(WASM_URL)
+    let bytes = download_with_retry(WASM_URL, DOWNLOAD_ATTEMPTS, DOWNLOAD_BASE_DELAY).await?;
+    check_magic(&bytes, WASM_URL)?;
+
+    write_atomically(&local_path, &bytes)
         .await
-        .with_context(|| format!("Failed to download WASM from {WASM_URL}"))?;
+        .with_context(|| format!("Failed to write WASM to {}", local_path.display()))?;
+
+    tracing::info!(path = %local_path.display(), url = WASM_URL, "downloaded PoW WASM module");
+    Ok((local_path, WasmSource::Downloaded))
+}
+
+/// Writes `bytes` to a temporary file next to `path` and renames it to `path`,
+/// so that an interrupted write never leaves a partial module in the cache.
+async fn write_atomically(path: &Path, bytes: &[u8]) -> Result<()> {
+    let mut temp_name = path.file_name().map(OsString::from).unwrap_or_default();
+    temp_name.push(format!(
+        ".{}.{:x}.tmp",
+        std::process::id(),
+        fastrand::u64(..)
+    ));
+    let temp_path = path.with_file_name(temp_name);
+    let result = async {
+        tokio::fs::write(&temp_path, bytes).await?;
+        tokio::fs::rename(&temp_path, path).await
+    }
+    .await;
+    if result.is_err() {
+        let _ = tokio::fs::remove_file(&temp_path).await;
+    }
+    Ok(result?)
+}
+
+/// Removes the cached WASM module so the next solver initialization downloads it again.
+///
+/// Succeeds if there is no cached module.
+///
+/// # Errors
+/// Returns an error if the cache directory cannot be determined or the file
+/// cannot be removed.
+pub async fn clear_cache() -> Result<(), DeepSeekError> {
+    Ok(clear_cache_in(None).await?)
+}
+
+/// Like [`clear_cache`], for the module cached in `cache_dir` if set.
+pub(crate) async fn clear_cache_in(cache_dir: Option<&Path>) -> Result<()> {
+    let local_path = cached_wasm_path(cache_dir)?;
+    match tokio::fs::remove_file(&local_path).await {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e)
+            .with_context(|| format!("Failed to remove cached WASM at {}", local_path.display())),
+    }
+}
+
+/// Returns where the WASM module is cached: in `configured` if set, in the
+/// directory named by [`WASM_DIR_ENV`] or in the user's cache directory.
+fn cached_wasm_path(configured: Option<&Path>) -> Result<PathBuf> {
+    Ok(wasm_dir(configured, std::env::var_os(WASM_DIR_ENV))?.join(WASM_FILENAME))
+}
+
+/// Picks the cache directory from the configured one, the value of
+/// [`WASM_DIR_ENV`] and the user's cache directory, in that order.
+fn wasm_dir(configured: Option<&Path>, env: Option<OsString>) -> Result<PathBuf> {
+    if let Some(dir) = configured {
+        return Ok(dir.to_path_buf());
+    }
+    if let Some(dir) = env.filter(|dir| !dir.is_empty()) {
+        return Ok(PathBuf::from(dir));
+    }
+    Ok(cache_dir()
+        .with_context(|| {
+            format!(
+                "Could not determine cache directory; set {WASM_DIR_ENV} or PowConfig::cache_dir"
+            )
+        })?
+        .join("deepseek"))
+}
+
+/// Downloads `url`, retrying with exponential backoff on failure.
+///
+/// The returned error carries a [`DeepSeekError::WasmDownload`] once all
+/// attempts are exhausted.
+async fn download_with_retry(
+    url: &str,
+    attempts: u32,
+    base_delay: Duration,
+) -> Result<bytes::Bytes> {
+    let mut delay = base_delay;
+    let mut attempt = 1;
+    loop {
+        match download(url).await {
+            Ok(bytes) => return Ok(bytes),
+            Err(_) if attempt < attempts => {
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+                attempt += 1;
+            }
+            Err(e) => {
+                return Err(e.context(DeepSeekError::WasmDownload {
+                    url: url.to_string(),
+                    attempts,
+                }));
+            }
+        }
+    }
+}
+
+async fn download(url: &str) -> Result<bytes::Bytes> {
+    let response = reqwest::get(url)
+        .await
+        .with_context(|| format!("Failed to download WASM from {url}"))?
+        .error_for_status()?;
 
     let bytes = response
         .bytes()
         .await
         .context("Failed to read response body")?;
+    Ok(bytes)
+}
 
-    tokio::fs::write(&local_path, &bytes)
-        .await
-        .with_context(|| format!("Failed to write WASM to {}", local_path.display()))?;
+/// Checks that `bytes` downloaded from `url` start like a WebAssembly module.
+///
+/// A server answering with an error page is not retried, as it would likely
+/// serve the same page again.
+fn check_magic(bytes: &[u8], url: &str) -> Result<()> {
+    if bytes.starts_with(WASM_MAGIC) {
+        return Ok(());
+    }
+    Err(
+        anyhow::anyhow!("Downloaded file from {url} is not a WebAssembly module").context(
+            DeepSeekError::WasmInvalid {
+                location: url.to_string(),
+            },
+        ),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
 
-    Ok(local_path)
+    /// Starts a server that answers the first `failures` requests with a 500
+    /// and every later one with `body`, returning its URL.
+    async fn flaky_server(failures: usize, body: &'static [u8]) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            for served in 0.. {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut request = [0u8; 1024];
+                let _ = socket.read(&mut request).await;
+                let response = if served < failures {
+                    b"HTTP/1.1 500 Internal Server Error\r\ncontent-length: 0\r\nconnection: close\r\n\r\n"
+                        .to_vec()
+                } else {
+                    let mut response = format!(
+                        "HTTP/1.1 200 OK\r\ncontent-length: {}\r\nconnection: close\r\n\r\n",
+                        body.len()
+                    )
+                    .into_bytes();
+                    response.extend_from_slice(body);
+                    response
+                };
+                socket.write_all(&response).await.unwrap();
+            }
+        });
+        format!("http://{addr}/{WASM_FILENAME}")
+    }
+
+    #[test]
+    fn configured_cache_dir_takes_precedence() {
+        let configured = Path::new("/srv/wasm");
+        assert_eq!(
+            wasm_dir(Some(configured), Some("/env/wasm".into())).unwrap(),
+            configured
+        );
+        assert_eq!(
+            wasm_dir(None, Some("/env/wasm".into())).unwrap(),
+            Path::new("/env/wasm")
+        );
+        if let Some(dir) = cache_dir() {
+            assert_eq!(
+                wasm_dir(None, Some(OsString::new())).unwrap(),
+                dir.join("deepseek")
+            );
+            assert_eq!(wasm_dir(None, None).unwrap(), dir.join("deepseek"));
+        }
+    }
+
+    #[tokio::test]
+    async fn download_retries_transient_failures() {
+        let url = flaky_server(2, b"\0asm").await;
+        let bytes = download_with_retry(&url, 3, Duration::from_millis(1))
+            .await
+            .unwrap();
+        assert_eq!(&bytes[..], b"\0asm");
+    }
+
+    #[tokio::test]
+    async fn download_rejects_other_content() {
+        let url = flaky_server(1, b"<html>").await;
+        // Only the failed request is retried
+        let bytes = download_with_retry(&url, 2, Duration::from_millis(1))
+            .await
+            .unwrap();
+        let err = check_magic(&bytes, &url).unwrap_err();
+        assert!(format!("{err:#}").contains("not a WebAssembly module"));
+        assert_eq!(
+            DeepSeekError::from(err),
+            DeepSeekError::WasmInvalid { location: url }
+        );
+    }
+
+    #[tokio::test]
+    async fn module_is_written_atomically() {
+        let dir = std::env::temp_dir().join(format!("deepseek-wasm-{}", fastrand::u64(..)));
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let path = dir.join(WASM_FILENAME);
+        write_atomically(&path, b"\0asm old").await.unwrap();
+        write_atomically(&path, b"\0asm new").await.unwrap();
+        assert_eq!(tokio::fs::read(&path).await.unwrap(), b"\0asm new");
+        let mut entries = tokio::fs::read_dir(&dir).await.unwrap();
+        let mut names = Vec::new();
+        while let Some(entry) = entries.next_entry().await.unwrap() {
+            names.push(entry.file_name());
+        }
+        assert_eq!(names, [OsString::from(WASM_FILENAME)]);
+
+        // Failing to write the temporary file is reported
+        let missing = dir.join("missing").join(WASM_FILENAME);
+        assert!(write_atomically(&missing, b"\0asm").await.is_err());
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn download_gives_up_after_max_attempts() {
+        let url = flaky_server(usize::MAX, b"").await;
+        let err = download_with_retry(&url, 2, Duration::from_millis(1))
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<DeepSeekError>(),
+            Some(DeepSeekError::WasmDownload { attempts: 2, .. })
+        ));
+    }
 }
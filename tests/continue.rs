@@ -1,4 +1,5 @@
 use anyhow::Result;
+use deepseek_api::models::MessageStatus;
 use deepseek_api::{DeepSeekAPI, StreamChunk};
 use futures_util::StreamExt;
 use std::env;
@@ -35,11 +36,16 @@ async fn test_continue_incomplete_message() -> Result<()> {
                 println!("Thinking chunk received ({} chars)", text.len());
                 thinking_chunks.push(text);
             }
+            StreamChunk::Warning(warning) => println!("Warning: {warning:?}"),
+            StreamChunk::Image(image) => println!("Image: {image:?}"),
+            StreamChunk::SearchResults(results) => println!("Search results: {results:?}"),
+            StreamChunk::Usage(tokens) => println!("Tokens used: {tokens}"),
             StreamChunk::Message(msg) => {
                 println!("Final message received with status: {:?}", msg.status);
                 final_message = Some(msg);
                 break;
             }
+            _ => (),
         }
     }
 
@@ -47,8 +53,8 @@ async fn test_continue_incomplete_message() -> Result<()> {
 
     // With auto-continuation, the message should be complete.
     assert_ne!(
-        final_msg.status.as_deref(),
-        Some("INCOMPLETE"),
+        final_msg.status,
+        Some(MessageStatus::Incomplete),
         "Message should be complete after auto-continuation"
     );
 
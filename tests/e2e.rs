@@ -2,7 +2,8 @@
 //!
 //! These tests require the `DEEPSEEK_TOKEN` environment variable to be set.
 
-use deepseek_api::{DeepSeekAPI, StreamChunk};
+use deepseek_api::models::Role;
+use deepseek_api::{CompletionOptions, DeepSeekAPI, DeepSeekError, StreamChunk};
 use futures_util::{StreamExt, pin_mut};
 
 #[tokio::test]
@@ -30,7 +31,7 @@ async fn test_e2e_completion() {
         "message_id should be present"
     );
     assert!(response.parent_id.is_some(), "parent_id should be present");
-    assert!(response.role.is_some(), "role should be present");
+    assert_eq!(response.role, Some(Role::Assistant));
     assert!(
         response.inserted_at.is_some(),
         "inserted_at should be present"
@@ -232,6 +233,18 @@ async fn test_e2e_streaming() {
             StreamChunk::Thinking(thought) => {
                 println!("Thinking: {thought}");
             }
+            StreamChunk::Warning(warning) => {
+                println!("Warning: {warning:?}");
+            }
+            StreamChunk::Image(image) => {
+                println!("Image: {image:?}");
+            }
+            StreamChunk::SearchResults(results) => {
+                println!("Search results: {results:?}");
+            }
+            StreamChunk::Usage(tokens) => {
+                println!("Tokens used: {tokens}");
+            }
             StreamChunk::Message(msg) => {
                 println!("Final message: {msg:#?}");
                 // Optionally check content and fields
@@ -244,6 +257,7 @@ async fn test_e2e_streaming() {
                 assert!(msg.role.is_some(), "role should be present");
                 assert!(msg.inserted_at.is_some(), "inserted_at should be present");
             }
+            _ => (),
         }
     }
 
@@ -252,3 +266,170 @@ async fn test_e2e_streaming() {
         "Should have received at least one content chunk"
     );
 }
+
+#[tokio::test]
+async fn test_e2e_last_message() {
+    let token = std::env::var("DEEPSEEK_TOKEN")
+        .expect("DEEPSEEK_TOKEN environment variable must be set to run this test");
+
+    let api = DeepSeekAPI::new(token).await.unwrap();
+    let chat = api.create_chat().await.unwrap();
+
+    // A fresh session has no messages
+    assert!(api.get_last_message(&chat.id).await.unwrap().is_none());
+
+    let response = api
+        .complete(&chat.id, "Hello", None, false, false, vec![])
+        .await
+        .unwrap();
+
+    let last = api
+        .get_last_message(&chat.id)
+        .await
+        .unwrap()
+        .expect("session should have a last message after a completion");
+    assert_eq!(last.message_id, response.message_id);
+}
+
+#[tokio::test]
+async fn test_e2e_complete_split() {
+    let token = std::env::var("DEEPSEEK_TOKEN")
+        .expect("DEEPSEEK_TOKEN environment variable must be set to run this test");
+
+    let api = DeepSeekAPI::new(token).await.unwrap();
+    let chat = api.create_chat().await.unwrap();
+
+    let (stream, message) = api.complete_split(
+        chat.id.clone(),
+        "Hello".to_string(),
+        None,
+        false,
+        false,
+        vec![],
+    );
+    let mut content = String::new();
+    let drive = async {
+        pin_mut!(stream);
+        while let Some(chunk) = stream.next().await {
+            if let StreamChunk::Content(text) = chunk.unwrap() {
+                content.push_str(&text);
+            }
+        }
+    };
+    let ((), message) = tokio::join!(drive, message);
+
+    let message = message.unwrap();
+    assert_eq!(message.content, content);
+}
+
+#[tokio::test]
+async fn test_e2e_dual_stream() {
+    let token = std::env::var("DEEPSEEK_TOKEN")
+        .expect("DEEPSEEK_TOKEN environment variable must be set to run this test");
+
+    let api = DeepSeekAPI::new(token).await.unwrap();
+    let chat = api.create_chat().await.unwrap();
+
+    let (thinking, content) = api.complete_dual_stream(
+        chat.id.clone(),
+        "Explain quantum computing in one sentence".to_string(),
+        None,
+        false,
+        true,
+        vec![],
+    );
+    let (thinking, content) = tokio::join!(collect(thinking), collect(content));
+
+    assert!(!content.is_empty(), "Content should not be empty");
+    println!("Thinking: {thinking}");
+}
+
+async fn collect(
+    stream: impl futures_util::Stream<Item = Result<String, DeepSeekError>>,
+) -> String {
+    pin_mut!(stream);
+    let mut text = String::new();
+    while let Some(chunk) = stream.next().await {
+        text.push_str(&chunk.unwrap());
+    }
+    text
+}
+
+#[tokio::test]
+async fn test_e2e_server_time() {
+    let token = std::env::var("DEEPSEEK_TOKEN")
+        .expect("DEEPSEEK_TOKEN environment variable must be set to run this test");
+
+    let api = DeepSeekAPI::new(token).await.unwrap();
+    assert!(api.skew().is_none());
+
+    let server_time = api.get_server_time().await.unwrap();
+    assert!(server_time > 0.0);
+    assert!(api.skew().is_some(), "skew should be recorded");
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn test_e2e_pow_solving_keeps_runtime_responsive() {
+    let token = std::env::var("DEEPSEEK_TOKEN")
+        .expect("DEEPSEEK_TOKEN environment variable must be set to run this test");
+
+    let api = DeepSeekAPI::new(token).await.unwrap();
+    // On a single-threaded runtime, a solve blocking the thread would stall this task
+    let ticker = tokio::spawn(async {
+        let mut longest_gap = std::time::Duration::ZERO;
+        let mut last = std::time::Instant::now();
+        loop {
+            tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+            longest_gap = longest_gap.max(last.elapsed());
+            last = std::time::Instant::now();
+            if longest_gap > std::time::Duration::from_millis(50) {
+                return longest_gap;
+            }
+        }
+    });
+    api.prefetch_pow("/api/v0/chat/completion", 3)
+        .await
+        .unwrap();
+    assert!(
+        !ticker.is_finished(),
+        "runtime stalled for {:?}",
+        ticker.await.unwrap()
+    );
+    ticker.abort();
+}
+
+#[tokio::test]
+async fn test_e2e_complete_once() {
+    let token = std::env::var("DEEPSEEK_TOKEN")
+        .expect("DEEPSEEK_TOKEN environment variable must be set to run this test");
+
+    let api = DeepSeekAPI::new(token).await.unwrap();
+    let chat = api.create_chat().await.unwrap();
+
+    let message = api
+        .complete_once(&chat.id, "Hello", None, CompletionOptions::default())
+        .await
+        .unwrap();
+    assert!(message.message_id.is_some(), "message_id should be present");
+    assert!(message.status.is_some(), "status should be present");
+}
+
+#[tokio::test]
+async fn test_e2e_complete_or_create() {
+    let token = std::env::var("DEEPSEEK_TOKEN")
+        .expect("DEEPSEEK_TOKEN environment variable must be set to run this test");
+
+    let api = DeepSeekAPI::new(token).await.unwrap();
+
+    let (chat_id, message) = api
+        .complete_or_create(None, "Hello", CompletionOptions::default())
+        .await
+        .unwrap();
+    assert!(message.message_id.is_some(), "message_id should be present");
+
+    let (reused_id, _) = api
+        .complete_or_create(Some(&chat_id), "Hello again", CompletionOptions::default())
+        .await
+        .unwrap();
+    assert_eq!(reused_id, chat_id);
+}
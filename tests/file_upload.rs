@@ -62,10 +62,15 @@ async fn test_file_upload_and_use() -> Result<()> {
                 got_content = true;
             }
             StreamChunk::Thinking(t) => println!("Thinking: {t}"),
+            StreamChunk::Warning(w) => println!("Warning: {w:?}"),
+            StreamChunk::Image(i) => println!("Image: {i:?}"),
+            StreamChunk::SearchResults(r) => println!("Search results: {r:?}"),
+            StreamChunk::Usage(u) => println!("Tokens used: {u}"),
             StreamChunk::Message(msg) => {
                 println!("Final message: {msg:?}");
                 assert!(!msg.content.is_empty());
             }
+            _ => (),
         }
     }
     assert!(got_content, "Should have received content");